@@ -0,0 +1,37 @@
+use std::time::Instant;
+
+use gabba::mem;
+
+const ROM_SIZE: usize = 4 * 1024 * 1024;
+const FETCHES: u32 = 10_000_000;
+
+// Measures instruction fetch throughput by reading sequential words the way
+// the CPU does when running straight-line code.
+fn bench_fetch(name: &str, m: &mut mem::Memory, start: u32, len: u32) {
+    let begin = Instant::now();
+    let mut sum = 0u32;
+    for idx in 0..FETCHES {
+        let addr = start + (idx * 4) % len;
+        sum = sum.wrapping_add(m.fetch32(addr));
+    }
+    let elapsed = begin.elapsed();
+
+    println!(
+        "{:<12} {:>10} fetches in {:>8.2?} ({:>7.1} Mfetch/s) [{:x}]",
+        name,
+        FETCHES,
+        elapsed,
+        FETCHES as f64 / elapsed.as_secs_f64() / 1_000_000.0,
+        sum,
+    );
+}
+
+fn main() {
+    let mut m = mem::Memory::new();
+    let rom: Vec<u8> = (0..ROM_SIZE).map(|idx| idx as u8).collect();
+    m.load_pak(&rom);
+
+    bench_fetch("pak rom", &mut m, mem::PAK_ROM, ROM_SIZE as u32);
+    bench_fetch("int wram", &mut m, mem::INT_WRAM, 32 * 1024);
+    bench_fetch("ext wram", &mut m, mem::EXT_WRAM, 256 * 1024);
+}
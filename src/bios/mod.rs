@@ -0,0 +1,83 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+
+use crate::crc32;
+
+pub const BIOS_SIZE: usize = 16 * 1024;
+
+const GBA_BIOS_CRC32: u32 = 0x81977335;
+const NDS_BIOS_CRC32: u32 = 0xA6473709;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum BiosKind {
+    Gba,
+    // The DS has its own copy of the GBA BIOS which differs in a few bytes.
+    Nds,
+    Unknown(u32),
+}
+
+pub struct Bios {
+    kind: BiosKind,
+    data: Vec<u8>,
+}
+
+impl Bios {
+    pub fn load_from_file(path: &str) -> Result<Bios, String> {
+        match fs::read(path) {
+            Ok(data) => Bios::load(data),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    // Loads a BIOS image, which has to be exactly 16 KiB. Images that don't
+    // match a known BIOS dump still load, but report an unknown kind so the
+    // caller can warn about them.
+    pub fn load(data: Vec<u8>) -> Result<Bios, String> {
+        if data.len() != BIOS_SIZE {
+            return Err(format!("bios is the wrong size ({}, expected {})", data.len(), BIOS_SIZE));
+        }
+
+        let kind = match crc32::crc32(&data) {
+            GBA_BIOS_CRC32 => BiosKind::Gba,
+            NDS_BIOS_CRC32 => BiosKind::Nds,
+            crc => BiosKind::Unknown(crc),
+        };
+
+        Ok(Bios { kind, data })
+    }
+
+    pub fn kind(&self) -> BiosKind {
+        self.kind
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl fmt::Debug for Bios {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Bios{{ kind: {:?} }}", self.kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod bios {
+        use super::super::*;
+
+        #[test]
+        fn load() {
+            let bios = Bios::load(vec![0; BIOS_SIZE]).unwrap();
+            assert_eq!(BiosKind::Unknown(crc32::crc32(&[0; BIOS_SIZE])), bios.kind());
+            assert_eq!(BIOS_SIZE, bios.data().len());
+        }
+
+        #[test]
+        fn load_wrong_size() {
+            assert!(Bios::load(vec![0; BIOS_SIZE - 1]).is_err());
+            assert!(Bios::load(vec![0; BIOS_SIZE * 2]).is_err());
+        }
+    }
+}
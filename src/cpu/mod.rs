@@ -1,9 +1,9 @@
 use std::fmt;
 
 use crate::mem;
-use std::fmt::{Formatter, Error};
 
 mod opcode;
+pub mod trace;
 
 pub const REG_SP: usize = 13;
 pub const REG_LR: usize = 14;
@@ -17,31 +17,132 @@ const MODE_ABT: usize = 4;
 const MODE_IRQ: usize = 5;
 const MODE_UND: usize = 6;
 
+const CPSR_N: u32 = 1 << 31;
+const CPSR_Z: u32 = 1 << 30;
+const CPSR_C: u32 = 1 << 29;
+const CPSR_V: u32 = 1 << 28;
+const CPSR_I: u32 = 1 << 7;
+const CPSR_F: u32 = 1 << 6;
+const CPSR_T: u32 = 1 << 5;
+const CPSR_MODE: u32 = 0x1F;
+
+const CPSR_MODE_USR: u32 = 0x10;
+const CPSR_MODE_FIQ: u32 = 0x11;
+const CPSR_MODE_IRQ: u32 = 0x12;
+const CPSR_MODE_SVC: u32 = 0x13;
+const CPSR_MODE_ABT: u32 = 0x17;
+const CPSR_MODE_UND: u32 = 0x1B;
+const CPSR_MODE_SYS: u32 = 0x1F;
+
 pub struct ARM7TDMI {
     state: CPUState,
+
+    trace: Option<trace::Trace>,
 }
 
 impl ARM7TDMI {
     pub fn new() -> ARM7TDMI {
         let mut cpu = ARM7TDMI {
             state: CPUState::new(),
+            trace: None,
         };
         cpu.state.reset();
         cpu
     }
 
-    pub fn step(&mut self, m: &mem::Memory) {
+    // Sets where each executed instruction is logged to, or turns tracing
+    // off with None.
+    pub fn set_trace(&mut self, trace: Option<trace::Trace>) {
+        if let Some(t) = self.trace.as_mut() {
+            let _ = t.flush();
+        }
+        self.trace = trace;
+    }
+
+    // Runs a single instruction and returns the number of cycles it took.
+    // There's no Thumb decoder yet, so in Thumb state the CPU stops with an
+    // error instead of running halfwords as ARM ops.
+    pub fn step(&mut self, m: &mut mem::Memory) -> Result<u32, String> {
+        let start = m.cycles();
+
         // Get the op at the current pc
-        match m.read(self.get_reg(REG_PC), opcode::OP_SIZE) {
-            Some(data) => match opcode::Op::parse(&data) {
-                Some(op) => self.exec_op(m, &op),
-                None => println!("no opcode found"),
+        let pc = self.get_reg(REG_PC);
+        if self.get_cpsr() & CPSR_T != 0 {
+            return Err(format!("thumb state is not implemented, stopped at {:08x}", pc));
+        }
+        m.set_pc(pc);
+        let opdata = m.fetch32(pc);
+        let pipeline = self.pipeline(m, pc);
+        m.set_open_bus(pipeline);
+
+        let op = opcode::Op::parse(&opdata.to_le_bytes());
+        if self.trace.is_some() {
+            self.trace_op(opdata, op.as_ref());
+        }
+
+        match op {
+            Some(op) => self.exec_op(m, &op),
+            None => println!("no opcode found"),
+        }
+
+        Ok((m.cycles() - start) as u32)
+    }
+
+    // Returns what the last prefetch left on the bus while the opcode at pc
+    // executes, which is what reads from unmapped memory see. In ARM state
+    // that's the opcode two ahead. In Thumb state the 32-bit bus carries two
+    // halfwords, and which ones depends on how the region code runs from is
+    // wired up.
+    fn pipeline(&self, m: &mem::Memory, pc: u32) -> u32 {
+        if self.get_cpsr() & CPSR_T == 0 {
+            return m.peek32(pc.wrapping_add(8));
+        }
+
+        let half = |offset: u32| m.peek16(pc.wrapping_add(offset)) as u32;
+        let aligned = pc & 0x2 == 0;
+        let (lsw, msw) = match mem::Memory::locate(pc).0 {
+            mem::Region::SysRom | mem::Region::Oam => match aligned {
+                true => (half(4), half(6)),
+                false => (half(2), half(4)),
+            },
+            mem::Region::IntWram => match aligned {
+                true => (half(4), half(2)),
+                false => (half(2), half(4)),
+            },
+            _ => (half(4), half(4)),
+        };
+
+        (msw << 16) | lsw
+    }
+
+    fn trace_op(&mut self, opdata: u32, op: Option<&opcode::Op>) {
+        let pc = self.get_reg(REG_PC);
+        let mut regs = [0; 16];
+        for (idx, reg) in regs.iter_mut().enumerate() {
+            *reg = self.get_reg(idx);
+        }
+        regs[REG_PC] = pc + 2 * opcode::OP_SIZE as u32;
+
+        let record = trace::TraceRecord {
+            pc,
+            opcode: opdata,
+            regs,
+            cpsr: self.get_cpsr(),
+            disasm: match op {
+                Some(op) => op.to_string(),
+                None => String::from("undefined"),
+            },
+        };
+
+        if let Some(t) = self.trace.as_mut() {
+            if let Err(e) = t.record(&record) {
+                println!("could not write trace, disabling it: {}", e);
+                self.trace = None;
             }
-            None => println!("no data"),
         }
     }
 
-    fn exec_op(&mut self, m: &mem::Memory, op: &opcode::Op) {
+    fn exec_op(&mut self, _m: &mut mem::Memory, op: &opcode::Op) {
         match op {
             opcode::Op::B(offset) => {
                 let old_pc = self.get_reg(REG_PC);
@@ -64,21 +165,120 @@ impl ARM7TDMI {
 
                 self.set_reg(REG_PC, old_pc + opcode::OP_SIZE as u32);
             }
-            opcode::Op::Msr(immediate, r, field_flags, operand) => {
+            opcode::Op::Movs(rd, operand) => {
+                let (result, carry) = self.operand_value(operand);
+                self.exec_data_proc_s(*rd, result, carry, None);
+            }
+            opcode::Op::Subs(rd, rn, operand) => {
+                let lhs = self.get_reg(*rn);
+                let (rhs, _) = self.operand_value(operand);
+                let (result, borrow) = lhs.overflowing_sub(rhs);
+                let overflow = (lhs as i32).overflowing_sub(rhs as i32).1;
+
+                self.exec_data_proc_s(*rd, result, Some(!borrow), Some(overflow));
+            }
+            opcode::Op::Msr(immediate, spsr, field_mask, operand) => {
                 let old_pc = self.get_reg(REG_PC);
 
+                let val = match immediate {
+                    true => *operand as u32,
+                    false => self.get_reg(*operand & 0xF),
+                };
+                self.exec_msr(*spsr, *field_mask, val);
+
                 self.set_reg(REG_PC, old_pc + opcode::OP_SIZE as u32);
             }
             _ => println!("op not implemented: {}", op)
         }
     }
 
-    fn state(&self) -> CPUState {
-        self.state.clone()
+    // Returns the value of an operand along with the carry out of the
+    // shifter. Only rotated immediates have one, and everything else leaves
+    // the carry flag as it was.
+    fn operand_value(&self, operand: &opcode::Operand) -> (u32, Option<bool>) {
+        match operand {
+            opcode::Operand::Imm(val, 0) => (*val, None),
+            opcode::Operand::Imm(val, rotate) => {
+                let val = val.rotate_right(*rotate);
+                (val, Some(val & 0x8000_0000 != 0))
+            }
+            opcode::Operand::Reg(r) => (self.get_reg(*r), None),
+        }
     }
 
+    // Writes the result of a data processing op with the S bit set. Writing
+    // to the PC is an exception return: the SPSR of the current mode is
+    // copied into the CPSR, which switches register banks along with it,
+    // and its T bit picks whether the return lands in ARM or Thumb code.
+    // Any other destination just updates the condition flags.
+    fn exec_data_proc_s(&mut self, rd: usize, result: u32, carry: Option<bool>, overflow: Option<bool>) {
+        match rd {
+            REG_PC => {
+                self.state.restore_cpsr();
+                let align = match self.get_cpsr() & CPSR_T {
+                    0 => !0x3,
+                    _ => !0x1,
+                };
+                self.set_reg(REG_PC, result & align);
+            }
+            _ => {
+                self.set_reg(rd, result);
+                self.state.set_nz(result);
+                if let Some(carry) = carry {
+                    self.state.set_flag(CPSR_C, carry);
+                }
+                if let Some(overflow) = overflow {
+                    self.state.set_flag(CPSR_V, overflow);
+                }
+
+                let old_pc = self.get_reg(REG_PC);
+                self.set_reg(REG_PC, old_pc + opcode::OP_SIZE as u32);
+            }
+        }
+    }
+
+    // Each bit of the field mask picks a byte of the PSR to write. Outside
+    // the privileged modes only the flags can be written.
+    fn exec_msr(&mut self, spsr: bool, field_mask: u8, val: u32) {
+        let mut mask = (0..4)
+            .filter(|byte| field_mask & (1 << byte) != 0)
+            .fold(0, |mask, byte| mask | (0xFF << (byte * 8)));
+        if !self.state.privileged_mode() {
+            mask &= 0xFF00_0000;
+        }
+
+        match spsr {
+            true => {
+                let old = self.state.get_spsr();
+                self.state.set_spsr((old & !mask) | (val & mask));
+            }
+            false => {
+                let old = self.get_cpsr();
+                self.state.set_cpsr((old & !mask) | (val & mask));
+            }
+        }
+    }
+
+    // Puts the CPU in the state a hardware reset leaves it in: supervisor
+    // mode with interrupts disabled, about to run the reset vector.
     pub fn reset(&mut self) {
-        self.state.reset()
+        self.state.reset();
+        self.state.set_cpsr(CPSR_MODE_SVC | CPSR_I | CPSR_F);
+    }
+
+    // Puts the CPU in the state the BIOS leaves it in when it jumps to entry
+    // after booting, for running without a BIOS image.
+    pub fn skip_bios(&mut self, entry: u32) {
+        self.reset();
+
+        self.state.set_mode(MODE_SVC);
+        self.set_reg(REG_SP, 0x03_00_7F_E0);
+        self.state.set_mode(MODE_IRQ);
+        self.set_reg(REG_SP, 0x03_00_7F_A0);
+        self.state.set_cpsr(CPSR_MODE_SYS);
+        self.set_reg(REG_SP, 0x03_00_7F_00);
+
+        self.set_reg(REG_PC, entry);
     }
 
     pub fn get_reg(&self, reg: usize) -> u32 {
@@ -87,17 +287,24 @@ impl ARM7TDMI {
     pub fn set_reg(&mut self, reg: usize, val: u32) {
         self.state.set_reg(reg, val)
     }
+
+    pub fn get_cpsr(&self) -> u32 {
+        self.state.cpsr
+    }
+    pub fn set_cpsr(&mut self, val: u32) {
+        self.state.set_cpsr(val)
+    }
 }
 
-impl fmt::Debug for ARM7TDMI {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.state.fmt(f)
+impl Default for ARM7TDMI {
+    fn default() -> ARM7TDMI {
+        ARM7TDMI::new()
     }
 }
 
-impl fmt::Display for ARM7TDMI {
+impl fmt::Debug for ARM7TDMI {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        unimplemented!()
+        self.state.fmt(f)
     }
 }
 
@@ -132,10 +339,8 @@ impl CPUState {
 
     pub fn reset(&mut self) {
         self.mode = MODE_USR;
-        self.cpsr = 0;
-        for idx in 0..8 {
-            self.gpreg[idx] = 0;
-        }
+        self.cpsr = CPSR_MODE_USR;
+        self.gpreg = [0; 8];
 
         self.reset_mode(MODE_USR);
         self.reset_mode(MODE_FIQ);
@@ -149,7 +354,7 @@ impl CPUState {
         match reg {
             r if r < 8 => self.gpreg[r] = val,
             r => match r {
-                13 | 14 => self.regbank[self.mode][r] = val,
+                13 | 14 => self.regbank[self.bank()][r] = val,
                 15 => self.regbank[MODE_USR][15] = val,
                 r => match self.mode {
                     MODE_FIQ => self.regbank[self.mode][r] = val,
@@ -163,7 +368,7 @@ impl CPUState {
         match reg {
             r if r < 8 => self.gpreg[r],
             _ => match reg {
-                13 | 14 => self.regbank[self.mode][reg],
+                13 | 14 => self.regbank[self.bank()][reg],
                 15 => self.regbank[MODE_USR][15],
                 _ => match self.mode {
                     MODE_FIQ => self.regbank[self.mode][reg],
@@ -173,8 +378,18 @@ impl CPUState {
         }
     }
 
+    // System mode runs with the user mode registers, so it shares its bank
+    // rather than using its own.
+    fn bank(&self) -> usize {
+        match self.mode {
+            MODE_SYS => MODE_USR,
+            m => m,
+        }
+    }
+
     fn set_mode(&mut self, mode: usize) {
         self.mode = mode;
+        self.cpsr = (self.cpsr & !CPSR_MODE) | mode_bits(mode);
     }
 
     fn reset_mode(&mut self, mode: usize) {
@@ -183,28 +398,74 @@ impl CPUState {
     }
 
     fn privileged_mode(&self) -> bool {
-        match self.mode {
-            MODE_USR => false,
-            _ => true
-        }
+        self.mode != MODE_USR
     }
 
+    // Setting the CPSR switches to whatever mode is in its mode bits. Invalid
+    // mode bits leave the current register bank in place.
     fn set_cpsr(&mut self, val: u32) {
         self.cpsr = val;
+        if let Some(mode) = mode_from_bits(val & CPSR_MODE) {
+            self.mode = mode;
+        }
     }
 
+    // User and system mode have no SPSR, so reads return the CPSR and writes
+    // are ignored.
     fn get_spsr(&self) -> u32 {
         match self.mode {
-            MODE_USR => panic!("get spsr with mode sys"),
+            MODE_USR | MODE_SYS => self.cpsr,
             _ => self.spsr[self.mode],
         }
     }
     fn set_spsr(&mut self, val: u32) {
         match self.mode {
-            MODE_USR => panic!("set spsr with mode sys"),
+            MODE_USR | MODE_SYS => {}
             _ => self.spsr[self.mode] = val,
         }
     }
+
+    fn restore_cpsr(&mut self) {
+        let spsr = self.get_spsr();
+        self.set_cpsr(spsr);
+    }
+
+    fn set_nz(&mut self, result: u32) {
+        self.set_flag(CPSR_N, result & 0x8000_0000 != 0);
+        self.set_flag(CPSR_Z, result == 0);
+    }
+
+    fn set_flag(&mut self, flag: u32, set: bool) {
+        match set {
+            true => self.cpsr |= flag,
+            false => self.cpsr &= !flag,
+        }
+    }
+}
+
+fn mode_from_bits(bits: u32) -> Option<usize> {
+    match bits {
+        CPSR_MODE_USR => Some(MODE_USR),
+        CPSR_MODE_SYS => Some(MODE_SYS),
+        CPSR_MODE_FIQ => Some(MODE_FIQ),
+        CPSR_MODE_SVC => Some(MODE_SVC),
+        CPSR_MODE_ABT => Some(MODE_ABT),
+        CPSR_MODE_IRQ => Some(MODE_IRQ),
+        CPSR_MODE_UND => Some(MODE_UND),
+        _ => None,
+    }
+}
+
+fn mode_bits(mode: usize) -> u32 {
+    match mode {
+        MODE_USR => CPSR_MODE_USR,
+        MODE_SYS => CPSR_MODE_SYS,
+        MODE_FIQ => CPSR_MODE_FIQ,
+        MODE_SVC => CPSR_MODE_SVC,
+        MODE_ABT => CPSR_MODE_ABT,
+        MODE_IRQ => CPSR_MODE_IRQ,
+        _ => CPSR_MODE_UND,
+    }
 }
 
 impl fmt::Debug for CPUState {
@@ -426,7 +687,7 @@ mod tests {
                     _ => match reg {
                         8..=12 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                         13..=14 => assert_eq!(1234, state.regbank[MODE_SVC][reg]),
-                        _15 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
+                        _ => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                     }
                 }
             }
@@ -445,7 +706,7 @@ mod tests {
                     _ => match reg {
                         8..=12 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                         13..=14 => assert_eq!(1234, state.regbank[MODE_ABT][reg]),
-                        _15 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
+                        _ => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                     }
                 }
             }
@@ -464,7 +725,7 @@ mod tests {
                     _ => match reg {
                         8..=12 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                         13..=14 => assert_eq!(1234, state.regbank[MODE_IRQ][reg]),
-                        _15 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
+                        _ => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                     }
                 }
             }
@@ -483,12 +744,51 @@ mod tests {
                     _ => match reg {
                         8..=12 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                         13..=14 => assert_eq!(1234, state.regbank[MODE_UND][reg]),
-                        _15 => assert_eq!(1234, state.regbank[MODE_USR][reg]),
+                        _ => assert_eq!(1234, state.regbank[MODE_USR][reg]),
                     }
                 }
             }
         }
 
+        #[test]
+        fn get_r_sys_shares_usr_bank() {
+            let mut state = CPUState::new();
+            state.set_mode(MODE_USR);
+            state.set_reg(13, 1234);
+            state.set_reg(14, 5678);
+
+            state.set_mode(MODE_SYS);
+            assert_eq!(1234, state.get_reg(13));
+            assert_eq!(5678, state.get_reg(14));
+        }
+
+        #[test]
+        fn get_spsr_usr() {
+            let mut state = CPUState::new();
+            state.set_mode(MODE_USR);
+
+            assert_eq!(state.cpsr, state.get_spsr());
+        }
+
+        #[test]
+        fn set_spsr_usr() {
+            let mut state = CPUState::new();
+            state.set_mode(MODE_USR);
+
+            state.set_spsr(1234);
+            assert_eq!([0; 7], state.spsr);
+        }
+
+        #[test]
+        fn set_cpsr_switches_mode() {
+            let mut state = CPUState::new();
+            state.set_cpsr(CPSR_MODE_IRQ);
+            assert_eq!(MODE_IRQ, state.mode);
+
+            state.set_cpsr(CPSR_MODE_SYS);
+            assert_eq!(MODE_SYS, state.mode);
+        }
+
         #[test]
         fn set_spsr_fiq() {
             let mut state = CPUState::new();
@@ -536,38 +836,61 @@ mod tests {
     }
 
     mod cpu {
+        mod reset {
+            use super::super::super::*;
+
+            #[test]
+            fn reset() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_reg(REG_PC, 0x5000);
+                cpu.reset();
+
+                assert_eq!(0, cpu.get_reg(REG_PC));
+                assert_eq!(CPSR_MODE_SVC | CPSR_I | CPSR_F, cpu.get_cpsr());
+                assert_eq!(MODE_SVC, cpu.state.mode);
+            }
+
+            #[test]
+            fn skip_bios() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.skip_bios(0x08_00_00_00);
+
+                assert_eq!(0x08_00_00_00, cpu.get_reg(REG_PC));
+                assert_eq!(CPSR_MODE_SYS, cpu.get_cpsr());
+                assert_eq!(0x03_00_7F_00, cpu.get_reg(REG_SP));
+                assert_eq!(0x03_00_7F_E0, cpu.state.regbank[MODE_SVC][REG_SP]);
+                assert_eq!(0x03_00_7F_A0, cpu.state.regbank[MODE_IRQ][REG_SP]);
+            }
+        }
+
         mod b {
             use super::super::super::*;
 
             #[test]
             fn exec_offset_positive() {
                 let mut cpu = ARM7TDMI::new();
-                cpu.set_reg(REG_PC, 0x50_00);
+                cpu.set_reg(REG_PC, 0x5000);
 
-                let old_state = cpu.state();
-                assert_eq!(0x50_00, cpu.get_reg(REG_PC));
+                assert_eq!(0x5000, cpu.get_reg(REG_PC));
                 assert_eq!(0, cpu.get_reg(REG_LR));
 
-                cpu.exec_op(&mem::Memory::new(), &opcode::Op::B(0x32));
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::B(0x32));
 
-                let new_state = cpu.state();
-                assert_eq!(0x50_32, cpu.get_reg(REG_PC));
+                assert_eq!(0x5032, cpu.get_reg(REG_PC));
                 assert_eq!(0, cpu.get_reg(REG_LR));
             }
 
             #[test]
             fn exec_offset_negative() {
                 let mut cpu = ARM7TDMI::new();
-                cpu.set_reg(REG_PC, 0x50_00);
+                cpu.set_reg(REG_PC, 0x5000);
 
-                let old_state = cpu.state();
-                assert_eq!(0x50_00, cpu.get_reg(REG_PC));
+                assert_eq!(0x5000, cpu.get_reg(REG_PC));
                 assert_eq!(0, cpu.get_reg(REG_LR));
 
-                cpu.exec_op(&mem::Memory::new(), &opcode::Op::B(-0x32));
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::B(-0x32));
 
-                let new_state = cpu.state();
-                assert_eq!(0x4F_CE, cpu.get_reg(REG_PC));
+                assert_eq!(0x4FCE, cpu.get_reg(REG_PC));
                 assert_eq!(0, cpu.get_reg(REG_LR));
             }
         }
@@ -578,33 +901,172 @@ mod tests {
             #[test]
             fn exec_offset_positive() {
                 let mut cpu = ARM7TDMI::new();
-                cpu.set_reg(REG_PC, 0x50_00);
+                cpu.set_reg(REG_PC, 0x5000);
 
-                let old_state = cpu.state();
-                assert_eq!(0x50_00, cpu.get_reg(REG_PC));
+                assert_eq!(0x5000, cpu.get_reg(REG_PC));
                 assert_eq!(0, cpu.get_reg(REG_LR));
 
-                cpu.exec_op(&mem::Memory::new(), &opcode::Op::Bl(0x32));
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Bl(0x32));
 
-                let new_state = cpu.state();
-                assert_eq!(0x50_32, cpu.get_reg(REG_PC));
-                assert_eq!(0x50_00, cpu.get_reg(REG_LR));
+                assert_eq!(0x5032, cpu.get_reg(REG_PC));
+                assert_eq!(0x5000, cpu.get_reg(REG_LR));
             }
 
             #[test]
             fn exec_offset_negative() {
                 let mut cpu = ARM7TDMI::new();
-                cpu.set_reg(REG_PC, 0x50_00);
+                cpu.set_reg(REG_PC, 0x5000);
 
-                let old_state = cpu.state();
-                assert_eq!(0x50_00, cpu.get_reg(REG_PC));
+                assert_eq!(0x5000, cpu.get_reg(REG_PC));
                 assert_eq!(0, cpu.get_reg(REG_LR));
 
-                cpu.exec_op(&mem::Memory::new(), &opcode::Op::Bl(-0x32));
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Bl(-0x32));
 
-                let new_state = cpu.state();
-                assert_eq!(0x4F_CE, cpu.get_reg(REG_PC));
-                assert_eq!(0x50_00, cpu.get_reg(REG_LR));
+                assert_eq!(0x4FCE, cpu.get_reg(REG_PC));
+                assert_eq!(0x5000, cpu.get_reg(REG_LR));
+            }
+        }
+
+        mod movs {
+            use super::super::super::*;
+
+            #[test]
+            fn exec_pc_lr_returns_from_exception() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_reg(REG_LR, 0x08_00_10_00);
+                cpu.set_cpsr(CPSR_MODE_IRQ);
+                cpu.set_reg(REG_LR, 0x08_00_20_00);
+                cpu.state.set_spsr(CPSR_MODE_USR | CPSR_Z);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Movs(REG_PC, opcode::Operand::Reg(REG_LR)));
+
+                assert_eq!(0x08_00_20_00, cpu.get_reg(REG_PC));
+                assert_eq!(CPSR_MODE_USR | CPSR_Z, cpu.get_cpsr());
+                assert_eq!(0x08_00_10_00, cpu.get_reg(REG_LR));
+            }
+
+            #[test]
+            fn exec_rebanks_fiq_registers() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_reg(8, 1234);
+                cpu.set_cpsr(CPSR_MODE_FIQ);
+                cpu.set_reg(8, 5678);
+                cpu.set_reg(REG_LR, 0x5000);
+                cpu.state.set_spsr(CPSR_MODE_USR);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Movs(REG_PC, opcode::Operand::Reg(REG_LR)));
+
+                assert_eq!(1234, cpu.get_reg(8));
+                cpu.set_cpsr(CPSR_MODE_FIQ);
+                assert_eq!(5678, cpu.get_reg(8));
+            }
+
+            #[test]
+            fn exec_sets_flags() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_reg(REG_PC, 0x5000);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Movs(0, opcode::Operand::Imm(0, 0)));
+
+                assert_eq!(0x5004, cpu.get_reg(REG_PC));
+                assert_eq!(CPSR_Z, cpu.get_cpsr() & (CPSR_N | CPSR_Z));
+            }
+
+            #[test]
+            fn exec_rotated_immediate_sets_carry() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_SYS);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Movs(0, opcode::Operand::Imm(0x3, 2)));
+                assert_eq!(0xC000_0000, cpu.get_reg(0));
+                assert_eq!(CPSR_N | CPSR_C, cpu.get_cpsr() & (CPSR_N | CPSR_Z | CPSR_C));
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Movs(0, opcode::Operand::Imm(0x4, 2)));
+                assert_eq!(0x1, cpu.get_reg(0));
+                assert_eq!(0, cpu.get_cpsr() & (CPSR_N | CPSR_Z | CPSR_C));
+
+                cpu.set_cpsr(CPSR_MODE_SYS | CPSR_C);
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Movs(0, opcode::Operand::Imm(0x4, 0)));
+                assert_eq!(CPSR_C, cpu.get_cpsr() & CPSR_C);
+            }
+
+            #[test]
+            fn exec_returns_to_thumb() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_IRQ);
+                cpu.set_reg(REG_LR, mem::EXT_WRAM + 0x7);
+                cpu.state.set_spsr(CPSR_MODE_SYS | CPSR_T);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Movs(REG_PC, opcode::Operand::Reg(REG_LR)));
+                assert_eq!(mem::EXT_WRAM + 0x6, cpu.get_reg(REG_PC));
+
+                // Thumb code can't run yet, so the CPU stops there.
+                let mut m = mem::Memory::new();
+                m.write32(mem::EXT_WRAM + 0x4, 0xE1A0_0000);
+                let cycles = m.cycles();
+                assert!(cpu.step(&mut m).is_err());
+                assert_eq!(cycles, m.cycles());
+                assert_eq!(mem::EXT_WRAM + 0x6, cpu.get_reg(REG_PC));
+            }
+        }
+
+        mod msr {
+            use super::super::super::*;
+
+            #[test]
+            fn exec_spsr_fields() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_IRQ);
+                cpu.state.set_spsr(CPSR_MODE_SYS);
+                cpu.set_reg(0, CPSR_N | CPSR_T | CPSR_MODE_USR);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Msr(false, true, 0x8, 0));
+                assert_eq!(CPSR_N | CPSR_MODE_SYS, cpu.state.get_spsr());
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Msr(false, true, 0x9, 0));
+                assert_eq!(CPSR_N | CPSR_T | CPSR_MODE_USR, cpu.state.get_spsr());
+            }
+
+            #[test]
+            fn exec_cpsr_user_flags_only() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_USR);
+                cpu.set_reg(REG_PC, 0x5000);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Msr(true, false, 0x9, (CPSR_Z | CPSR_MODE_SVC) as usize));
+
+                assert_eq!(CPSR_Z | CPSR_MODE_USR, cpu.get_cpsr());
+                assert_eq!(MODE_USR, cpu.state.mode);
+                assert_eq!(0x5004, cpu.get_reg(REG_PC));
+            }
+        }
+
+        mod subs {
+            use super::super::super::*;
+
+            #[test]
+            fn exec_pc_lr_returns_from_exception() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_SVC);
+                cpu.set_reg(REG_LR, 0x08_00_20_04);
+                cpu.state.set_spsr(CPSR_MODE_SYS);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Subs(REG_PC, REG_LR, opcode::Operand::Imm(4, 0)));
+
+                assert_eq!(0x08_00_20_00, cpu.get_reg(REG_PC));
+                assert_eq!(CPSR_MODE_SYS, cpu.get_cpsr());
+                assert_eq!(MODE_SYS, cpu.state.mode);
+            }
+
+            #[test]
+            fn exec_sets_flags() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_reg(1, 0);
+
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Subs(0, 1, opcode::Operand::Imm(1, 0)));
+
+                assert_eq!(0xFFFF_FFFF, cpu.get_reg(0));
+                assert_eq!(CPSR_N, cpu.get_cpsr() & (CPSR_N | CPSR_Z | CPSR_C | CPSR_V));
             }
         }
 
@@ -614,16 +1076,120 @@ mod tests {
             #[test]
             fn exec() {
                 let mut cpu = ARM7TDMI::new();
-                cpu.set_reg(REG_PC, 0x50_00);
+                cpu.set_reg(REG_PC, 0x5000);
 
-                let old_state = cpu.state();
                 assert_eq!(0, cpu.get_reg(0));
 
-                cpu.exec_op(&mem::Memory::new(), &opcode::Op::Mov(0, 0x12));
+                cpu.exec_op(&mut mem::Memory::new(), &opcode::Op::Mov(0, 0x12));
 
-                assert_eq!(0x50_04, cpu.get_reg(REG_PC));
+                assert_eq!(0x5004, cpu.get_reg(REG_PC));
                 assert_eq!(0x12, cpu.get_reg(0));
             }
         }
+
+        mod trace {
+            use super::super::super::*;
+            use std::cell::RefCell;
+            use std::io;
+            use std::rc::Rc;
+
+            struct Shared(Rc<RefCell<Vec<u8>>>);
+
+            impl io::Write for Shared {
+                fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                    self.0.borrow_mut().extend_from_slice(buf);
+                    Ok(buf.len())
+                }
+
+                fn flush(&mut self) -> io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            #[test]
+            fn step_records_op() {
+                let out = Rc::new(RefCell::new(Vec::new()));
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_SYS | CPSR_Z);
+                cpu.set_reg(REG_PC, mem::EXT_WRAM);
+                cpu.set_trace(Some(trace::Trace::new(Box::new(Shared(out.clone())), trace::TraceFormat::Binary)));
+
+                let mut m = mem::Memory::new();
+                m.write32(mem::EXT_WRAM, 0xE3_A0_00_12);
+                cpu.step(&mut m).unwrap();
+
+                let out = out.borrow();
+                assert_eq!(trace::BINARY_MAGIC, &out[..8]);
+                let mut r = &out[8..];
+                let record = trace::TraceRecord::read_binary(&mut r).unwrap().unwrap();
+                assert_eq!(mem::EXT_WRAM, record.pc);
+                assert_eq!(0xE3_A0_00_12, record.opcode);
+                assert_eq!(mem::EXT_WRAM + 8, record.regs[REG_PC]);
+                assert_eq!(CPSR_MODE_SYS | CPSR_Z, record.cpsr);
+                assert_eq!(None, trace::TraceRecord::read_binary(&mut r).unwrap());
+            }
+        }
+
+        mod pipeline {
+            use super::super::super::*;
+
+            fn memory(base: u32) -> mem::Memory {
+                let mut m = mem::Memory::new();
+                for idx in 0..8 {
+                    m.write16(base + idx * 2, 0x1000 + idx as u16);
+                }
+                m
+            }
+
+            #[test]
+            fn arm() {
+                let cpu = ARM7TDMI::new();
+                let m = memory(mem::EXT_WRAM);
+
+                assert_eq!(0x1005_1004, cpu.pipeline(&m, mem::EXT_WRAM));
+            }
+
+            #[test]
+            fn thumb() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_SYS | CPSR_T);
+                let m = memory(mem::EXT_WRAM);
+
+                assert_eq!(0x1002_1002, cpu.pipeline(&m, mem::EXT_WRAM));
+                assert_eq!(0x1003_1003, cpu.pipeline(&m, mem::EXT_WRAM + 2));
+            }
+
+            #[test]
+            fn thumb_oam() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_SYS | CPSR_T);
+                let m = memory(mem::OAM);
+
+                assert_eq!(0x1003_1002, cpu.pipeline(&m, mem::OAM));
+                assert_eq!(0x1003_1002, cpu.pipeline(&m, mem::OAM + 2));
+            }
+
+            #[test]
+            fn thumb_int_wram() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_cpsr(CPSR_MODE_SYS | CPSR_T);
+                let m = memory(mem::INT_WRAM);
+
+                assert_eq!(0x1001_1002, cpu.pipeline(&m, mem::INT_WRAM));
+                assert_eq!(0x1003_1002, cpu.pipeline(&m, mem::INT_WRAM + 2));
+            }
+
+            #[test]
+            fn step_sets_open_bus() {
+                let mut cpu = ARM7TDMI::new();
+                cpu.set_reg(REG_PC, mem::EXT_WRAM);
+                let mut m = memory(mem::EXT_WRAM);
+                m.write32(mem::EXT_WRAM, 0xE1_A0_00_00);
+
+                cpu.step(&mut m).unwrap();
+
+                assert_eq!(0x1005_1004, m.read32(0x01_00_00_00));
+            }
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,8 @@
+// The tables here cover the whole ARM encoding, including conditions and
+// ops the decoder doesn't pick out yet.
+#![allow(dead_code)]
+
 use std::fmt;
-use std::process::id;
 
 pub const OP_SIZE: usize = 4;
 
@@ -28,6 +31,20 @@ const OP_B: u32 = 0x0A000000;
 const MASK_B: u32 = 0x0E000000;
 const MASK_B_L: u32 = 0x01000000;
 
+const OP_DATA_PROC_S: u32 = 0x00100000;
+const MASK_DATA_PROC_S: u32 = 0x0C100000;
+const MASK_DATA_PROC_I: u32 = 0x02000000;
+const MASK_DATA_PROC_OPCODE: u32 = 0x01E00000;
+const MASK_DATA_PROC_RN: u32 = 0x000F0000;
+const MASK_DATA_PROC_RD: u32 = 0x0000F000;
+const MASK_DATA_PROC_SHIFT: u32 = 0x00000FF0;
+const MASK_DATA_PROC_RM: u32 = 0x0000000F;
+const MASK_DATA_PROC_IMM: u32 = 0x000000FF;
+const MASK_DATA_PROC_ROTATE: u32 = 0x00000F00;
+
+const DATA_PROC_SUB: u32 = 0b0010;
+const DATA_PROC_MOV: u32 = 0b1101;
+
 const OP_MOV: u32 = 0x03A00000;
 const MASK_MOV: u32 = 0x0BE00000;
 const MASK_MOV_SHIFTER: u32 = 0x00000fff;
@@ -41,17 +58,27 @@ const MASK_MSR_IMMEDIATE: u32 = 0x000000ff;
 const MASK_MSR_ROTATE: u32 = 0x00000f00;
 const MASK_MSR_FIELD_MASK: u32 = 0x000f0000;
 
+// The second operand of a data processing op. Immediates are an 8-bit value
+// and how far it is rotated right.
+#[derive(Debug, PartialEq)]
+pub enum Operand {
+    Imm(u32, u32),
+    Reg(usize),
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Op {
     // Branch
     B(i32),
     Bl(i32),
-    Bx,
+        Bx,
     Blx,
     Mov(usize, i32),
+    Movs(usize, Operand),
+    Subs(usize, usize, Operand),
     Msr(bool, bool, u8, usize),
-    Swi,
-    Bkpt,
+        Swi,
+        Bkpt,
 }
 
 impl Op {
@@ -64,7 +91,7 @@ impl Op {
         println!("mask:\t{:032b}", MASK_MSR);
         println!("msr:\t{:032b} {:0x}", opdata & MASK_MSR, opdata & MASK_MSR);
         if opdata & MASK_B == OP_B {
-            let mut offset = opdata as i32 & 0x00FFFFFF;
+            let offset = opdata as i32 & 0x00FFFFFF;
             // TODO This is probably really wrong (it should be 24 instead of 16)
             //      but I don't have a good example of a real negative offset yet.
             println!("offset:\t{:032b}", offset);
@@ -81,6 +108,8 @@ impl Op {
                 1 => Some(Op::Bl(offset)),
                 _ => Some(Op::Blx),
             }
+        } else if opdata & MASK_DATA_PROC_S == OP_DATA_PROC_S {
+            Op::parse_data_proc_s(opdata)
         } else if opdata & MASK_MOV == OP_MOV {
             println!("maskmov: {:032b} {:0x}", opdata & MASK_MOV, opdata & MASK_MOV);
             match opdata & MASK_MOV {
@@ -102,7 +131,7 @@ impl Op {
                     let immediate = opdata & MASK_MSR_IMMEDIATE;
                     let rotate_imm = (opdata & MASK_MSR_ROTATE) >> 8;
 
-                    Some(Op::Msr(true, r, field_mask, immediate as usize))
+                    Some(Op::Msr(true, r, field_mask, immediate.rotate_right(rotate_imm * 2) as usize))
                 }
                 _ => Some(Op::Msr(false, r, field_mask, (opdata & MASK_MSR_IMMEDIATE) as usize))
             }
@@ -110,12 +139,55 @@ impl Op {
             None
         }
     }
+
+    // Data processing ops with the S bit set. Only MOVS and SUBS are
+    // supported so far, and only with an immediate or unshifted register
+    // operand.
+    fn parse_data_proc_s(opdata: u32) -> Option<Op> {
+        let rn = ((opdata & MASK_DATA_PROC_RN) >> 16) as usize;
+        let rd = ((opdata & MASK_DATA_PROC_RD) >> 12) as usize;
+
+        let operand = match opdata & MASK_DATA_PROC_I {
+            MASK_DATA_PROC_I => {
+                let immediate = opdata & MASK_DATA_PROC_IMM;
+                let rotate = (opdata & MASK_DATA_PROC_ROTATE) >> 7;
+                Operand::Imm(immediate, rotate)
+            }
+            _ => match opdata & MASK_DATA_PROC_SHIFT {
+                0 => Operand::Reg((opdata & MASK_DATA_PROC_RM) as usize),
+                _ => return None,
+            }
+        };
+
+        match (opdata & MASK_DATA_PROC_OPCODE) >> 21 {
+            DATA_PROC_MOV => Some(Op::Movs(rd, operand)),
+            DATA_PROC_SUB => Some(Op::Subs(rd, rn, operand)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Imm(val, rotate) => write!(f, "#{:#x}", val.rotate_right(*rotate)),
+            Operand::Reg(r) => write!(f, "r{}", r),
+        }
+    }
 }
 
 impl fmt::Display for Op {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Op::B(addr) => write!(f, "B {:#x}", addr),
+            Op::Bl(addr) => write!(f, "BL {:#x}", addr),
+            Op::Mov(rd, val) => write!(f, "MOV r{}, #{:#x}", rd, val),
+            Op::Msr(_, r, field_mask, _) => match r {
+                true => write!(f, "MSR SPSR_{:x}", field_mask),
+                false => write!(f, "MSR CPSR_{:x}", field_mask),
+            },
+            Op::Movs(rd, operand) => write!(f, "MOVS r{}, {}", rd, operand),
+            Op::Subs(rd, rn, operand) => write!(f, "SUBS r{}, r{}, {}", rd, rn, operand),
             _ => write!(f, "unsupported op"),
         }
     }
@@ -130,7 +202,7 @@ mod tests {
         fn parse_positive_offset() {
             assert_eq!(
                 Some(Op::B(0xd0)),
-                Op::parse(&vec![0x32, 0x00, 0x00, 0xEA]),
+                Op::parse(&[0x32, 0x00, 0x00, 0xEA]),
             );
         }
 
@@ -139,7 +211,7 @@ mod tests {
 // TODO I don't have a good example of this in practice yet
 //            assert_eq!(
 //                Some(Op::B(-0xd0)),
-//                Op::parse(&vec![0x32, 0x80, 0x00, 0xEA]),
+//                Op::parse(&[0x32, 0x80, 0x00, 0xEA]),
 //            );
         }
     }
@@ -151,7 +223,39 @@ mod tests {
         fn parse() {
             assert_eq!(
                 Some(Op::Mov(0, 0x12)),
-                Op::parse(&vec![0x12, 0x00, 0xA0, 0xE3])
+                Op::parse(&[0x12, 0x00, 0xA0, 0xE3])
+            )
+        }
+    }
+
+    mod movs {
+        use super::super::*;
+
+        #[test]
+        fn parse_pc_lr() {
+            assert_eq!(
+                Some(Op::Movs(15, Operand::Reg(14))),
+                Op::parse(&[0x0E, 0xF0, 0xB0, 0xE1]),
+            )
+        }
+
+        #[test]
+        fn parse_immediate() {
+            assert_eq!(
+                Some(Op::Movs(1, Operand::Imm(0x12, 0))),
+                Op::parse(&[0x12, 0x10, 0xB0, 0xE3]),
+            )
+        }
+    }
+
+    mod subs {
+        use super::super::*;
+
+        #[test]
+        fn parse_pc_lr() {
+            assert_eq!(
+                Some(Op::Subs(15, 14, Operand::Imm(4, 0))),
+                Op::parse(&[0x04, 0xF0, 0x5E, 0xE2]),
             )
         }
     }
@@ -163,7 +267,7 @@ mod tests {
         fn parse_register() {
             assert_eq!(
                 Some(Op::Msr(false, false, 0x09, 0)),
-                Op::parse(&vec![0x00, 0xF8, 0x29, 0xE1]),
+                Op::parse(&[0x00, 0xF8, 0x29, 0xE1]),
             )
         }
     }
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+
+// Binary traces start with this magic so readers can tell them apart from
+// text traces.
+pub const BINARY_MAGIC: &[u8; 8] = b"GBATRC01";
+pub const BINARY_RECORD_SIZE: usize = 19 * 4;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+// A snapshot of the CPU taken just before an instruction executes. r15 holds
+// the value the instruction itself would read, which is two instructions
+// ahead of the pc because of the pipeline. That is also what mGBA and
+// NanoBoyAdvance log, so traces line up with theirs.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceRecord {
+    pub pc: u32,
+    pub opcode: u32,
+    pub regs: [u32; 16],
+    pub cpsr: u32,
+    pub disasm: String,
+}
+
+impl TraceRecord {
+    // Parses a single line written in the text format. The disassembly is
+    // optional so hand-trimmed reference traces still parse.
+    pub fn parse_line(line: &str) -> Option<TraceRecord> {
+        let (state, instr) = match line.find('|') {
+            Some(idx) => (&line[..idx], &line[idx + 1..]),
+            None => return None,
+        };
+
+        let mut fields = state.split_whitespace();
+        let mut regs = [0; 16];
+        for reg in regs.iter_mut() {
+            *reg = parse_hex(fields.next()?)?;
+        }
+        match fields.next() {
+            Some("cpsr:") => {}
+            _ => return None,
+        }
+        let cpsr = parse_hex(fields.next()?)?;
+
+        let instr = instr.trim();
+        let (pc, rest) = match instr.find(':') {
+            Some(idx) => (parse_hex(&instr[..idx])?, instr[idx + 1..].trim_start()),
+            None => return None,
+        };
+        let (opcode, disasm) = match rest.find(char::is_whitespace) {
+            Some(idx) => (parse_hex(&rest[..idx])?, rest[idx..].trim()),
+            None => (parse_hex(rest)?, ""),
+        };
+
+        Some(TraceRecord {
+            pc,
+            opcode,
+            regs,
+            cpsr,
+            disasm: String::from(disasm),
+        })
+    }
+
+    pub fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "{}", self)
+    }
+
+    pub fn write_binary(&self, w: &mut dyn Write) -> io::Result<()> {
+        let mut buf = [0; BINARY_RECORD_SIZE];
+        let words = [self.pc, self.opcode].iter()
+            .chain(self.regs.iter())
+            .chain([self.cpsr].iter())
+            .cloned()
+            .collect::<Vec<u32>>();
+        for (idx, word) in words.iter().enumerate() {
+            buf[idx * 4..idx * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        w.write_all(&buf)
+    }
+
+    // Reads the next binary record, returning None at a clean end of file.
+    // A file that ends partway through a record is truncated or corrupt.
+    pub fn read_binary(r: &mut dyn Read) -> io::Result<Option<TraceRecord>> {
+        let mut buf = [0; BINARY_RECORD_SIZE];
+        let mut len = 0;
+        while len < BINARY_RECORD_SIZE {
+            match r.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match len {
+            0 => return Ok(None),
+            BINARY_RECORD_SIZE => {}
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("trace ends partway through a record, {} of {} bytes", len, BINARY_RECORD_SIZE),
+            )),
+        }
+
+        let word = |idx: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&buf[idx * 4..idx * 4 + 4]);
+            u32::from_le_bytes(bytes)
+        };
+
+        let mut regs = [0; 16];
+        for (idx, reg) in regs.iter_mut().enumerate() {
+            *reg = word(idx + 2);
+        }
+
+        Ok(Some(TraceRecord {
+            pc: word(0),
+            opcode: word(1),
+            regs,
+            cpsr: word(18),
+            disasm: String::new(),
+        }))
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for reg in self.regs.iter() {
+            write!(f, "{:08X} ", reg)?;
+        }
+        write!(f, "cpsr: {:08X} | {:08X}: {:08X}", self.cpsr, self.pc, self.opcode)?;
+        match self.disasm.is_empty() {
+            true => Ok(()),
+            false => write!(f, "  {}", self.disasm),
+        }
+    }
+}
+
+pub struct Trace {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    started: bool,
+}
+
+impl Trace {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Trace {
+        Trace {
+            out,
+            format,
+            started: false,
+        }
+    }
+
+    pub fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => record.write_text(&mut self.out),
+            TraceFormat::Binary => {
+                if !self.started {
+                    self.out.write_all(BINARY_MAGIC)?;
+                    self.started = true;
+                }
+                record.write_binary(&mut self.out)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    mod trace_record {
+        use super::super::*;
+
+        fn record() -> TraceRecord {
+            let mut regs = [0; 16];
+            for (idx, reg) in regs.iter_mut().enumerate() {
+                *reg = idx as u32;
+            }
+            regs[15] = 0x08_00_00_08;
+
+            TraceRecord {
+                pc: 0x08_00_00_00,
+                opcode: 0xEA_00_00_2E,
+                regs,
+                cpsr: 0x1F,
+                disasm: String::from("B 0xc0"),
+            }
+        }
+
+        #[test]
+        fn text_format() {
+            assert_eq!(
+                "00000000 00000001 00000002 00000003 00000004 00000005 00000006 00000007 \
+                 00000008 00000009 0000000A 0000000B 0000000C 0000000D 0000000E 08000008 \
+                 cpsr: 0000001F | 08000000: EA00002E  B 0xc0",
+                record().to_string(),
+            );
+        }
+
+        #[test]
+        fn text_round_trip() {
+            assert_eq!(Some(record()), TraceRecord::parse_line(&record().to_string()));
+        }
+
+        #[test]
+        fn parse_line_without_disasm() {
+            let mut expected = record();
+            expected.disasm = String::new();
+
+            let line = record().to_string();
+            let line = line.trim_end_matches("  B 0xc0");
+            assert_eq!(Some(expected), TraceRecord::parse_line(line));
+        }
+
+        #[test]
+        fn parse_line_invalid() {
+            assert_eq!(None, TraceRecord::parse_line("not a trace line"));
+        }
+
+        #[test]
+        fn binary_round_trip() {
+            let mut buf = Vec::new();
+            record().write_binary(&mut buf).unwrap();
+            assert_eq!(BINARY_RECORD_SIZE, buf.len());
+
+            let mut expected = record();
+            expected.disasm = String::new();
+
+            let mut r = &buf[..];
+            assert_eq!(Some(expected), TraceRecord::read_binary(&mut r).unwrap());
+            assert_eq!(None, TraceRecord::read_binary(&mut r).unwrap());
+        }
+
+        #[test]
+        fn binary_truncated() {
+            let mut buf = Vec::new();
+            record().write_binary(&mut buf).unwrap();
+            buf.truncate(BINARY_RECORD_SIZE - 1);
+
+            let mut r = &buf[..];
+            let err = TraceRecord::read_binary(&mut r).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+}
@@ -0,0 +1,58 @@
+// CRC-32 as used by zip, PNG and most ROM databases.
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+const TABLE: [u32; 256] = new_table();
+
+const fn new_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ POLYNOMIAL,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    update(0, data)
+}
+
+// Continues a CRC over more data, for data that isn't all in one place.
+pub fn update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for b in data.iter() {
+        crc = TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    mod crc32 {
+        use super::super::*;
+
+        #[test]
+        fn empty() {
+            assert_eq!(0, crc32(&[]));
+        }
+
+        #[test]
+        fn check_value() {
+            assert_eq!(0xCBF43926, crc32(b"123456789"));
+        }
+
+        #[test]
+        fn update_in_parts() {
+            assert_eq!(crc32(b"123456789"), update(crc32(b"1234"), b"56789"));
+        }
+    }
+}
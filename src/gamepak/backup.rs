@@ -0,0 +1,301 @@
+use std::fmt;
+
+use super::eeprom::{Eeprom, EEPROM_512, EEPROM_8K};
+use super::flash::{Flash, FlashChip, FLASH_128K, FLASH_64K};
+use super::sram::Sram;
+
+// The kind of memory a cartridge keeps its saves in, mapped at PAK_RAM
+// (or, for EEPROM, at the top of the ROM space).
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum BackupType {
+    None,
+    Sram,
+    Eeprom,
+    Flash64K,
+    Flash128K,
+}
+
+impl BackupType {
+    pub fn from_name(name: &str) -> Option<BackupType> {
+        match name {
+            "none" => Some(BackupType::None),
+            "sram" => Some(BackupType::Sram),
+            "eeprom" => Some(BackupType::Eeprom),
+            "flash64" => Some(BackupType::Flash64K),
+            "flash128" => Some(BackupType::Flash128K),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackupType::None => "none",
+            BackupType::Sram => "sram",
+            BackupType::Eeprom => "eeprom",
+            BackupType::Flash64K => "flash64",
+            BackupType::Flash128K => "flash128",
+        }
+    }
+}
+
+impl fmt::Display for BackupType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// The save memory a cartridge has. EEPROM isn't on the PAK_RAM bus at all,
+// so it looks like no backup from there.
+pub enum Backup {
+    None,
+    Sram(Sram),
+    Eeprom(Eeprom),
+    Flash(Flash),
+}
+
+impl Backup {
+    // Flash backups get the usual chip for their size unless one is given.
+    pub fn new(backup_type: BackupType, flash_chip: Option<FlashChip>) -> Backup {
+        let flash = |size| Backup::Flash(Flash::new(size, flash_chip.unwrap_or_else(|| FlashChip::default_for(size))));
+
+        match backup_type {
+            BackupType::Sram => Backup::Sram(Sram::new()),
+            BackupType::Eeprom => Backup::Eeprom(Eeprom::new(None)),
+            BackupType::Flash64K => flash(FLASH_64K),
+            BackupType::Flash128K => flash(FLASH_128K),
+            _ => Backup::None,
+        }
+    }
+
+    // With nothing to answer them, reads from PAK_RAM see the bus pulled high.
+    pub fn read8(&self, offset: usize) -> u8 {
+        match self {
+            Backup::None | Backup::Eeprom(_) => 0xFF,
+            Backup::Sram(s) => s.read8(offset),
+            Backup::Flash(f) => f.read8(offset),
+        }
+    }
+
+    pub fn write8(&mut self, offset: usize, val: u8) {
+        match self {
+            Backup::None | Backup::Eeprom(_) => {}
+            Backup::Sram(s) => s.write8(offset, val),
+            Backup::Flash(f) => f.write8(offset, val),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Backup::None => &[],
+            Backup::Sram(s) => s.data(),
+            Backup::Eeprom(e) => e.data(),
+            Backup::Flash(f) => f.data(),
+        }
+    }
+
+    // Fills the backup with a save. EEPROM whose size isn't known yet takes
+    // the size of the save, if it is a size EEPROM comes in. Saves of the
+    // wrong size are loaded as far as they fit.
+    pub fn load(&mut self, save: &[u8]) -> Result<(), SizeMismatch> {
+        if let Backup::Eeprom(e) = self {
+            if !e.is_size_known() && (save.len() == EEPROM_512 || save.len() == EEPROM_8K) {
+                e.set_size(save.len());
+            }
+        }
+
+        let data = match self {
+            Backup::None => &mut [],
+            Backup::Sram(s) => s.data_mut(),
+            Backup::Eeprom(e) => e.data_mut(),
+            Backup::Flash(f) => f.data_mut(),
+        };
+        let len = data.len().min(save.len());
+        data[..len].copy_from_slice(&save[..len]);
+
+        match data.len() == save.len() {
+            true => Ok(()),
+            false => Err(SizeMismatch { expected: data.len(), actual: save.len() }),
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        match self {
+            Backup::None => false,
+            Backup::Sram(s) => s.is_dirty(),
+            Backup::Eeprom(e) => e.is_dirty(),
+            Backup::Flash(f) => f.is_dirty(),
+        }
+    }
+
+    pub fn clear_dirty(&mut self) {
+        match self {
+            Backup::None => {}
+            Backup::Sram(s) => s.clear_dirty(),
+            Backup::Eeprom(e) => e.clear_dirty(),
+            Backup::Flash(f) => f.clear_dirty(),
+        }
+    }
+
+    pub fn eeprom_mut(&mut self) -> Option<&mut Eeprom> {
+        match self {
+            Backup::Eeprom(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct SizeMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "save is {} bytes but the backup is {} bytes", self.actual, self.expected)
+    }
+}
+
+// Games built with the official SDK link in a backup library, which leaves
+// an ID string like "FLASH1M_V103" in the ROM. SRAM_F is the FRAM library,
+// which is used just like SRAM.
+const LIBRARY_IDS: [(&[u8], BackupType); 6] = [
+    (b"EEPROM_V", BackupType::Eeprom),
+    (b"SRAM_V", BackupType::Sram),
+    (b"SRAM_F_V", BackupType::Sram),
+    (b"FLASH_V", BackupType::Flash64K),
+    (b"FLASH512_V", BackupType::Flash64K),
+    (b"FLASH1M_V", BackupType::Flash128K),
+];
+
+const VERSION_SIZE: usize = 3;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LibraryId {
+    pub backup_type: BackupType,
+    pub name: String,
+    pub version: String,
+}
+
+impl fmt::Display for LibraryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.name, self.version)
+    }
+}
+
+// Finds the backup library ID in a ROM. The strings are word aligned, so only
+// every fourth byte needs checking.
+pub fn detect(data: &[u8]) -> Option<LibraryId> {
+    for offset in (0..data.len()).step_by(4) {
+        let rest = &data[offset..];
+        for (id, backup_type) in LIBRARY_IDS.iter() {
+            if !rest.starts_with(id) {
+                continue;
+            }
+
+            let version = match rest.get(id.len()..id.len() + VERSION_SIZE) {
+                Some(v) if v.iter().all(|b| b.is_ascii_digit()) => v,
+                _ => continue,
+            };
+            return Some(LibraryId {
+                backup_type: *backup_type,
+                name: String::from_utf8_lossy(id).to_string(),
+                version: String::from_utf8_lossy(version).to_string(),
+            });
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    mod backup {
+        use super::super::*;
+
+        #[test]
+        fn load() {
+            let mut b = Backup::new(BackupType::Sram, None);
+            let mut save = vec![0; 32 * 1024];
+            save[0x10] = 0x12;
+
+            assert_eq!(Ok(()), b.load(&save));
+            assert_eq!(0x12, b.read8(0x10));
+            assert!(!b.is_dirty());
+        }
+
+        #[test]
+        fn load_size_mismatch() {
+            let mut b = Backup::new(BackupType::Flash64K, None);
+            let save = vec![0x12; FLASH_128K];
+
+            assert_eq!(Err(SizeMismatch { expected: FLASH_64K, actual: FLASH_128K }), b.load(&save));
+            assert_eq!(0x12, b.read8(0xFFFF));
+        }
+
+        #[test]
+        fn load_eeprom_size() {
+            let mut b = Backup::new(BackupType::Eeprom, None);
+            assert_eq!(Ok(()), b.load(&[0; EEPROM_8K]));
+            assert_eq!(EEPROM_8K, b.data().len());
+
+            assert_eq!(Err(SizeMismatch { expected: EEPROM_8K, actual: 100 }), b.load(&[0; 100]));
+        }
+    }
+
+    mod detect {
+        use super::super::*;
+
+        fn rom(id: &[u8], offset: usize) -> Vec<u8> {
+            let mut data = vec![0; 0x400];
+            data[offset..offset + id.len()].copy_from_slice(id);
+            data
+        }
+
+        #[test]
+        fn library_ids() {
+            let cases: [(&[u8], BackupType); 6] = [
+                (b"EEPROM_V124", BackupType::Eeprom),
+                (b"SRAM_V113", BackupType::Sram),
+                (b"SRAM_F_V102", BackupType::Sram),
+                (b"FLASH_V126", BackupType::Flash64K),
+                (b"FLASH512_V131", BackupType::Flash64K),
+                (b"FLASH1M_V103", BackupType::Flash128K),
+            ];
+            for (id, backup_type) in cases.iter() {
+                let detected = detect(&rom(id, 0x200)).unwrap();
+                assert_eq!(*backup_type, detected.backup_type);
+                assert_eq!(String::from_utf8_lossy(id), detected.to_string());
+            }
+        }
+
+        #[test]
+        fn version() {
+            let detected = detect(&rom(b"FLASH1M_V103", 0x100)).unwrap();
+            assert_eq!("FLASH1M_V", detected.name);
+            assert_eq!("103", detected.version);
+        }
+
+        #[test]
+        fn unaligned_ignored() {
+            assert_eq!(None, detect(&rom(b"SRAM_V113", 0x102)));
+        }
+
+        #[test]
+        fn without_version_ignored() {
+            assert_eq!(None, detect(&rom(b"SRAM_V", 0x100)));
+            assert_eq!(None, detect(&rom(b"SRAM_Vxyz", 0x100)));
+        }
+
+        #[test]
+        fn none() {
+            assert_eq!(None, detect(&[0; 0x400]));
+        }
+
+        #[test]
+        fn from_name() {
+            assert_eq!(Some(BackupType::Flash128K), BackupType::from_name("flash128"));
+            assert_eq!(None, BackupType::from_name("flash"));
+        }
+    }
+}
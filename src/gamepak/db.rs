@@ -0,0 +1,68 @@
+// Hardware a cartridge can have beyond its ROM and save memory, which
+// nothing in the ROM says it has.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Feature {
+    Rtc,
+    SolarSensor,
+    Tilt,
+    Gyro,
+    Rumble,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Game {
+    // The first three characters of the game code. The last one is the
+    // region, and the hardware is the same in all of them.
+    pub code: &'static str,
+    pub title: &'static str,
+    pub features: &'static [Feature],
+}
+
+impl Game {
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+const GAMES: [Game; 12] = [
+    Game { code: "AXV", title: "Pokemon Ruby", features: &[Feature::Rtc] },
+    Game { code: "AXP", title: "Pokemon Sapphire", features: &[Feature::Rtc] },
+    Game { code: "BPE", title: "Pokemon Emerald", features: &[Feature::Rtc] },
+    Game { code: "BR4", title: "Rockman EXE 4.5 Real Operation", features: &[Feature::Rtc] },
+    Game { code: "BKA", title: "Sennen Kazoku", features: &[Feature::Rtc] },
+    Game { code: "U3I", title: "Boktai", features: &[Feature::Rtc, Feature::SolarSensor] },
+    Game { code: "U32", title: "Boktai 2", features: &[Feature::Rtc, Feature::SolarSensor] },
+    Game { code: "U33", title: "Shin Bokura no Taiyou", features: &[Feature::Rtc, Feature::SolarSensor] },
+    Game { code: "KYG", title: "Yoshi Topsy-Turvy", features: &[Feature::Tilt] },
+    Game { code: "KHP", title: "Koro Koro Puzzle Happy Panechu!", features: &[Feature::Tilt] },
+    Game { code: "RZW", title: "WarioWare: Twisted!", features: &[Feature::Gyro, Feature::Rumble] },
+    Game { code: "V49", title: "Drill Dozer", features: &[Feature::Rumble] },
+];
+
+// Finds the game with the given game code from the cartridge header.
+pub fn lookup(game_code: &str) -> Option<&'static Game> {
+    GAMES.iter().find(|g| game_code.starts_with(g.code))
+}
+
+#[cfg(test)]
+mod tests {
+    mod db {
+        use super::super::*;
+
+        #[test]
+        fn lookup_any_region() {
+            assert_eq!("Pokemon Sapphire", lookup("AXPE").unwrap().title);
+            assert_eq!("Pokemon Sapphire", lookup("AXPJ").unwrap().title);
+            assert!(lookup("AXPE").unwrap().has(Feature::Rtc));
+            assert!(!lookup("AXPE").unwrap().has(Feature::SolarSensor));
+            assert!(lookup("U3IJ").unwrap().has(Feature::SolarSensor));
+            assert!(lookup("RZWE").unwrap().has(Feature::Gyro));
+        }
+
+        #[test]
+        fn lookup_unknown() {
+            assert_eq!(None, lookup("ZZZE"));
+            assert_eq!(None, lookup(""));
+        }
+    }
+}
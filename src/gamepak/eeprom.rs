@@ -0,0 +1,293 @@
+const KBYTE: usize = 1024;
+
+pub const EEPROM_512: usize = 512;
+pub const EEPROM_8K: usize = 8 * KBYTE;
+
+// EEPROM is read and written 64 bits at a time, and addressed in blocks of
+// that size.
+const BLOCK_SIZE: usize = 8;
+const BLOCK_BITS: usize = BLOCK_SIZE * 8;
+
+// Reads answer with 4 bits of padding before the data.
+const READ_PADDING: usize = 4;
+
+// Writing a block takes about 6.5ms, during which the chip reports busy.
+pub const WRITE_CYCLES: u64 = 108_368;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum State {
+    // Taking in the bits of a request.
+    Request,
+    // Sending back the bits of the block at addr.
+    Reading { addr: usize, bit: usize },
+}
+
+// EEPROM backup memory. It is wired to the data bus one bit at a time, so
+// games talk to it with DMA transfers of halfwords whose lowest bit is the
+// one that counts. Requests start with 11 to read a block or 10 to write
+// one, followed by the block's address, the 64 bits to write if writing,
+// and a 0. Reads get 4 bits of padding and then the block.
+//
+// The 512 byte chips take 6-bit addresses and the 8 KiB chips 14-bit ones,
+// but nothing in the cartridge says which is fitted. Unless told, the size
+// is worked out from the length of the first request a game sends.
+pub struct Eeprom {
+    data: Vec<u8>,
+    size_known: bool,
+    dirty: bool,
+
+    state: State,
+    bits: u128,
+    bit_count: usize,
+    ready_at: u64,
+}
+
+impl Eeprom {
+    pub fn new(size: Option<usize>) -> Eeprom {
+        Eeprom {
+            data: vec![0xFF; size.unwrap_or(EEPROM_512)],
+            size_known: size.is_some(),
+            dirty: false,
+
+            state: State::Request,
+            bits: 0,
+            bit_count: 0,
+            ready_at: 0,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_size_known(&self) -> bool {
+        self.size_known
+    }
+
+    // Fixes the size of the chip, growing or shrinking the data to match.
+    pub fn set_size(&mut self, size: usize) {
+        self.data.resize(size, 0xFF);
+        self.size_known = true;
+    }
+
+    // Works out the size of the chip from the number of bits in a DMA
+    // transfer to it, if it isn't known yet. Read requests are 9 or 17 bits
+    // long and writes 73 or 81, depending on the address width. Transfers
+    // of any other length say nothing about the size.
+    pub fn infer_size(&mut self, count: u32) {
+        if self.size_known {
+            return;
+        }
+
+        match count {
+            9 | 73 => self.set_size(EEPROM_512),
+            17 | 81 => self.set_size(EEPROM_8K),
+            _ => {}
+        }
+    }
+
+    fn addr_bits(&self) -> usize {
+        match self.data.len() {
+            EEPROM_512 => 6,
+            _ => 14,
+        }
+    }
+
+    // Reads the next bit of a block being read, or otherwise whether the
+    // chip is ready for another request.
+    pub fn read_bit(&mut self, cycle: u64) -> u16 {
+        match self.state {
+            State::Reading { addr, bit } => {
+                let next = bit + 1;
+                self.state = match next {
+                    n if n == READ_PADDING + BLOCK_BITS => State::Request,
+                    _ => State::Reading { addr, bit: next },
+                };
+                match bit.checked_sub(READ_PADDING) {
+                    Some(bit) => ((self.data[addr + bit / 8] >> (7 - bit % 8)) & 0x1) as u16,
+                    None => 0,
+                }
+            }
+            State::Request => (cycle >= self.ready_at) as u16,
+        }
+    }
+
+    // Takes the next bit of a request. Requests sent while the chip is busy
+    // writing are ignored.
+    pub fn write_bit(&mut self, val: u16, cycle: u64) {
+        if cycle < self.ready_at {
+            return;
+        }
+
+        self.state = State::Request;
+        self.bits = (self.bits << 1) | (val & 0x1) as u128;
+        self.bit_count += 1;
+
+        let addr_bits = self.addr_bits();
+        let len = match self.bit_count {
+            1 => return,
+            _ => match (self.bits >> (self.bit_count - 2)) & 0x3 {
+                0b11 => 2 + addr_bits + 1,
+                0b10 => 2 + addr_bits + BLOCK_BITS + 1,
+                // Not a request, so start over.
+                _ => {
+                    self.bit_count = 0;
+                    self.bits = 0;
+                    return;
+                }
+            },
+        };
+        if self.bit_count < len {
+            return;
+        }
+
+        let bits = self.bits;
+        let write = (bits >> (len - 2)) & 0x1 == 0;
+        self.bits = 0;
+        self.bit_count = 0;
+
+        // The stop bit is last, after the data when there is any.
+        let data_bits = if write { BLOCK_BITS } else { 0 };
+        let addr = ((bits >> (1 + data_bits)) as usize & ((1 << addr_bits) - 1)) * BLOCK_SIZE;
+        let addr = addr % self.data.len();
+        match write {
+            true => {
+                let block = ((bits >> 1) as u64).to_be_bytes();
+                if self.data[addr..addr + BLOCK_SIZE] != block {
+                    self.data[addr..addr + BLOCK_SIZE].copy_from_slice(&block);
+                    self.dirty = true;
+                }
+                self.ready_at = cycle + WRITE_CYCLES;
+            }
+            false => self.state = State::Reading { addr, bit: 0 },
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod eeprom {
+        use super::super::*;
+
+        fn send(e: &mut Eeprom, bits: &[u16], cycle: u64) {
+            for bit in bits {
+                e.write_bit(*bit, cycle);
+            }
+        }
+
+        fn addr(addr: usize, width: usize) -> Vec<u16> {
+            (0..width).rev().map(|bit| ((addr >> bit) & 0x1) as u16).collect()
+        }
+
+        fn write(e: &mut Eeprom, block: usize, width: usize, data: u64) {
+            send(e, &[1, 0], 0);
+            send(e, &addr(block, width), 0);
+            for bit in (0..64).rev() {
+                e.write_bit(((data >> bit) & 0x1) as u16, 0);
+            }
+            send(e, &[0], 0);
+        }
+
+        // Reads wait until any write before them has finished.
+        fn read(e: &mut Eeprom, block: usize, width: usize) -> (u64, u64) {
+            send(e, &[1, 1], WRITE_CYCLES);
+            send(e, &addr(block, width), WRITE_CYCLES);
+            send(e, &[0], WRITE_CYCLES);
+
+            let mut padding = 0;
+            for _ in 0..4 {
+                padding = (padding << 1) | e.read_bit(0) as u64;
+            }
+            let mut data = 0;
+            for _ in 0..64 {
+                data = (data << 1) | e.read_bit(0) as u64;
+            }
+            (padding, data)
+        }
+
+        #[test]
+        fn new() {
+            let e = Eeprom::new(None);
+            assert_eq!(EEPROM_512, e.size());
+            assert!(!e.is_size_known());
+            assert!(e.data().iter().all(|b| *b == 0xFF));
+        }
+
+        #[test]
+        fn write_read_512() {
+            let mut e = Eeprom::new(Some(EEPROM_512));
+            write(&mut e, 3, 6, 0x0123_4567_89AB_CDEF);
+
+            assert_eq!(&[0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF], &e.data()[24..32]);
+            assert!(e.is_dirty());
+            assert_eq!((0, 0x0123_4567_89AB_CDEF), read(&mut e, 3, 6));
+            assert_eq!((0, u64::MAX), read(&mut e, 4, 6));
+        }
+
+        #[test]
+        fn write_read_8k() {
+            let mut e = Eeprom::new(Some(EEPROM_8K));
+            write(&mut e, 0x3FF, 14, 0x1122_3344_5566_7788);
+
+            assert_eq!(0x88, e.data()[EEPROM_8K - 1]);
+            assert_eq!((0, 0x1122_3344_5566_7788), read(&mut e, 0x3FF, 14));
+        }
+
+        #[test]
+        fn busy_after_write() {
+            let mut e = Eeprom::new(Some(EEPROM_512));
+            assert_eq!(1, e.read_bit(0));
+
+            write(&mut e, 0, 6, 0);
+            assert_eq!(0, e.read_bit(WRITE_CYCLES - 1));
+            assert_eq!(1, e.read_bit(WRITE_CYCLES));
+        }
+
+        #[test]
+        fn infer_size() {
+            let mut e = Eeprom::new(None);
+            e.infer_size(68);
+            assert!(!e.is_size_known());
+
+            e.infer_size(17);
+            assert_eq!(EEPROM_8K, e.size());
+            assert!(e.is_size_known());
+
+            e.infer_size(9);
+            assert_eq!(EEPROM_8K, e.size());
+        }
+
+        #[test]
+        fn infer_size_512() {
+            let mut e = Eeprom::new(None);
+            e.infer_size(73);
+            assert_eq!(EEPROM_512, e.size());
+            assert!(e.is_size_known());
+        }
+
+        #[test]
+        fn invalid_request_ignored() {
+            let mut e = Eeprom::new(Some(EEPROM_512));
+            send(&mut e, &[0, 1], 0);
+            write(&mut e, 1, 6, 0x1234);
+
+            assert_eq!((0, 0x1234), read(&mut e, 1, 6));
+        }
+    }
+}
@@ -0,0 +1,374 @@
+const KBYTE: usize = 1024;
+
+pub const FLASH_64K: usize = 64 * KBYTE;
+pub const FLASH_128K: usize = 128 * KBYTE;
+
+const BANK_SIZE: usize = 64 * KBYTE;
+const SECTOR_SIZE: usize = 4 * KBYTE;
+const ATMEL_PAGE_SIZE: usize = 128;
+
+// Commands are unlocked by writing 0xAA then 0x55 to these addresses, and
+// then written to the first of them.
+const CMD_ADDR1: usize = 0x5555;
+const CMD_ADDR2: usize = 0x2AAA;
+
+const CMD_ENTER_ID: u8 = 0x90;
+const CMD_EXIT_ID: u8 = 0xF0;
+const CMD_ERASE: u8 = 0x80;
+const CMD_ERASE_CHIP: u8 = 0x10;
+const CMD_ERASE_SECTOR: u8 = 0x30;
+const CMD_PROGRAM: u8 = 0xA0;
+const CMD_BANK: u8 = 0xB0;
+
+// The chips found in cartridges. Games read the ID back to decide how to
+// drive the chip, and some only know about the chips they shipped with.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum FlashChip {
+    Macronix,
+    Panasonic,
+    Sanyo,
+    Sst,
+    Atmel,
+}
+
+impl FlashChip {
+    pub fn from_name(name: &str) -> Option<FlashChip> {
+        match name {
+            "macronix" => Some(FlashChip::Macronix),
+            "panasonic" => Some(FlashChip::Panasonic),
+            "sanyo" => Some(FlashChip::Sanyo),
+            "sst" => Some(FlashChip::Sst),
+            "atmel" => Some(FlashChip::Atmel),
+            _ => None,
+        }
+    }
+
+    // The chips most 64 and 128 KiB cartridges shipped with.
+    pub fn default_for(size: usize) -> FlashChip {
+        match size {
+            FLASH_128K => FlashChip::Sanyo,
+            _ => FlashChip::Panasonic,
+        }
+    }
+
+    // Returns the manufacturer and device IDs the chip answers with.
+    pub fn id(&self, size: usize) -> (u8, u8) {
+        match self {
+            FlashChip::Macronix if size == FLASH_128K => (0xC2, 0x09),
+            FlashChip::Macronix => (0xC2, 0x1C),
+            FlashChip::Panasonic => (0x32, 0x1B),
+            FlashChip::Sanyo => (0x62, 0x13),
+            FlashChip::Sst => (0xBF, 0xD4),
+            FlashChip::Atmel => (0x1F, 0x3D),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Pending {
+    None,
+    // Bytes left to program before going back to reading.
+    Program(usize),
+    Bank,
+}
+
+// Flash backup memory. Reads come straight from the array, but writes are
+// commands to the chip, which only changes the array when told to erase or
+// program it. 128 KiB chips are addressed as two 64 KiB banks.
+pub struct Flash {
+    data: Vec<u8>,
+    chip: FlashChip,
+    dirty: bool,
+
+    unlock: u8,
+    id_mode: bool,
+    erase_armed: bool,
+    pending: Pending,
+    bank: usize,
+}
+
+impl Flash {
+    pub fn new(size: usize, chip: FlashChip) -> Flash {
+        Flash {
+            data: vec![0xFF; size],
+            chip,
+            dirty: false,
+
+            unlock: 0,
+            id_mode: false,
+            erase_armed: false,
+            pending: Pending::None,
+            bank: 0,
+        }
+    }
+
+    pub fn chip(&self) -> FlashChip {
+        self.chip
+    }
+
+    pub fn set_chip(&mut self, chip: FlashChip) {
+        self.chip = chip;
+    }
+
+    pub fn read8(&self, offset: usize) -> u8 {
+        let offset = offset % BANK_SIZE;
+        if self.id_mode && offset < 2 {
+            let (manufacturer, device) = self.chip.id(self.data.len());
+            return match offset {
+                0 => manufacturer,
+                _ => device,
+            };
+        }
+
+        self.data[self.bank * BANK_SIZE + offset]
+    }
+
+    pub fn write8(&mut self, offset: usize, val: u8) {
+        let offset = offset % BANK_SIZE;
+
+        match self.pending {
+            Pending::Program(count) => {
+                self.program(offset, val);
+                self.pending = match count {
+                    1 => Pending::None,
+                    _ => Pending::Program(count - 1),
+                };
+                return;
+            }
+            Pending::Bank => {
+                if offset == 0 {
+                    self.bank = val as usize & (self.data.len() / BANK_SIZE - 1);
+                }
+                self.pending = Pending::None;
+                return;
+            }
+            Pending::None => {}
+        }
+
+        match (self.unlock, offset, val) {
+            (0, CMD_ADDR1, 0xAA) => self.unlock = 1,
+            (1, CMD_ADDR2, 0x55) => self.unlock = 2,
+            (2, _, CMD_ERASE_SECTOR) if self.erase_armed && offset % SECTOR_SIZE == 0 => {
+                self.unlock = 0;
+                self.erase_armed = false;
+                self.erase(self.bank * BANK_SIZE + offset, SECTOR_SIZE);
+            }
+            (2, CMD_ADDR1, cmd) => {
+                self.unlock = 0;
+                self.command(cmd);
+            }
+            // Leaving ID mode works without unlocking on most chips.
+            (_, _, CMD_EXIT_ID) => {
+                self.unlock = 0;
+                self.id_mode = false;
+            }
+            _ => self.unlock = 0,
+        }
+    }
+
+    fn command(&mut self, cmd: u8) {
+        let erase_armed = self.erase_armed;
+        self.erase_armed = false;
+
+        match cmd {
+            CMD_ENTER_ID => self.id_mode = true,
+            CMD_EXIT_ID => self.id_mode = false,
+            CMD_ERASE => self.erase_armed = true,
+            CMD_ERASE_CHIP if erase_armed => {
+                let size = self.data.len();
+                self.erase(0, size);
+            }
+            // Atmel chips have no erase command, and program a whole page at
+            // a time instead.
+            CMD_PROGRAM => {
+                self.pending = match self.chip {
+                    FlashChip::Atmel => Pending::Program(ATMEL_PAGE_SIZE),
+                    _ => Pending::Program(1),
+                }
+            }
+            CMD_BANK if self.data.len() == FLASH_128K => self.pending = Pending::Bank,
+            _ => {}
+        }
+    }
+
+    fn program(&mut self, offset: usize, val: u8) {
+        let addr = self.bank * BANK_SIZE + offset;
+        if self.chip == FlashChip::Atmel && self.pending == Pending::Program(ATMEL_PAGE_SIZE) {
+            self.erase(addr & !(ATMEL_PAGE_SIZE - 1), ATMEL_PAGE_SIZE);
+        }
+
+        if self.data[addr] != val {
+            self.data[addr] = val;
+            self.dirty = true;
+        }
+    }
+
+    fn erase(&mut self, start: usize, size: usize) {
+        let range = &mut self.data[start..start + size];
+        if range.iter().any(|b| *b != 0xFF) {
+            range.fill(0xFF);
+            self.dirty = true;
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod flash {
+        use super::super::*;
+
+        fn command(f: &mut Flash, cmd: u8) {
+            f.write8(CMD_ADDR1, 0xAA);
+            f.write8(CMD_ADDR2, 0x55);
+            f.write8(CMD_ADDR1, cmd);
+        }
+
+        fn program(f: &mut Flash, offset: usize, val: u8) {
+            command(f, CMD_PROGRAM);
+            f.write8(offset, val);
+        }
+
+        #[test]
+        fn new() {
+            let f = Flash::new(FLASH_64K, FlashChip::Panasonic);
+            assert_eq!(FLASH_64K, f.data().len());
+            assert_eq!(0xFF, f.read8(0));
+            assert!(!f.is_dirty());
+        }
+
+        #[test]
+        fn chip_id() {
+            let mut f = Flash::new(FLASH_128K, FlashChip::Sanyo);
+            command(&mut f, CMD_ENTER_ID);
+            assert_eq!(0x62, f.read8(0));
+            assert_eq!(0x13, f.read8(1));
+
+            command(&mut f, CMD_EXIT_ID);
+            assert_eq!(0xFF, f.read8(0));
+        }
+
+        #[test]
+        fn chip_ids() {
+            assert_eq!((0xC2, 0x1C), FlashChip::Macronix.id(FLASH_64K));
+            assert_eq!((0xC2, 0x09), FlashChip::Macronix.id(FLASH_128K));
+            assert_eq!((0x32, 0x1B), FlashChip::Panasonic.id(FLASH_64K));
+            assert_eq!((0xBF, 0xD4), FlashChip::Sst.id(FLASH_64K));
+            assert_eq!((0x1F, 0x3D), FlashChip::Atmel.id(FLASH_64K));
+        }
+
+        #[test]
+        fn exit_id_without_unlock() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Macronix);
+            command(&mut f, CMD_ENTER_ID);
+            f.write8(0, CMD_EXIT_ID);
+            assert_eq!(0xFF, f.read8(0));
+        }
+
+        #[test]
+        fn write_without_command_ignored() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Panasonic);
+            f.write8(0x10, 0x12);
+            assert_eq!(0xFF, f.read8(0x10));
+            assert!(!f.is_dirty());
+        }
+
+        #[test]
+        fn program_byte() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Panasonic);
+            program(&mut f, 0x10, 0x12);
+            f.write8(0x11, 0x34);
+
+            assert_eq!(0x12, f.read8(0x10));
+            assert_eq!(0xFF, f.read8(0x11));
+            assert!(f.is_dirty());
+        }
+
+        #[test]
+        fn erase_sector() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Panasonic);
+            program(&mut f, 0x1000, 0x12);
+            program(&mut f, 0x2000, 0x34);
+
+            command(&mut f, CMD_ERASE);
+            f.write8(CMD_ADDR1, 0xAA);
+            f.write8(CMD_ADDR2, 0x55);
+            f.write8(0x1000, CMD_ERASE_SECTOR);
+
+            assert_eq!(0xFF, f.read8(0x1000));
+            assert_eq!(0x34, f.read8(0x2000));
+        }
+
+        #[test]
+        fn erase_chip() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Panasonic);
+            program(&mut f, 0x1000, 0x12);
+            program(&mut f, 0xFFFF, 0x34);
+
+            command(&mut f, CMD_ERASE);
+            command(&mut f, CMD_ERASE_CHIP);
+
+            assert!(f.data().iter().all(|b| *b == 0xFF));
+        }
+
+        #[test]
+        fn erase_chip_needs_arming() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Panasonic);
+            program(&mut f, 0x1000, 0x12);
+            command(&mut f, CMD_ERASE_CHIP);
+
+            assert_eq!(0x12, f.read8(0x1000));
+        }
+
+        #[test]
+        fn switch_bank() {
+            let mut f = Flash::new(FLASH_128K, FlashChip::Sanyo);
+            command(&mut f, CMD_BANK);
+            f.write8(0, 1);
+            program(&mut f, 0x10, 0x12);
+
+            assert_eq!(0x12, f.read8(0x10));
+            assert_eq!(0x12, f.data()[BANK_SIZE + 0x10]);
+
+            command(&mut f, CMD_BANK);
+            f.write8(0, 0);
+            assert_eq!(0xFF, f.read8(0x10));
+        }
+
+        #[test]
+        fn switch_bank_64k_ignored() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Panasonic);
+            command(&mut f, CMD_BANK);
+            f.write8(0, 1);
+            assert_eq!(0xFF, f.read8(0));
+        }
+
+        #[test]
+        fn program_atmel_page() {
+            let mut f = Flash::new(FLASH_64K, FlashChip::Atmel);
+            command(&mut f, CMD_PROGRAM);
+            for idx in 0..ATMEL_PAGE_SIZE {
+                f.write8(0x100 + idx, idx as u8);
+            }
+            f.write8(0x200, 0x34);
+
+            assert_eq!(0x7F, f.read8(0x17F));
+            assert_eq!(0xFF, f.read8(0x200));
+        }
+    }
+}
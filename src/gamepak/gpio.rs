@@ -0,0 +1,246 @@
+use super::gyro::{Gyro, PIN_RUMBLE};
+use super::rtc::Rtc;
+use super::solar::SolarSensor;
+
+// The GPIO port's registers sit over unused bytes of the ROM header.
+pub const GPIO_DATA: usize = 0xC4;
+pub const GPIO_DIRECTION: usize = 0xC6;
+pub const GPIO_CONTROL: usize = 0xC8;
+const GPIO_END: usize = 0xCA;
+
+const PIN_MASK: u8 = 0xF;
+
+// The general purpose IO port some cartridges use to talk to extra hardware,
+// like a clock or sensors. It has four pins, each of which the GBA either
+// drives or reads from, as set in the direction register. The registers
+// can always be written, but only read back once the control register
+// allows it, and until then reads see the ROM underneath.
+pub struct Gpio {
+    data: u8,
+    direction: u8,
+    readable: bool,
+
+    rtc: Option<Rtc>,
+    solar: Option<SolarSensor>,
+    gyro: Option<Gyro>,
+    rumble: bool,
+}
+
+impl Gpio {
+    pub fn new() -> Gpio {
+        Gpio {
+            data: 0,
+            direction: 0,
+            readable: false,
+
+            rtc: None,
+            solar: None,
+            gyro: None,
+            rumble: false,
+        }
+    }
+
+    // Whether offset into the ROM falls on the GPIO registers.
+    pub fn contains(offset: usize) -> bool {
+        (GPIO_DATA..GPIO_END).contains(&offset)
+    }
+
+    pub fn set_rtc(&mut self, rtc: Option<Rtc>) {
+        self.rtc = rtc;
+    }
+
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+
+    pub fn set_solar(&mut self, solar: Option<SolarSensor>) {
+        self.solar = solar;
+    }
+
+    pub fn solar(&self) -> Option<&SolarSensor> {
+        self.solar.as_ref()
+    }
+
+    pub fn solar_mut(&mut self) -> Option<&mut SolarSensor> {
+        self.solar.as_mut()
+    }
+
+    pub fn set_gyro(&mut self, gyro: Option<Gyro>) {
+        self.gyro = gyro;
+    }
+
+    pub fn gyro(&self) -> Option<&Gyro> {
+        self.gyro.as_ref()
+    }
+
+    pub fn gyro_mut(&mut self) -> Option<&mut Gyro> {
+        self.gyro.as_mut()
+    }
+
+    // Sets whether the cartridge has a rumble motor, which runs while the
+    // GBA drives its pin high.
+    pub fn set_rumble(&mut self, rumble: bool) {
+        self.rumble = rumble;
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        self.rumble
+    }
+
+    pub fn is_rumbling(&self) -> bool {
+        self.rumble && self.data & self.direction & PIN_RUMBLE != 0
+    }
+
+    // Reads the register at offset into the ROM, or None when the port
+    // can't be read and the ROM shows through. Pins the GBA drives read back
+    // as it set them, and the rest as the hardware drives them.
+    pub fn read16(&self, offset: usize) -> Option<u16> {
+        if !self.readable {
+            return None;
+        }
+
+        match offset {
+            GPIO_DATA => Some(((self.data & self.direction) | (self.pins() & !self.direction)) as u16),
+            GPIO_DIRECTION => Some(self.direction as u16),
+            GPIO_CONTROL => Some(self.readable as u16),
+            _ => None,
+        }
+    }
+
+    pub fn write16(&mut self, offset: usize, val: u16, cycle: u64) {
+        match offset {
+            GPIO_DATA => {
+                self.data = val as u8 & PIN_MASK;
+                let pins = self.data & self.direction;
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.write(pins, cycle);
+                }
+                if let Some(solar) = self.solar.as_mut() {
+                    solar.write(pins, cycle);
+                }
+                if let Some(gyro) = self.gyro.as_mut() {
+                    gyro.write(pins, cycle);
+                }
+            }
+            GPIO_DIRECTION => self.direction = val as u8 & PIN_MASK,
+            GPIO_CONTROL => self.readable = val & 0x1 != 0,
+            _ => {}
+        }
+    }
+
+    fn pins(&self) -> u8 {
+        self.rtc.as_ref().map_or(0, |rtc| rtc.read())
+            | self.solar.as_ref().map_or(0, |solar| solar.read())
+            | self.gyro.as_ref().map_or(0, |gyro| gyro.read())
+    }
+}
+
+impl Default for Gpio {
+    fn default() -> Gpio {
+        Gpio::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod gpio {
+        use super::super::*;
+        use crate::gamepak::rtc::{DateTime, TimeSource, PIN_CS, PIN_SCK, PIN_SIO};
+
+        #[test]
+        fn write_only() {
+            let mut g = Gpio::new();
+            g.write16(GPIO_DIRECTION, 0x7, 0);
+            assert_eq!(None, g.read16(GPIO_DIRECTION));
+
+            g.write16(GPIO_CONTROL, 1, 0);
+            assert_eq!(Some(0x7), g.read16(GPIO_DIRECTION));
+            assert_eq!(Some(1), g.read16(GPIO_CONTROL));
+            assert_eq!(None, g.read16(0xCA));
+        }
+
+        #[test]
+        fn data_direction() {
+            let mut g = Gpio::new();
+            g.write16(GPIO_CONTROL, 1, 0);
+            g.write16(GPIO_DIRECTION, 0x5, 0);
+            g.write16(GPIO_DATA, 0xFF, 0);
+
+            assert_eq!(Some(0x5), g.read16(GPIO_DATA));
+        }
+
+        #[test]
+        fn solar() {
+            use crate::gamepak::solar::{LightSchedule, PIN_CLK, PIN_FLAG, PIN_RST};
+
+            let mut g = Gpio::new();
+            g.set_solar(Some(SolarSensor::new(LightSchedule::fixed(0xFE))));
+            g.write16(GPIO_CONTROL, 1, 0);
+            g.write16(GPIO_DIRECTION, 0x7, 0);
+
+            g.write16(GPIO_DATA, PIN_RST as u16, 0);
+            g.write16(GPIO_DATA, 0, 0);
+            assert_eq!(Some(0), g.read16(GPIO_DATA));
+            g.write16(GPIO_DATA, PIN_CLK as u16, 0);
+            assert_eq!(Some((PIN_FLAG | PIN_CLK) as u16), g.read16(GPIO_DATA));
+        }
+
+        #[test]
+        fn gyro_and_rumble() {
+            use crate::gamepak::gyro::{PIN_CLK, PIN_DATA, PIN_RESET};
+            use crate::gamepak::motion::{Motion, MotionScript};
+
+            let mut g = Gpio::new();
+            g.set_gyro(Some(Gyro::new(MotionScript::fixed(Motion::default()))));
+            g.set_rumble(true);
+            g.write16(GPIO_CONTROL, 1, 0);
+            g.write16(GPIO_DIRECTION, 0xB, 0);
+
+            g.write16(GPIO_DATA, PIN_RESET as u16, 0);
+            g.write16(GPIO_DATA, 0, 0);
+            let mut reading = 0;
+            for _ in 0..16 {
+                g.write16(GPIO_DATA, PIN_CLK as u16, 0);
+                g.write16(GPIO_DATA, 0, 0);
+                reading = (reading << 1) | ((g.read16(GPIO_DATA).unwrap() as u8 & PIN_DATA) >> 2) as u16;
+            }
+            assert_eq!(0x6C0, reading);
+            assert!(!g.is_rumbling());
+
+            g.write16(GPIO_DATA, PIN_RUMBLE as u16, 0);
+            assert!(g.is_rumbling());
+            g.write16(GPIO_DIRECTION, 0x3, 0);
+            assert!(!g.is_rumbling());
+        }
+
+        #[test]
+        fn rtc() {
+            let mut g = Gpio::new();
+            g.set_rtc(Some(Rtc::new(TimeSource::Fixed(DateTime::new(2004, 6, 1, 0, 0, 0)))));
+            g.write16(GPIO_CONTROL, 1, 0);
+            g.write16(GPIO_DIRECTION, 0x7, 0);
+
+            // Read the status register.
+            g.write16(GPIO_DATA, PIN_SCK as u16, 0);
+            g.write16(GPIO_DATA, (PIN_SCK | PIN_CS) as u16, 0);
+            for bit in (0..8).rev() {
+                let sio = ((0x63u8 >> bit) & 0x1) * PIN_SIO;
+                g.write16(GPIO_DATA, (PIN_CS | sio) as u16, 0);
+                g.write16(GPIO_DATA, (PIN_CS | PIN_SCK | sio) as u16, 0);
+            }
+
+            g.write16(GPIO_DIRECTION, 0x5, 0);
+            let mut status = 0;
+            for bit in 0..8 {
+                g.write16(GPIO_DATA, PIN_CS as u16, 0);
+                g.write16(GPIO_DATA, (PIN_CS | PIN_SCK) as u16, 0);
+                status |= ((g.read16(GPIO_DATA).unwrap() as u8 & PIN_SIO) >> 1) << bit;
+            }
+            assert_eq!(0x40, status);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use super::motion::{MotionInput, MotionScript};
+
+// The gyro takes the first three GPIO pins, and the rumble motor the last.
+pub const PIN_RESET: u8 = 0x1;
+pub const PIN_CLK: u8 = 0x2;
+pub const PIN_DATA: u8 = 0x4;
+pub const PIN_RUMBLE: u8 = 0x8;
+
+// Readings are 12 bits, and sit around this when the GBA is still.
+const CENTER: i32 = 0x6C0;
+const MAX: i32 = 0xFFF;
+
+// The gyro on WarioWare: Twisted! cartridges. Raising RESET takes a
+// reading, which is then shifted out most significant bit first, a bit on
+// each falling edge of CLK. The reading is 16 bits wide, so the first four
+// bits are always 0.
+pub struct Gyro {
+    input: MotionInput,
+
+    clk: bool,
+    reading: u16,
+    data: bool,
+}
+
+impl Gyro {
+    pub fn new(script: MotionScript) -> Gyro {
+        Gyro {
+            input: MotionInput::new(script),
+
+            clk: false,
+            reading: 0,
+            data: false,
+        }
+    }
+
+    pub fn input(&self) -> &MotionInput {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut MotionInput {
+        &mut self.input
+    }
+
+    // Takes the pins the GBA drives.
+    pub fn write(&mut self, pins: u8, cycle: u64) {
+        let clk = pins & PIN_CLK != 0;
+        if pins & PIN_RESET != 0 {
+            let m = self.input.sample(cycle);
+            self.reading = (CENTER + m.rotation as i32).clamp(0, MAX) as u16;
+        }
+        if self.clk && !clk {
+            self.data = self.reading & 0x8000 != 0;
+            self.reading <<= 1;
+        }
+        self.clk = clk;
+    }
+
+    // Returns the pins the gyro drives.
+    pub fn read(&self) -> u8 {
+        match self.data {
+            true => PIN_DATA,
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod gyro {
+        use super::super::*;
+        use crate::gamepak::motion::Motion;
+
+        fn measure(g: &mut Gyro) -> u16 {
+            g.write(PIN_RESET, 0);
+            g.write(0, 0);
+            let mut reading = 0;
+            for _ in 0..16 {
+                g.write(PIN_CLK, 0);
+                g.write(0, 0);
+                reading = (reading << 1) | ((g.read() & PIN_DATA) >> 2) as u16;
+            }
+            reading
+        }
+
+        #[test]
+        fn still() {
+            let mut g = Gyro::new(MotionScript::fixed(Motion::default()));
+            assert_eq!(0x6C0, measure(&mut g));
+        }
+
+        #[test]
+        fn rotated() {
+            let mut g = Gyro::new(MotionScript::fixed(Motion::default()));
+            g.input_mut().set(Motion { tilt_x: 0, tilt_y: 0, rotation: -0x100 });
+            assert_eq!(0x5C0, measure(&mut g));
+
+            g.input_mut().set(Motion { tilt_x: 0, tilt_y: 0, rotation: 0x7FFF });
+            assert_eq!(0xFFF, measure(&mut g));
+        }
+    }
+}
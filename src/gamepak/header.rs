@@ -3,19 +3,91 @@ use std::fmt;
 const MAGIC_NUMBER: u8 = 0x96;
 
 const HEADER_SIZE: usize = 192;
+const LOGO_SIZE: usize = 156;
 const GAME_TITLE_SIZE: usize = 12;
 const GAME_CODE_SIZE: usize = 4;
 const MAKER_CODE_SIZE: usize = 2;
 
+const H_ENTRY_POINT: usize = 0x000;
+const H_LOGO: usize = 0x004;
 const H_GAME_TITLE: usize = 0x0A0;
 const H_GAME_CODE: usize = 0x0AC;
 const H_MAKER_CODE: usize = 0x0B0;
 const H_MAGIC: usize = 0x0B2;
+const H_MAIN_UNIT_CODE: usize = 0x0B3;
+const H_DEVICE_TYPE: usize = 0x0B4;
+const H_SOFTWARE_VERSION: usize = 0x0BC;
+const H_COMPLEMENT_CHECK: usize = 0x0BD;
+
+// Multiboot images carry more entry points after the cartridge header, for
+// when they are sent over the link cable.
+const MULTIBOOT_HEADER_SIZE: usize = 0x0E4;
+const H_RAM_ENTRY_POINT: usize = 0x0C0;
+const H_BOOT_MODE: usize = 0x0C4;
+const H_SLAVE_ID: usize = 0x0C5;
+const H_JOYBUS_ENTRY_POINT: usize = 0x0E0;
+
+const ROM_BASE: u32 = 0x08_00_00_00;
+const MULTIBOOT_BASE: u32 = 0x02_00_00_00;
+
+// The BIOS refuses to boot a cartridge unless its header has this exact
+// logo, and the complement check over the rest of the header.
+pub const NINTENDO_LOGO: [u8; LOGO_SIZE] = [
+    0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A, 0x84, 0xE4, 0x09, 0xAD,
+    0x11, 0x24, 0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21, 0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09, 0xCE, 0x20,
+    0x10, 0x46, 0x4A, 0x4A, 0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82, 0xE3, 0xCE, 0xBF,
+    0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B, 0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0, 0x13, 0x72, 0xA7, 0xFC,
+    0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61, 0x58, 0x97, 0xA3, 0x27, 0xFC, 0x03, 0x98, 0x76,
+    0x23, 0x1D, 0xC7, 0x61, 0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38, 0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD,
+    0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97, 0xFB, 0xC0, 0x85, 0x60, 0xD6, 0x80, 0x25,
+    0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2, 0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E, 0x03, 0x44,
+    0x78, 0x00, 0x90, 0xCB, 0x88, 0x11, 0x3A, 0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF,
+    0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A, 0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07,
+];
+
+// Something wrong with a header that would stop real hardware from booting
+// the cartridge, though it can still be run here.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum HeaderProblem {
+    BadLogo,
+    BadFixedValue { actual: u8 },
+    BadComplementCheck { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for HeaderProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderProblem::BadLogo => write!(f, "logo does not match the nintendo logo"),
+            HeaderProblem::BadFixedValue { actual } => {
+                write!(f, "fixed value is {:02x}, should be {:02x}", actual, MAGIC_NUMBER)
+            }
+            HeaderProblem::BadComplementCheck { expected, actual } => {
+                write!(f, "complement check is {:02x}, should be {:02x}", actual, expected)
+            }
+        }
+    }
+}
 
 pub struct Header {
+    entry_point: u32,
+    logo: [u8; LOGO_SIZE],
     game_title: String,
     game_code: String,
     maker_code: String,
+    main_unit_code: u8,
+    device_type: u8,
+    software_version: u8,
+    complement_check: u8,
+    expected_complement_check: u8,
+    multiboot: Option<MultibootHeader>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct MultibootHeader {
+    pub ram_entry_point: u32,
+    pub boot_mode: u8,
+    pub slave_id: u8,
+    pub joybus_entry_point: u32,
 }
 
 impl Header {
@@ -25,21 +97,53 @@ impl Header {
         }
 
         if data[H_MAGIC] != MAGIC_NUMBER {
-            return Err(format!("magic number is invalid"));
+            return Err(String::from("magic number is invalid"));
         }
 
+        let mut logo = [0; LOGO_SIZE];
+        logo.copy_from_slice(&data[H_LOGO..H_LOGO + LOGO_SIZE]);
+
         Ok(
             Header {
-                game_title: String::from_utf8_lossy(data[H_GAME_TITLE..H_GAME_TITLE + GAME_TITLE_SIZE].as_ref())
-                    .to_string(),
-                game_code: String::from_utf8_lossy(data[H_GAME_CODE..H_GAME_CODE + GAME_CODE_SIZE].as_ref())
-                    .to_string(),
-                maker_code: String::from_utf8_lossy(data[H_MAKER_CODE..H_MAKER_CODE + MAKER_CODE_SIZE].as_ref())
-                    .to_string(),
+                entry_point: read_u32(data, H_ENTRY_POINT),
+                logo,
+                game_title: read_string(data, H_GAME_TITLE, GAME_TITLE_SIZE),
+                game_code: read_string(data, H_GAME_CODE, GAME_CODE_SIZE),
+                maker_code: read_string(data, H_MAKER_CODE, MAKER_CODE_SIZE),
+                main_unit_code: data[H_MAIN_UNIT_CODE],
+                device_type: data[H_DEVICE_TYPE],
+                software_version: data[H_SOFTWARE_VERSION],
+                complement_check: data[H_COMPLEMENT_CHECK],
+                expected_complement_check: complement_check(data),
+                multiboot: None,
             }
         )
     }
 
+    // Loads the header of a multiboot image, which also has the entry points
+    // used when it is sent over the link cable. Nothing in the image marks
+    // it as multiboot, and ordinary cartridges often have code where the
+    // extra fields would be, so the caller has to know what it is loading.
+    pub fn load_multiboot(data: &[u8]) -> Result<Header, String> {
+        let mut h = Header::load(data)?;
+        h.multiboot = Some(MultibootHeader::load(data)?);
+        Ok(h)
+    }
+
+    // The ARM opcode run first, which is normally a branch over the header.
+    pub fn entry_point(&self) -> u32 {
+        self.entry_point
+    }
+
+    // Where the entry point branches to, if it is a branch.
+    pub fn entry_address(&self) -> Option<u32> {
+        branch_target(self.entry_point, ROM_BASE + H_ENTRY_POINT as u32)
+    }
+
+    pub fn logo(&self) -> &[u8] {
+        &self.logo
+    }
+
     pub fn game_title(&self) -> &str {
         &self.game_title
     }
@@ -51,10 +155,309 @@ impl Header {
     pub fn maker_code(&self) -> &str {
         &self.maker_code
     }
+
+    pub fn main_unit_code(&self) -> u8 {
+        self.main_unit_code
+    }
+
+    pub fn device_type(&self) -> u8 {
+        self.device_type
+    }
+
+    pub fn software_version(&self) -> u8 {
+        self.software_version
+    }
+
+    pub fn complement_check(&self) -> u8 {
+        self.complement_check
+    }
+
+    pub fn multiboot(&self) -> Option<&MultibootHeader> {
+        self.multiboot.as_ref()
+    }
+
+    // Checks the header the way the BIOS does before booting.
+    pub fn validate(&self) -> Vec<HeaderProblem> {
+        let mut problems = Vec::new();
+
+        if self.logo != NINTENDO_LOGO {
+            problems.push(HeaderProblem::BadLogo);
+        }
+        if self.complement_check != self.expected_complement_check {
+            problems.push(HeaderProblem::BadComplementCheck {
+                expected: self.expected_complement_check,
+                actual: self.complement_check,
+            });
+        }
+
+        problems
+    }
+}
+
+// Computes the complement check over the header bytes from the title up to
+// the check itself.
+pub fn complement_check(data: &[u8]) -> u8 {
+    let sum = data[H_GAME_TITLE..H_COMPLEMENT_CHECK]
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b));
+    0u8.wrapping_sub(sum).wrapping_sub(0x19)
+}
+
+// Makes a ROM bootable on hardware by writing the logo, the fixed value and
+// the complement check into its header, like gbafix does for homebrew.
+// Returns the problems the header had before.
+pub fn fix(data: &mut [u8]) -> Result<Vec<HeaderProblem>, String> {
+    if data.len() < HEADER_SIZE {
+        return Err(format!("header is too small ({})", data.len()));
+    }
+
+    let mut problems = Vec::new();
+
+    if data[H_LOGO..H_LOGO + LOGO_SIZE] != NINTENDO_LOGO[..] {
+        problems.push(HeaderProblem::BadLogo);
+        data[H_LOGO..H_LOGO + LOGO_SIZE].copy_from_slice(&NINTENDO_LOGO);
+    }
+
+    if data[H_MAGIC] != MAGIC_NUMBER {
+        problems.push(HeaderProblem::BadFixedValue { actual: data[H_MAGIC] });
+        data[H_MAGIC] = MAGIC_NUMBER;
+    }
+
+    let expected = complement_check(data);
+    if data[H_COMPLEMENT_CHECK] != expected {
+        problems.push(HeaderProblem::BadComplementCheck {
+            expected,
+            actual: data[H_COMPLEMENT_CHECK],
+        });
+        data[H_COMPLEMENT_CHECK] = expected;
+    }
+
+    Ok(problems)
+}
+
+impl MultibootHeader {
+    fn load(data: &[u8]) -> Result<MultibootHeader, String> {
+        if data.len() < MULTIBOOT_HEADER_SIZE {
+            return Err(format!("multiboot header is too small ({})", data.len()));
+        }
+
+        Ok(MultibootHeader {
+            ram_entry_point: read_u32(data, H_RAM_ENTRY_POINT),
+            boot_mode: data[H_BOOT_MODE],
+            slave_id: data[H_SLAVE_ID],
+            joybus_entry_point: read_u32(data, H_JOYBUS_ENTRY_POINT),
+        })
+    }
+
+    pub fn ram_entry_address(&self) -> Option<u32> {
+        branch_target(self.ram_entry_point, MULTIBOOT_BASE + H_RAM_ENTRY_POINT as u32)
+    }
+
+    pub fn joybus_entry_address(&self) -> Option<u32> {
+        branch_target(self.joybus_entry_point, MULTIBOOT_BASE + H_JOYBUS_ENTRY_POINT as u32)
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+// Text fields are padded out with zeroes, which aren't part of the text.
+fn read_string(data: &[u8], offset: usize, size: usize) -> String {
+    String::from_utf8_lossy(&data[offset..offset + size])
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+// Returns where an unconditional ARM branch at addr goes to.
+fn branch_target(opcode: u32, addr: u32) -> Option<u32> {
+    if opcode & 0xFF_00_00_00 != 0xEA_00_00_00 {
+        return None;
+    }
+
+    let offset = ((opcode << 8) as i32 >> 6) as u32;
+    Some(addr.wrapping_add(8).wrapping_add(offset))
 }
 
 impl std::fmt::Debug for Header {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "header")
+        match self.entry_address() {
+            Some(addr) => writeln!(f, "entry point:      {:08x} (b {:08x})", self.entry_point, addr)?,
+            None => writeln!(f, "entry point:      {:08x}", self.entry_point)?,
+        }
+        write!(f, "logo:            ")?;
+        for (idx, b) in self.logo.iter().enumerate() {
+            if idx > 0 && idx % 26 == 0 {
+                write!(f, "\n                 ")?;
+            }
+            write!(f, " {:02x}", b)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "game title:       {}", self.game_title)?;
+        writeln!(f, "game code:        {}", self.game_code)?;
+        writeln!(f, "maker code:       {}", self.maker_code)?;
+        writeln!(f, "main unit code:   {:02x}", self.main_unit_code)?;
+        writeln!(f, "device type:      {:02x}", self.device_type)?;
+        writeln!(f, "software version: {:02x}", self.software_version)?;
+        write!(f, "complement check: {:02x}", self.complement_check)?;
+
+        if let Some(mb) = self.multiboot.as_ref() {
+            writeln!(f)?;
+            writeln!(f, "ram entry point:  {:08x}", mb.ram_entry_point)?;
+            writeln!(f, "boot mode:        {:02x}", mb.boot_mode)?;
+            writeln!(f, "slave id:         {:02x}", mb.slave_id)?;
+            write!(f, "joybus entry:     {:08x}", mb.joybus_entry_point)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod header {
+        use super::super::*;
+
+        fn data() -> Vec<u8> {
+            let mut data = vec![0; 0x100];
+            data[H_ENTRY_POINT..H_ENTRY_POINT + 4].copy_from_slice(&0xEA_00_00_2E_u32.to_le_bytes());
+            data[H_LOGO] = 0x24;
+            data[H_GAME_TITLE..H_GAME_TITLE + 6].copy_from_slice(b"GABBA!");
+            data[H_GAME_CODE..H_GAME_CODE + 4].copy_from_slice(b"AGBE");
+            data[H_MAKER_CODE..H_MAKER_CODE + 2].copy_from_slice(b"01");
+            data[H_MAGIC] = MAGIC_NUMBER;
+            data[H_DEVICE_TYPE] = 0x80;
+            data[H_SOFTWARE_VERSION] = 0x01;
+            data[H_COMPLEMENT_CHECK] = 0x5A;
+            data
+        }
+
+        #[test]
+        fn load() {
+            let h = Header::load(&data()).unwrap();
+
+            assert_eq!(0xEA_00_00_2E, h.entry_point());
+            assert_eq!(Some(0x08_00_00_C0), h.entry_address());
+            assert_eq!(0x24, h.logo()[0]);
+            assert_eq!(LOGO_SIZE, h.logo().len());
+            assert_eq!("GABBA!", h.game_title());
+            assert_eq!("AGBE", h.game_code());
+            assert_eq!("01", h.maker_code());
+            assert_eq!(0x00, h.main_unit_code());
+            assert_eq!(0x80, h.device_type());
+            assert_eq!(0x01, h.software_version());
+            assert_eq!(0x5A, h.complement_check());
+            assert!(h.multiboot().is_none());
+        }
+
+        #[test]
+        fn load_too_small() {
+            assert!(Header::load(&data()[..HEADER_SIZE - 1]).is_err());
+        }
+
+        #[test]
+        fn load_bad_magic() {
+            let mut data = data();
+            data[H_MAGIC] = 0;
+            assert_eq!(Some(String::from("magic number is invalid")), Header::load(&data).err());
+        }
+
+        #[test]
+        fn load_multiboot() {
+            let mut data = data();
+            data[H_RAM_ENTRY_POINT..H_RAM_ENTRY_POINT + 4].copy_from_slice(&0xEA_00_00_06_u32.to_le_bytes());
+            data[H_BOOT_MODE] = 0x03;
+            data[H_SLAVE_ID] = 0x01;
+            data[H_JOYBUS_ENTRY_POINT..H_JOYBUS_ENTRY_POINT + 4].copy_from_slice(&0xEA_FF_FF_FE_u32.to_le_bytes());
+
+            let h = Header::load_multiboot(&data).unwrap();
+            let mb = h.multiboot().unwrap();
+            assert_eq!(0x03, mb.boot_mode);
+            assert_eq!(0x01, mb.slave_id);
+            assert_eq!(Some(0x02_00_00_E0), mb.ram_entry_address());
+            assert_eq!(Some(0x02_00_00_E0), mb.joybus_entry_address());
+        }
+
+        #[test]
+        fn load_cart_with_branch_after_header() {
+            // Cartridge startup code often starts with a branch right after
+            // the header, where a multiboot image has its RAM entry point.
+            let mut data = data();
+            data[H_RAM_ENTRY_POINT..H_RAM_ENTRY_POINT + 4].copy_from_slice(&0xEA_00_00_06_u32.to_le_bytes());
+
+            assert!(Header::load(&data).unwrap().multiboot().is_none());
+        }
+
+        #[test]
+        fn load_multiboot_too_small() {
+            assert_eq!(
+                Some(String::from("multiboot header is too small (192)")),
+                Header::load_multiboot(&data()[..HEADER_SIZE]).err(),
+            );
+        }
+
+        #[test]
+        fn branch_target_backwards() {
+            assert_eq!(Some(0x08_00_00_00), branch_target(0xEA_FF_FF_FC, 0x08_00_00_08));
+            assert_eq!(None, branch_target(0xE3_A0_00_12, 0x08_00_00_00));
+        }
+
+        #[test]
+        fn validate() {
+            let h = Header::load(&data()).unwrap();
+            assert_eq!(
+                vec![
+                    HeaderProblem::BadLogo,
+                    HeaderProblem::BadComplementCheck { expected: 0xF2, actual: 0x5A },
+                ],
+                h.validate(),
+            );
+        }
+
+        #[test]
+        fn complement_check_sum() {
+            let mut data = vec![0; HEADER_SIZE];
+            data[H_MAGIC] = MAGIC_NUMBER;
+            assert_eq!(0x51, complement_check(&data));
+        }
+
+        #[test]
+        fn fix_header() {
+            let mut data = data();
+            let problems = fix(&mut data).unwrap();
+            assert_eq!(2, problems.len());
+
+            let h = Header::load(&data).unwrap();
+            assert_eq!(NINTENDO_LOGO[..], h.logo()[..]);
+            assert_eq!(0xF2, h.complement_check());
+            assert!(h.validate().is_empty());
+            assert!(fix(&mut data).unwrap().is_empty());
+        }
+
+        #[test]
+        fn fix_fixed_value() {
+            let mut data = data();
+            fix(&mut data).unwrap();
+            data[H_MAGIC] = 0;
+
+            let problems = fix(&mut data).unwrap();
+            assert_eq!(vec![HeaderProblem::BadFixedValue { actual: 0 }], problems);
+            assert_eq!(MAGIC_NUMBER, data[H_MAGIC]);
+            assert!(Header::load(&data).is_ok());
+        }
+
+        #[test]
+        fn fix_too_small() {
+            assert!(fix(&mut [0; HEADER_SIZE - 1]).is_err());
+        }
+
+        #[test]
+        fn debug() {
+            let dump = format!("{:?}", Header::load(&data()).unwrap());
+
+            assert!(dump.starts_with("entry point:      ea00002e (b 080000c0)\nlogo:             24 00"));
+            assert!(dump.contains("\ngame title:       GABBA!\n"));
+            assert!(dump.ends_with("complement check: 5a"));
+        }
     }
-}
\ No newline at end of file
+}
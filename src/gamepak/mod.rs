@@ -1,32 +1,117 @@
 use std::fs;
 use std::fmt;
-use std::error::Error;
 use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
 
+pub mod backup;
+pub mod db;
+pub mod eeprom;
+pub mod flash;
+pub mod gpio;
+pub mod gyro;
 pub mod header;
+pub mod motion;
+pub mod rtc;
+pub mod save;
+pub mod schedule;
+pub mod solar;
+pub mod sram;
+pub mod tilt;
 
+pub use backup::{Backup, BackupType};
+pub use eeprom::Eeprom;
+pub use flash::FlashChip;
+pub use gpio::Gpio;
+pub use gyro::Gyro;
 pub use header::Header;
+pub use motion::{Motion, MotionInput, MotionScript};
+pub use rtc::{Rtc, TimeSource};
+pub use solar::{LightSchedule, SolarSensor};
+pub use tilt::TiltSensor;
+
+// Hardware on the cartridge that keeps time does it by the system clock.
+pub const CYCLES_PER_SECOND: u64 = 1 << 24;
 
 pub struct GamePak {
     header: Header,
     data: Vec<u8>,
+
+    library_id: Option<backup::LibraryId>,
+    backup_type: BackupType,
+    flash_chip: Option<FlashChip>,
+
+    game: Option<&'static db::Game>,
+    rtc: bool,
+    time_source: TimeSource,
+    solar: bool,
+    light: LightSchedule,
+    tilt: bool,
+    gyro: bool,
+    rumble: bool,
+    motion: MotionScript,
+
+    path: Option<PathBuf>,
+    save_dir: Option<PathBuf>,
 }
 
 impl GamePak {
+    // Images named like rom_mb.gba are taken to be multiboot images, which is
+    // how they are usually distributed.
     pub fn load_from_file(path: &str) -> Result<GamePak, String> {
-        match fs::read(path) {
-            Ok(data) => GamePak::load(data),
-            Err(e) => Err(String::from(e.description())),
-        }
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        let mut gp = match is_multiboot_path(Path::new(path)) {
+            true => GamePak::load_multiboot(data)?,
+            false => GamePak::load(data)?,
+        };
+        gp.path = Some(PathBuf::from(path));
+        Ok(gp)
     }
 
     pub fn load(data: Vec<u8>) -> Result<GamePak, String> {
         let h = Header::load(&data)?;
+        Ok(GamePak::with_header(h, data))
+    }
+
+    pub fn load_multiboot(data: Vec<u8>) -> Result<GamePak, String> {
+        let h = Header::load_multiboot(&data)?;
+        Ok(GamePak::with_header(h, data))
+    }
+
+    fn with_header(h: Header, data: Vec<u8>) -> GamePak {
+        let library_id = backup::detect(&data);
+        let backup_type = match library_id.as_ref() {
+            Some(id) => id.backup_type,
+            None => BackupType::None,
+        };
 
-        Ok(GamePak {
+        let game = db::lookup(h.game_code());
+        let rtc = game.is_some_and(|g| g.has(db::Feature::Rtc));
+        let solar = game.is_some_and(|g| g.has(db::Feature::SolarSensor));
+        let tilt = game.is_some_and(|g| g.has(db::Feature::Tilt));
+        let gyro = game.is_some_and(|g| g.has(db::Feature::Gyro));
+        let rumble = game.is_some_and(|g| g.has(db::Feature::Rumble));
+
+        GamePak {
             header: h,
             data,
-        })
+
+            library_id,
+            backup_type,
+            flash_chip: None,
+
+            game,
+            rtc,
+            time_source: TimeSource::Host,
+            solar,
+            light: LightSchedule::fixed(0),
+            tilt,
+            gyro,
+            rumble,
+            motion: MotionScript::fixed(Motion::default()),
+
+            path: None,
+            save_dir: None,
+        }
     }
 
     pub fn header(&self) -> &Header {
@@ -36,11 +121,289 @@ impl GamePak {
     pub fn data(&self) -> &[u8] {
         &self.data
     }
+
+    // The backup library found in the ROM, if the game was built with one.
+    pub fn library_id(&self) -> Option<&backup::LibraryId> {
+        self.library_id.as_ref()
+    }
+
+    pub fn backup_type(&self) -> BackupType {
+        self.backup_type
+    }
+
+    // Overrides the detected backup type, for games that were built without
+    // the SDK libraries or detect wrong.
+    pub fn set_backup_type(&mut self, backup_type: BackupType) {
+        self.backup_type = backup_type;
+    }
+
+    // Picks which flash chip the cartridge has, for games that only work
+    // with some of them.
+    pub fn set_flash_chip(&mut self, chip: FlashChip) {
+        self.flash_chip = Some(chip);
+    }
+
+    // The entry for the game in the game database, if it has one.
+    pub fn game(&self) -> Option<&'static db::Game> {
+        self.game
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.rtc
+    }
+
+    // Overrides whether the cartridge has a real-time clock, for games
+    // missing from the game database.
+    pub fn set_rtc(&mut self, rtc: bool) {
+        self.rtc = rtc;
+    }
+
+    pub fn time_source(&self) -> TimeSource {
+        self.time_source
+    }
+
+    pub fn set_time_source(&mut self, source: TimeSource) {
+        self.time_source = source;
+    }
+
+    pub fn has_solar_sensor(&self) -> bool {
+        self.solar
+    }
+
+    // Overrides whether the cartridge has a light sensor.
+    pub fn set_solar_sensor(&mut self, solar: bool) {
+        self.solar = solar;
+    }
+
+    // Sets how bright it is for the light sensor, which starts off dark.
+    pub fn set_light(&mut self, light: LightSchedule) {
+        self.light = light;
+    }
+
+    pub fn has_tilt_sensor(&self) -> bool {
+        self.tilt
+    }
+
+    // Overrides whether the cartridge has an accelerometer.
+    pub fn set_tilt_sensor(&mut self, tilt: bool) {
+        self.tilt = tilt;
+    }
+
+    pub fn has_gyro(&self) -> bool {
+        self.gyro
+    }
+
+    // Overrides whether the cartridge has a gyro.
+    pub fn set_gyro(&mut self, gyro: bool) {
+        self.gyro = gyro;
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        self.rumble
+    }
+
+    // Overrides whether the cartridge has a rumble motor.
+    pub fn set_rumble(&mut self, rumble: bool) {
+        self.rumble = rumble;
+    }
+
+    // Sets how the cartridge is moved for the motion sensors, which start
+    // off held flat and still.
+    pub fn set_motion(&mut self, motion: MotionScript) {
+        self.motion = motion;
+    }
+
+    // Creates the GPIO port with the hardware wired to it, if the cartridge
+    // has any.
+    pub fn new_gpio(&self) -> Option<Gpio> {
+        if !self.rtc && !self.solar && !self.gyro && !self.rumble {
+            return None;
+        }
+
+        let mut gpio = Gpio::new();
+        if self.rtc {
+            gpio.set_rtc(Some(Rtc::new(self.time_source)));
+        }
+        if self.solar {
+            gpio.set_solar(Some(SolarSensor::new(self.light.clone())));
+        }
+        if self.gyro {
+            gpio.set_gyro(Some(Gyro::new(self.motion.clone())));
+        }
+        gpio.set_rumble(self.rumble);
+        Some(gpio)
+    }
+
+    // Creates the accelerometer, if the cartridge has one.
+    pub fn new_tilt(&self) -> Option<TiltSensor> {
+        match self.tilt {
+            true => Some(TiltSensor::new(self.motion.clone())),
+            false => None,
+        }
+    }
+
+    // Keeps saves in dir instead of next to the ROM.
+    pub fn set_save_dir(&mut self, dir: &Path) {
+        self.save_dir = Some(dir.to_path_buf());
+    }
+
+    // Returns where the cartridge's save lives, which is the ROM's name with
+    // a .sav extension, next to the ROM or in the save directory. ROMs that
+    // weren't loaded from a file have nowhere to keep one.
+    pub fn save_path(&self) -> Option<PathBuf> {
+        let rom = self.path.as_ref()?;
+        let save = rom.with_extension("sav");
+        match self.save_dir.as_ref() {
+            Some(dir) => Some(dir.join(save.file_name()?)),
+            None => Some(save),
+        }
+    }
+
+    // Creates blank save memory of the type the cartridge uses.
+    pub fn new_backup(&self) -> Backup {
+        Backup::new(self.backup_type, self.flash_chip)
+    }
 }
 
 impl fmt::Debug for GamePak {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "GamePak{{ data: {}b }}", self.data.len())
+        write!(f, "GamePak{{ data: {}b, backup: {} }}", self.data.len(), self.backup_type)
     }
 }
 
+fn is_multiboot_path(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.to_ascii_lowercase().ends_with("_mb"))
+}
+
+#[cfg(test)]
+mod tests {
+    mod gamepak {
+        use super::super::*;
+
+        fn rom() -> Vec<u8> {
+            let mut data = vec![0; 0x400];
+            data[0xB2] = 0x96;
+            data
+        }
+
+        #[test]
+        fn load_detects_backup() {
+            let mut data = rom();
+            data[0x300..0x30C].copy_from_slice(b"FLASH1M_V103");
+
+            let gp = GamePak::load(data).unwrap();
+            assert_eq!(BackupType::Flash128K, gp.backup_type());
+            assert_eq!("103", gp.library_id().unwrap().version);
+        }
+
+        #[test]
+        fn load_multiboot() {
+            let gp = GamePak::load(rom()).unwrap();
+            assert!(gp.header().multiboot().is_none());
+
+            let gp = GamePak::load_multiboot(rom()).unwrap();
+            assert!(gp.header().multiboot().is_some());
+        }
+
+        #[test]
+        fn multiboot_path() {
+            assert!(is_multiboot_path(Path::new("roms/240pee_mb.gba")));
+            assert!(is_multiboot_path(Path::new("DEMO_MB.GBA")));
+            assert!(!is_multiboot_path(Path::new("roms/240pee.gba")));
+            assert!(!is_multiboot_path(Path::new("roms_mb/game.gba")));
+        }
+
+        #[test]
+        fn load_without_backup() {
+            let gp = GamePak::load(rom()).unwrap();
+            assert_eq!(BackupType::None, gp.backup_type());
+            assert!(gp.library_id().is_none());
+        }
+
+        #[test]
+        fn new_backup_flash_chip() {
+            let mut gp = GamePak::load(rom()).unwrap();
+            gp.set_backup_type(BackupType::Flash64K);
+            gp.set_flash_chip(FlashChip::Atmel);
+
+            match gp.new_backup() {
+                Backup::Flash(f) => assert_eq!(FlashChip::Atmel, f.chip()),
+                _ => panic!("expected flash"),
+            }
+        }
+
+        #[test]
+        fn rtc_from_game_code() {
+            let mut data = rom();
+            data[0xAC..0xB0].copy_from_slice(b"AXVE");
+            let gp = GamePak::load(data).unwrap();
+
+            assert_eq!("Pokemon Ruby", gp.game().unwrap().title);
+            assert!(gp.has_rtc());
+            assert!(gp.new_gpio().unwrap().rtc().is_some());
+        }
+
+        #[test]
+        fn solar_from_game_code() {
+            let mut data = rom();
+            data[0xAC..0xB0].copy_from_slice(b"U3IE");
+            let mut gp = GamePak::load(data).unwrap();
+            gp.set_light(LightSchedule::fixed(100));
+
+            assert!(gp.has_solar_sensor());
+            let gpio = gp.new_gpio().unwrap();
+            assert!(gpio.rtc().is_some());
+            assert_eq!(&LightSchedule::fixed(100), gpio.solar().unwrap().light());
+        }
+
+        #[test]
+        fn motion_from_game_code() {
+            let mut data = rom();
+            data[0xAC..0xB0].copy_from_slice(b"RZWE");
+            let gp = GamePak::load(data).unwrap();
+
+            assert!(gp.has_gyro());
+            assert!(gp.new_tilt().is_none());
+            let gpio = gp.new_gpio().unwrap();
+            assert!(gpio.gyro().is_some());
+            assert!(gpio.has_rumble());
+            assert!(gpio.rtc().is_none());
+
+            let mut data = rom();
+            data[0xAC..0xB0].copy_from_slice(b"KYGE");
+            let gp = GamePak::load(data).unwrap();
+            assert!(gp.new_tilt().is_some());
+            assert!(gp.new_gpio().is_none());
+        }
+
+        #[test]
+        fn no_gpio() {
+            let mut gp = GamePak::load(rom()).unwrap();
+            assert!(gp.new_gpio().is_none());
+
+            gp.set_rtc(true);
+            assert!(gp.new_gpio().is_some());
+        }
+
+        #[test]
+        fn save_path() {
+            let mut gp = GamePak::load(rom()).unwrap();
+            assert_eq!(None, gp.save_path());
+
+            gp.path = Some(PathBuf::from("roms/game.gba"));
+            assert_eq!(Some(PathBuf::from("roms/game.sav")), gp.save_path());
+
+            gp.set_save_dir(Path::new("saves"));
+            assert_eq!(Some(PathBuf::from("saves/game.sav")), gp.save_path());
+        }
+
+        #[test]
+        fn set_backup_type() {
+            let mut gp = GamePak::load(rom()).unwrap();
+            gp.set_backup_type(BackupType::Sram);
+            assert_eq!(BackupType::Sram, gp.backup_type());
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::io;
+use std::io::Write;
+
+use super::schedule::{parse_time, Keyframe, Schedule};
+
+// A reading of the motion sensors, as signed offsets from what they read at
+// rest. Tilt is along the two axes of the screen, and rotation is around
+// the axis through it.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Motion {
+    pub tilt_x: i16,
+    pub tilt_y: i16,
+    pub rotation: i16,
+}
+
+impl Keyframe for Motion {
+    fn lerp(from: Motion, to: Motion, t: f64) -> Motion {
+        Motion {
+            tilt_x: i16::lerp(from.tilt_x, to.tilt_x, t),
+            tilt_y: i16::lerp(from.tilt_y, to.tilt_y, t),
+            rotation: i16::lerp(from.rotation, to.rotation, t),
+        }
+    }
+}
+
+// Motion over a run, as a schedule of readings.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MotionScript {
+    readings: Schedule<Motion>,
+}
+
+impl MotionScript {
+    pub fn fixed(motion: Motion) -> MotionScript {
+        MotionScript { readings: Schedule::fixed(motion) }
+    }
+
+    // Parses a script with a point on each line, written as the time and
+    // then the x tilt, y tilt and rotation. Times are in seconds into the
+    // run, or in cycles with a c after them.
+    pub fn parse(script: &str) -> Result<MotionScript, String> {
+        let readings = Schedule::parse_script(script, "motion", parse_point)?;
+        Ok(MotionScript { readings })
+    }
+
+    // Writes the script out in the form parse reads, with times in cycles
+    // so it plays back exactly.
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "# cycle tilt_x tilt_y rotation")?;
+        for (cycle, m) in self.readings.points() {
+            writeln!(w, "{}c {} {} {}", cycle, m.tilt_x, m.tilt_y, m.rotation)?;
+        }
+        Ok(())
+    }
+
+    // Returns the reading cycle cycles into the run.
+    pub fn motion(&self, cycle: u64) -> Motion {
+        self.readings.at(cycle)
+    }
+}
+
+fn parse_point(line: &str) -> Result<(u64, Motion), String> {
+    let bad = || format!("bad motion script line {:?}, expected <time> <tilt_x> <tilt_y> <rotation>", line);
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 4 {
+        return Err(bad());
+    }
+
+    let cycle = parse_time(fields[0]).map_err(|_| bad())?;
+    let axis = |f: &str| f.parse::<i16>().map_err(|_| bad());
+    Ok((cycle, Motion {
+        tilt_x: axis(fields[1])?,
+        tilt_y: axis(fields[2])?,
+        rotation: axis(fields[3])?,
+    }))
+}
+
+// Where the motion sensors get their readings. Readings follow a script,
+// or are set as they come in from a real device. Every reading the game
+// takes can be recorded, to play the run back later with the same motion.
+pub struct MotionInput {
+    script: MotionScript,
+    recording: Option<Vec<(u64, Motion)>>,
+}
+
+impl MotionInput {
+    pub fn new(script: MotionScript) -> MotionInput {
+        MotionInput {
+            script,
+            recording: None,
+        }
+    }
+
+    // Holds the sensors at a reading until it is set again.
+    pub fn set(&mut self, motion: Motion) {
+        self.script = MotionScript::fixed(motion);
+    }
+
+    pub fn set_script(&mut self, script: MotionScript) {
+        self.script = script;
+    }
+
+    // Takes a reading for the sensors, cycle cycles into the run.
+    pub fn sample(&mut self, cycle: u64) -> Motion {
+        let motion = self.script.motion(cycle);
+        if let Some(r) = self.recording.as_mut() {
+            r.push((cycle, motion));
+        }
+        motion
+    }
+
+    pub fn record(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    // Returns the readings taken since recording started, as a script that
+    // plays them back.
+    pub fn recording(&self) -> Option<MotionScript> {
+        let r = self.recording.as_ref()?;
+        match r.is_empty() {
+            true => Some(MotionScript::fixed(Motion::default())),
+            false => Some(MotionScript { readings: Schedule::from_points(r.clone(), "motion").ok()? }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod motion_script {
+        use super::super::*;
+        use crate::gamepak::CYCLES_PER_SECOND;
+
+        fn motion(tilt_x: i16, tilt_y: i16, rotation: i16) -> Motion {
+            Motion { tilt_x, tilt_y, rotation }
+        }
+
+        #[test]
+        fn parse() {
+            let s = MotionScript::parse("# tilt right\n0 0 0 0\n\n2 100 -50 10\n").unwrap();
+            assert_eq!(motion(0, 0, 0), s.motion(0));
+            assert_eq!(motion(50, -25, 5), s.motion(CYCLES_PER_SECOND));
+            assert_eq!(motion(100, -50, 10), s.motion(10 * CYCLES_PER_SECOND));
+        }
+
+        #[test]
+        fn parse_cycles() {
+            let s = MotionScript::parse("100c 1 2 3\n300c 3 4 5").unwrap();
+            assert_eq!(motion(1, 2, 3), s.motion(0));
+            assert_eq!(motion(2, 3, 4), s.motion(200));
+        }
+
+        #[test]
+        fn parse_errors() {
+            assert!(MotionScript::parse("").is_err());
+            assert!(MotionScript::parse("0 0 0").is_err());
+            assert!(MotionScript::parse("0 0 0 40000").is_err());
+            assert!(MotionScript::parse("2 0 0 0\n1 0 0 0").is_err());
+        }
+
+        #[test]
+        fn write() {
+            let s = MotionScript::parse("10c 1 -2 3\n20c 4 5 6").unwrap();
+            let mut buf = Vec::new();
+            s.write(&mut buf).unwrap();
+
+            let text = String::from_utf8(buf).unwrap();
+            assert_eq!("# cycle tilt_x tilt_y rotation\n10c 1 -2 3\n20c 4 5 6\n", text);
+            assert_eq!(Ok(s), MotionScript::parse(&text));
+        }
+    }
+
+    mod motion_input {
+        use super::super::*;
+
+        #[test]
+        fn record_and_replay() {
+            let mut input = MotionInput::new(MotionScript::fixed(Motion::default()));
+            assert_eq!(None, input.recording());
+
+            input.record();
+            input.set(Motion { tilt_x: 10, tilt_y: 0, rotation: 0 });
+            input.sample(100);
+            input.set(Motion { tilt_x: -20, tilt_y: 5, rotation: 0 });
+            input.sample(300);
+
+            let mut replay = MotionInput::new(input.recording().unwrap());
+            assert_eq!(10, replay.sample(100).tilt_x);
+            assert_eq!(Motion { tilt_x: -20, tilt_y: 5, rotation: 0 }, replay.sample(300));
+        }
+    }
+}
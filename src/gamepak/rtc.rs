@@ -0,0 +1,556 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::CYCLES_PER_SECOND;
+
+// The RTC is wired to the first three GPIO pins.
+pub const PIN_SCK: u8 = 0x1;
+pub const PIN_SIO: u8 = 0x2;
+pub const PIN_CS: u8 = 0x4;
+
+// Commands are a byte of 0110, the command and whether it is a read, sent
+// most significant bit first. Everything after is sent least significant
+// bit first.
+const CMD_FIXED: u8 = 0x6;
+const CMD_RESET: u8 = 0;
+const CMD_STATUS: u8 = 1;
+const CMD_DATETIME: u8 = 2;
+const CMD_TIME: u8 = 3;
+const CMD_ALARM: u8 = 4;
+
+// Status register bits. The top bit is set when the clock lost power and
+// needs setting again, which an emulated clock never does, and it can't be
+// written.
+const STATUS_24H: u8 = 0x40;
+const STATUS_WRITABLE: u8 = 0x6A;
+
+// The clock only keeps two digits of the year.
+const BASE_YEAR: u16 = 2000;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime { year, month, day, hour, minute, second }
+    }
+
+    // Parses a time written like 2004-06-01T12:30:00, with or without the
+    // time. The year has to be one the clock can show.
+    pub fn parse(s: &str) -> Result<DateTime, String> {
+        let bad = || format!("bad date and time {:?}, expected YYYY-MM-DDTHH:MM:SS", s);
+        let (date, time) = match s.split_once(['T', ' ']) {
+            Some((date, time)) => (date, time),
+            None => (s, "00:00:00"),
+        };
+
+        let fields: Vec<&str> = date.split('-').chain(time.split(':')).collect();
+        if fields.len() != 6 {
+            return Err(bad());
+        }
+        let year: u16 = fields[0].parse().map_err(|_| bad())?;
+        let rest = fields[1..].iter()
+            .map(|f| f.parse::<u8>().map_err(|_| bad()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let dt = DateTime::new(year, rest[0], rest[1], rest[2], rest[3], rest[4]);
+        match dt.is_valid() && (BASE_YEAR..BASE_YEAR + 100).contains(&year) {
+            true => Ok(dt),
+            false => Err(bad()),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    // Converts seconds since 1970 to a date and time, in UTC.
+    pub fn from_unix(secs: i64) -> DateTime {
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400);
+
+        // Counts from 0000-03-01 so leap days fall at the end of the year.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+
+        DateTime::new(year as u16, month as u8, day as u8,
+                      (rem / 3600) as u8, (rem / 60 % 60) as u8, (rem % 60) as u8)
+    }
+
+    pub fn unix(&self) -> i64 {
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+
+    // The day of the week, counting from Sunday.
+    pub fn weekday(&self) -> u8 {
+        (self.unix().div_euclid(86400) + 4).rem_euclid(7) as u8
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+               self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Where the clock gets the time from. The host clock is read in UTC. A fixed
+// time never moves, and a virtual clock starts at a time and runs with the
+// emulated cycles, so runs that use either see the same times every time.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TimeSource {
+    Host,
+    Fixed(DateTime),
+    Virtual(DateTime),
+}
+
+impl TimeSource {
+    // Parses host, fixed:<time> or virtual:<time>. A virtual clock starts at
+    // the start of 2000 unless given a time.
+    pub fn parse(s: &str) -> Result<TimeSource, String> {
+        match s.split_once(':') {
+            None if s == "host" => Ok(TimeSource::Host),
+            None if s == "virtual" => Ok(TimeSource::Virtual(DateTime::new(BASE_YEAR, 1, 1, 0, 0, 0))),
+            Some(("fixed", time)) => Ok(TimeSource::Fixed(DateTime::parse(time)?)),
+            Some(("virtual", time)) => Ok(TimeSource::Virtual(DateTime::parse(time)?)),
+            _ => Err(format!("unknown time source {:?}", s)),
+        }
+    }
+
+    // Returns the time in seconds since 1970, cycle cycles into the run.
+    pub fn now(&self, cycle: u64) -> i64 {
+        match self {
+            TimeSource::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0),
+            TimeSource::Fixed(t) => t.unix(),
+            TimeSource::Virtual(t) => t.unix() + (cycle / CYCLES_PER_SECOND) as i64,
+        }
+    }
+}
+
+impl fmt::Display for TimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeSource::Host => write!(f, "host clock"),
+            TimeSource::Fixed(t) => write!(f, "fixed at {}", t),
+            TimeSource::Virtual(t) => write!(f, "virtual from {}", t),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Command {
+    cmd: u8,
+    read: bool,
+}
+
+// The Seiko S-3511 real-time clock, talked to over a three wire serial bus
+// on the GPIO port. A transfer starts when CS goes high, and a bit moves on
+// each rising edge of SCK over SIO, a command byte first and then the
+// bytes of the register it reads or writes. Times are in BCD.
+//
+// The time kept is the time source's, moved by however far the game has
+// set the clock away from it.
+pub struct Rtc {
+    source: TimeSource,
+    offset: i64,
+    status: u8,
+    alarm: [u8; 2],
+
+    sck: bool,
+    cs: bool,
+    bits: u8,
+    bit_count: u8,
+    command: Option<Command>,
+    buf: Vec<u8>,
+    sio_out: bool,
+}
+
+impl Rtc {
+    pub fn new(source: TimeSource) -> Rtc {
+        Rtc {
+            source,
+            offset: 0,
+            status: STATUS_24H,
+            alarm: [0; 2],
+
+            sck: false,
+            cs: false,
+            bits: 0,
+            bit_count: 0,
+            command: None,
+            buf: Vec::new(),
+            sio_out: false,
+        }
+    }
+
+    pub fn source(&self) -> TimeSource {
+        self.source
+    }
+
+    // Returns the time the clock shows, cycle cycles into the run.
+    pub fn time(&self, cycle: u64) -> DateTime {
+        DateTime::from_unix(self.source.now(cycle) + self.offset)
+    }
+
+    pub fn set_time(&mut self, time: DateTime, cycle: u64) {
+        self.offset = time.unix() - self.source.now(cycle);
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    // Takes the pins the GBA drives.
+    pub fn write(&mut self, pins: u8, cycle: u64) {
+        let sck = pins & PIN_SCK != 0;
+        let cs = pins & PIN_CS != 0;
+
+        if !cs {
+            self.command = None;
+            self.bits = 0;
+            self.bit_count = 0;
+        } else if !self.cs {
+            self.command = None;
+            self.bits = 0;
+            self.bit_count = 0;
+            self.buf.clear();
+        } else if sck && !self.sck {
+            self.clock(pins & PIN_SIO != 0, cycle);
+        }
+
+        self.sck = sck;
+        self.cs = cs;
+    }
+
+    // Returns the pins the clock drives, which is only SIO while a register
+    // is being read.
+    pub fn read(&self) -> u8 {
+        match self.command {
+            Some(Command { read: true, .. }) if self.cs && self.sio_out => PIN_SIO,
+            _ => 0,
+        }
+    }
+
+    fn clock(&mut self, sio: bool, cycle: u64) {
+        if let Some(Command { read: true, .. }) = self.command {
+            let byte = self.buf.get(self.bit_count as usize / 8).copied().unwrap_or(0);
+            self.sio_out = (byte >> (self.bit_count % 8)) & 0x1 != 0;
+            self.bit_count = self.bit_count.wrapping_add(1);
+            return;
+        }
+
+        self.bits |= (sio as u8) << self.bit_count;
+        self.bit_count += 1;
+        if self.bit_count == 8 {
+            let byte = self.bits;
+            self.bits = 0;
+            self.bit_count = 0;
+            self.receive(byte, cycle);
+        }
+    }
+
+    fn receive(&mut self, byte: u8, cycle: u64) {
+        let cmd = match self.command {
+            Some(c) => c.cmd,
+            None => {
+                let byte = byte.reverse_bits();
+                if byte >> 4 != CMD_FIXED {
+                    return;
+                }
+
+                let c = Command {
+                    cmd: (byte >> 1) & 0x7,
+                    read: byte & 0x1 != 0,
+                };
+                self.command = Some(c);
+                self.buf = match c.read {
+                    true => self.register(c.cmd, cycle),
+                    false => Vec::new(),
+                };
+                if c.cmd == CMD_RESET {
+                    self.reset(cycle);
+                }
+                return;
+            }
+        };
+
+        self.buf.push(byte);
+        if self.buf.len() == register_size(cmd) {
+            let buf = std::mem::take(&mut self.buf);
+            self.set_register(cmd, &buf, cycle);
+        }
+    }
+
+    fn reset(&mut self, cycle: u64) {
+        self.status = 0;
+        self.alarm = [0; 2];
+        self.set_time(DateTime::new(BASE_YEAR, 1, 1, 0, 0, 0), cycle);
+    }
+
+    fn register(&self, cmd: u8, cycle: u64) -> Vec<u8> {
+        let t = self.time(cycle);
+        let hour = match self.status & STATUS_24H {
+            0 => bcd(t.hour % 12),
+            _ => bcd(t.hour),
+        } | if t.hour >= 12 { 0x80 } else { 0 };
+
+        match cmd {
+            CMD_STATUS => vec![self.status],
+            CMD_DATETIME => vec![bcd((t.year % 100) as u8), bcd(t.month), bcd(t.day), t.weekday(),
+                                 hour, bcd(t.minute), bcd(t.second)],
+            CMD_TIME => vec![hour, bcd(t.minute), bcd(t.second)],
+            CMD_ALARM => self.alarm.to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_register(&mut self, cmd: u8, data: &[u8], cycle: u64) {
+        let hour = |val: u8| match self.status & STATUS_24H {
+            0 => from_bcd(val & 0x3F) % 12 + if val & 0x80 != 0 { 12 } else { 0 },
+            _ => from_bcd(val & 0x3F),
+        };
+
+        match cmd {
+            CMD_STATUS => self.status = (self.status & !STATUS_WRITABLE) | (data[0] & STATUS_WRITABLE),
+            CMD_DATETIME => {
+                let t = DateTime::new(BASE_YEAR + from_bcd(data[0]) as u16, from_bcd(data[1]),
+                                      from_bcd(data[2]), hour(data[4]), from_bcd(data[5]), from_bcd(data[6]));
+                if t.is_valid() {
+                    self.set_time(t, cycle);
+                }
+            }
+            CMD_TIME => {
+                let now = self.time(cycle);
+                let t = DateTime::new(now.year, now.month, now.day,
+                                      hour(data[0]), from_bcd(data[1]), from_bcd(data[2]));
+                if t.is_valid() {
+                    self.set_time(t, cycle);
+                }
+            }
+            CMD_ALARM => self.alarm.copy_from_slice(data),
+            _ => {}
+        }
+    }
+}
+
+fn register_size(cmd: u8) -> usize {
+    match cmd {
+        CMD_STATUS => 1,
+        CMD_DATETIME => 7,
+        CMD_TIME => 3,
+        CMD_ALARM => 2,
+        _ => 0,
+    }
+}
+
+fn bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+fn from_bcd(val: u8) -> u8 {
+    (val >> 4) * 10 + (val & 0xF)
+}
+
+#[cfg(test)]
+mod tests {
+    mod date_time {
+        use super::super::*;
+
+        #[test]
+        fn unix() {
+            let t = DateTime::new(2004, 2, 29, 13, 45, 30);
+            assert_eq!(1_078_062_330, t.unix());
+            assert_eq!(t, DateTime::from_unix(t.unix()));
+            assert_eq!(DateTime::new(1970, 1, 1, 0, 0, 0), DateTime::from_unix(0));
+        }
+
+        #[test]
+        fn weekday() {
+            assert_eq!(4, DateTime::new(1970, 1, 1, 0, 0, 0).weekday());
+            assert_eq!(6, DateTime::new(2000, 1, 1, 0, 0, 0).weekday());
+        }
+
+        #[test]
+        fn parse() {
+            assert_eq!(Ok(DateTime::new(2004, 6, 1, 12, 30, 5)), DateTime::parse("2004-06-01T12:30:05"));
+            assert_eq!(Ok(DateTime::new(2004, 6, 1, 0, 0, 0)), DateTime::parse("2004-06-01"));
+            assert!(DateTime::parse("2003-02-29").is_err());
+            assert!(DateTime::parse("2004-06").is_err());
+            assert!(DateTime::parse("2004-262-01").is_err());
+            assert!(DateTime::parse("2004-06-01T268:00:00").is_err());
+            assert!(DateTime::parse("1999-12-31").is_err());
+            assert!(DateTime::parse("2100-01-01").is_err());
+            assert!(DateTime::parse("2099-12-31").is_ok());
+        }
+
+        #[test]
+        fn time_source() {
+            let t = DateTime::new(2004, 6, 1, 0, 0, 0);
+            assert_eq!(Ok(TimeSource::Host), TimeSource::parse("host"));
+            assert_eq!(Ok(TimeSource::Fixed(t)), TimeSource::parse("fixed:2004-06-01"));
+            assert_eq!(Ok(TimeSource::Virtual(t)), TimeSource::parse("virtual:2004-06-01"));
+            assert!(TimeSource::parse("fixed").is_err());
+
+            assert_eq!(t.unix() + 2, TimeSource::Virtual(t).now(2 * CYCLES_PER_SECOND + 1));
+            assert_eq!(t.unix(), TimeSource::Fixed(t).now(2 * CYCLES_PER_SECOND));
+        }
+    }
+
+    mod rtc {
+        use super::super::*;
+
+        fn start(r: &mut Rtc) {
+            r.write(PIN_SCK, 0);
+            r.write(PIN_SCK | PIN_CS, 0);
+        }
+
+        fn stop(r: &mut Rtc) {
+            r.write(PIN_SCK, 0);
+        }
+
+        fn send(r: &mut Rtc, byte: u8) {
+            for bit in 0..8 {
+                let sio = ((byte >> bit) & 0x1) * PIN_SIO;
+                r.write(PIN_CS | sio, 0);
+                r.write(PIN_CS | PIN_SCK | sio, 0);
+            }
+        }
+
+        fn receive(r: &mut Rtc) -> u8 {
+            let mut byte = 0;
+            for bit in 0..8 {
+                r.write(PIN_CS, 0);
+                r.write(PIN_CS | PIN_SCK, 0);
+                byte |= ((r.read() & PIN_SIO) >> 1) << bit;
+            }
+            byte
+        }
+
+        fn command(r: &mut Rtc, cmd: u8) {
+            start(r);
+            send(r, cmd.reverse_bits());
+        }
+
+        fn fixed() -> Rtc {
+            Rtc::new(TimeSource::Fixed(DateTime::new(2004, 6, 1, 13, 45, 30)))
+        }
+
+        #[test]
+        fn read_datetime() {
+            let mut r = fixed();
+            command(&mut r, 0x65);
+            let data: Vec<u8> = (0..7).map(|_| receive(&mut r)).collect();
+            stop(&mut r);
+
+            assert_eq!(vec![0x04, 0x06, 0x01, 2, 0x93, 0x45, 0x30], data);
+        }
+
+        #[test]
+        fn read_time_12h() {
+            let mut r = fixed();
+            command(&mut r, 0x62);
+            send(&mut r, 0);
+            stop(&mut r);
+
+            command(&mut r, 0x67);
+            let data: Vec<u8> = (0..3).map(|_| receive(&mut r)).collect();
+            assert_eq!(vec![0x81, 0x45, 0x30], data);
+        }
+
+        #[test]
+        fn read_status() {
+            let mut r = fixed();
+            command(&mut r, 0x63);
+            assert_eq!(STATUS_24H, receive(&mut r));
+            assert_eq!(0, r.status() & 0x80);
+        }
+
+        #[test]
+        fn write_datetime() {
+            let mut r = fixed();
+            command(&mut r, 0x64);
+            for byte in [0x05, 0x12, 0x31, 0, 0x23, 0x59, 0x58].iter() {
+                send(&mut r, *byte);
+            }
+            stop(&mut r);
+
+            assert_eq!(DateTime::new(2005, 12, 31, 23, 59, 58), r.time(0));
+        }
+
+        #[test]
+        fn virtual_clock() {
+            let mut r = Rtc::new(TimeSource::Virtual(DateTime::new(2004, 6, 1, 0, 0, 0)));
+            r.set_time(DateTime::new(2010, 1, 1, 0, 0, 0), 0);
+            assert_eq!(DateTime::new(2010, 1, 1, 0, 0, 5), r.time(5 * CYCLES_PER_SECOND));
+        }
+
+        #[test]
+        fn alarm() {
+            let mut r = fixed();
+            command(&mut r, 0x68);
+            send(&mut r, 0x07);
+            send(&mut r, 0x30);
+            stop(&mut r);
+
+            command(&mut r, 0x69);
+            assert_eq!(0x07, receive(&mut r));
+            assert_eq!(0x30, receive(&mut r));
+        }
+
+        #[test]
+        fn reset() {
+            let mut r = fixed();
+            command(&mut r, 0x60);
+            stop(&mut r);
+
+            assert_eq!(0, r.status());
+            assert_eq!(DateTime::new(2000, 1, 1, 0, 0, 0), r.time(0));
+        }
+
+        #[test]
+        fn bad_command_ignored() {
+            let mut r = fixed();
+            command(&mut r, 0x05);
+            assert_eq!(0, receive(&mut r));
+        }
+    }
+}
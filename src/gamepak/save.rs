@@ -0,0 +1,169 @@
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::backup::{Backup, SizeMismatch};
+
+// The file a cartridge's backup memory is kept in between runs.
+pub struct SaveFile {
+    path: PathBuf,
+    loaded: bool,
+    mismatch: Option<SizeMismatch>,
+}
+
+impl SaveFile {
+    // Loads the save at path into the backup, if there is one yet. A save
+    // of the wrong size is copied to a .bak file first, since writing the
+    // backup out would replace it with one of a different size.
+    pub fn open(path: PathBuf, backup: &mut Backup) -> Result<SaveFile, String> {
+        let (loaded, mismatch) = match fs::read(&path) {
+            Ok(data) => (true, backup.load(&data).err()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (false, None),
+            Err(e) => return Err(format!("could not read save {}: {}", path.display(), e)),
+        };
+
+        if mismatch.is_some() {
+            let bak = with_suffix(&path, ".bak");
+            if let Err(e) = fs::copy(&path, &bak) {
+                return Err(format!("could not keep save as {}: {}", bak.display(), e));
+            }
+        }
+
+        Ok(SaveFile {
+            path,
+            loaded,
+            mismatch,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Whether there was a save to load, rather than a new one being started.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    pub fn mismatch(&self) -> Option<&SizeMismatch> {
+        self.mismatch.as_ref()
+    }
+
+    // Writes the backup out if it changed since it was last written, and
+    // returns whether it did.
+    pub fn flush(&self, backup: &mut Backup) -> Result<bool, String> {
+        if !backup.is_dirty() {
+            return Ok(false);
+        }
+
+        if let Err(e) = write_atomic(&self.path, backup.data()) {
+            return Err(format!("could not write save {}: {}", self.path.display(), e));
+        }
+        backup.clear_dirty();
+        Ok(true)
+    }
+}
+
+// Writes a file by writing a temporary file next to it and renaming that
+// over it, so a crash part way through leaves the old file whole. If the
+// write fails, the temporary file is removed again.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    let result = write_synced(&tmp, data).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn write_synced(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    f.write_all(data)?;
+    f.sync_all()
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    mod save_file {
+        use super::super::*;
+        use crate::gamepak::BackupType;
+        use std::env;
+        use std::process;
+
+        fn temp_path(name: &str) -> PathBuf {
+            let path = env::temp_dir().join(format!("gabba-{}-{}.sav", process::id(), name));
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(with_suffix(&path, ".bak"));
+            path
+        }
+
+        #[test]
+        fn open_missing() {
+            let path = temp_path("missing");
+            let mut b = Backup::new(BackupType::Sram, None);
+            let s = SaveFile::open(path.clone(), &mut b).unwrap();
+
+            assert!(!s.is_loaded());
+            assert_eq!(None, s.mismatch());
+            assert!(!path.exists());
+        }
+
+        #[test]
+        fn flush_and_open() {
+            let path = temp_path("flush");
+            let mut b = Backup::new(BackupType::Sram, None);
+            let s = SaveFile::open(path.clone(), &mut b).unwrap();
+
+            assert_eq!(Ok(false), s.flush(&mut b));
+            b.write8(0x10, 0x12);
+            assert_eq!(Ok(true), s.flush(&mut b));
+            assert!(!b.is_dirty());
+            assert!(!with_suffix(&path, ".tmp").exists());
+
+            let mut b = Backup::new(BackupType::Sram, None);
+            let s = SaveFile::open(path.clone(), &mut b).unwrap();
+            assert!(s.is_loaded());
+            assert_eq!(0x12, b.read8(0x10));
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn write_atomic_failed_rename() {
+            // Renaming over a directory fails, after the temporary file has
+            // been written.
+            let path = temp_path("rename");
+            let _ = fs::remove_dir(&path);
+            fs::create_dir(&path).unwrap();
+
+            assert!(write_atomic(&path, &[1, 2, 3]).is_err());
+            assert!(!with_suffix(&path, ".tmp").exists());
+            fs::remove_dir(&path).unwrap();
+        }
+
+        #[test]
+        fn open_size_mismatch() {
+            let path = temp_path("mismatch");
+            fs::write(&path, [0x12; 100]).unwrap();
+
+            let mut b = Backup::new(BackupType::Sram, None);
+            let s = SaveFile::open(path.clone(), &mut b).unwrap();
+            assert_eq!(100, s.mismatch().unwrap().actual);
+            assert_eq!(0x12, b.read8(99));
+            assert_eq!(0xFF, b.read8(100));
+
+            let bak = with_suffix(&path, ".bak");
+            assert_eq!(100, fs::read(&bak).unwrap().len());
+            fs::remove_file(&path).unwrap();
+            fs::remove_file(&bak).unwrap();
+        }
+    }
+}
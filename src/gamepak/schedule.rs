@@ -0,0 +1,128 @@
+use super::CYCLES_PER_SECOND;
+
+// A value that can be ramped between two keyframes, t of the way from one
+// to the other.
+pub trait Keyframe: Copy {
+    fn lerp(from: Self, to: Self, t: f64) -> Self;
+}
+
+impl Keyframe for u8 {
+    fn lerp(from: u8, to: u8, t: f64) -> u8 {
+        (from as f64 + (to as f64 - from as f64) * t).round() as u8
+    }
+}
+
+impl Keyframe for i16 {
+    fn lerp(from: i16, to: i16, t: f64) -> i16 {
+        (from as f64 + (to as f64 - from as f64) * t).round() as i16
+    }
+}
+
+// Values over a run, as keyframes at points in time with straight ramps
+// between them. The value holds before the first point and after the last.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Schedule<T> {
+    // Cycles into the run and the value then, in order.
+    points: Vec<(u64, T)>,
+}
+
+impl<T: Keyframe> Schedule<T> {
+    pub fn fixed(value: T) -> Schedule<T> {
+        Schedule { points: vec![(0, value)] }
+    }
+
+    // Makes a schedule from points, which must be in time order. What is
+    // named in the errors.
+    pub fn from_points(points: Vec<(u64, T)>, what: &str) -> Result<Schedule<T>, String> {
+        if points.is_empty() {
+            return Err(format!("no {} given", what));
+        }
+        if points.windows(2).any(|w| w[1].0 < w[0].0) {
+            return Err(format!("{} must be given in time order", what));
+        }
+        Ok(Schedule { points })
+    }
+
+    // Parses a script with a point on each line, using parse_line for each.
+    // Blank lines and lines starting with # are skipped.
+    pub fn parse_script<F>(script: &str, what: &str, parse_line: F) -> Result<Schedule<T>, String>
+        where F: Fn(&str) -> Result<(u64, T), String> {
+        let points = script.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(parse_line)
+            .collect::<Result<_, _>>()?;
+        Schedule::from_points(points, what)
+    }
+
+    pub fn points(&self) -> &[(u64, T)] {
+        &self.points
+    }
+
+    // Returns the value cycle cycles into the run.
+    pub fn at(&self, cycle: u64) -> T {
+        let next = self.points.iter().position(|(at, _)| *at > cycle);
+        match next {
+            Some(0) => self.points[0].1,
+            Some(idx) => {
+                let (start, from) = self.points[idx - 1];
+                let (end, to) = self.points[idx];
+                let t = (cycle - start) as f64 / (end - start) as f64;
+                T::lerp(from, to, t)
+            }
+            None => self.points[self.points.len() - 1].1,
+        }
+    }
+}
+
+// Parses a time into the run, in seconds or in cycles with a c after them,
+// and returns it in cycles.
+pub fn parse_time(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let bad = || format!("bad time {:?}, expected <seconds> or <cycles>c", s);
+    match s.strip_suffix('c') {
+        Some(cycles) => cycles.parse().map_err(|_| bad()),
+        None => {
+            let secs: f64 = s.parse().map_err(|_| bad())?;
+            if secs < 0.0 {
+                return Err(bad());
+            }
+            Ok((secs * CYCLES_PER_SECOND as f64) as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod schedule {
+        use super::super::*;
+
+        #[test]
+        fn at() {
+            let s = Schedule::from_points(vec![(100, 0u8), (300, 200)], "levels").unwrap();
+            assert_eq!(0, s.at(0));
+            assert_eq!(0, s.at(100));
+            assert_eq!(100, s.at(200));
+            assert_eq!(200, s.at(300));
+            assert_eq!(200, s.at(1000));
+        }
+
+        #[test]
+        fn from_points_errors() {
+            assert_eq!(Err("no levels given".to_string()), Schedule::<u8>::from_points(vec![], "levels"));
+            assert_eq!(
+                Err("levels must be given in time order".to_string()),
+                Schedule::from_points(vec![(2, 0u8), (1, 0)], "levels"),
+            );
+        }
+
+        #[test]
+        fn time() {
+            assert_eq!(Ok(CYCLES_PER_SECOND / 2), parse_time("0.5"));
+            assert_eq!(Ok(1234), parse_time("1234c"));
+            assert!(parse_time("-1").is_err());
+            assert!(parse_time("1.5c").is_err());
+            assert!(parse_time("x").is_err());
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use super::schedule::{parse_time, Schedule};
+
+// The sensor is wired to the GPIO pins the RTC doesn't use for data, and
+// shares CS with it on cartridges with both.
+pub const PIN_CLK: u8 = 0x1;
+pub const PIN_RST: u8 = 0x2;
+pub const PIN_FLAG: u8 = 0x8;
+
+// How bright it is over a run, as a schedule of light levels. Levels run
+// from 0 for darkness to 255 for full sun.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LightSchedule {
+    levels: Schedule<u8>,
+}
+
+impl LightSchedule {
+    pub fn fixed(level: u8) -> LightSchedule {
+        LightSchedule { levels: Schedule::fixed(level) }
+    }
+
+    // Ramps from one level to another between two points in the run.
+    pub fn ramp(from: u8, to: u8, start: u64, end: u64) -> LightSchedule {
+        let levels = Schedule::from_points(vec![(start, from), (end.max(start), to)], "light levels");
+        LightSchedule { levels: levels.unwrap() }
+    }
+
+    // Parses a level on its own, or a list of <time>:<level> points like
+    // 0:0,10:255.
+    pub fn parse(s: &str) -> Result<LightSchedule, String> {
+        if let Ok(level) = s.parse() {
+            return Ok(LightSchedule::fixed(level));
+        }
+
+        let points = s.split(',')
+            .map(|point| match point.split_once(':') {
+                Some((time, level)) => parse_point(time, level),
+                None => Err(format!("bad light point {:?}, expected <time>:<level>", point)),
+            })
+            .collect::<Result<_, _>>()?;
+        let levels = Schedule::from_points(points, "light levels")?;
+        Ok(LightSchedule { levels })
+    }
+
+    // Parses a script with a point on each line, written as the time into
+    // the run and the level then. Times are in seconds, or in cycles with a
+    // c after them.
+    pub fn parse_script(script: &str) -> Result<LightSchedule, String> {
+        let levels = Schedule::parse_script(script, "light levels", |line| {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [time, level] => parse_point(time, level),
+                _ => Err(format!("bad light script line {:?}, expected <time> <level>", line)),
+            }
+        })?;
+        Ok(LightSchedule { levels })
+    }
+
+    // Returns the light level cycle cycles into the run.
+    pub fn level(&self, cycle: u64) -> u8 {
+        self.levels.at(cycle)
+    }
+}
+
+fn parse_point(time: &str, level: &str) -> Result<(u64, u8), String> {
+    let cycle = parse_time(time)?;
+    let level = level.trim().parse().map_err(|_| format!("bad light level {:?}, expected 0-255", level))?;
+    Ok((cycle, level))
+}
+
+// The light sensor on Boktai cartridges. The sensor takes a reading when
+// RST is raised, and the game then counts rising edges on CLK until FLAG
+// goes high, which happens once the count reaches the reading. Brighter
+// light gives lower readings.
+pub struct SolarSensor {
+    light: LightSchedule,
+
+    clk: bool,
+    counter: u8,
+    reading: u8,
+}
+
+impl SolarSensor {
+    pub fn new(light: LightSchedule) -> SolarSensor {
+        SolarSensor {
+            light,
+
+            clk: false,
+            counter: 0,
+            reading: 0xFF,
+        }
+    }
+
+    pub fn light(&self) -> &LightSchedule {
+        &self.light
+    }
+
+    pub fn set_light(&mut self, light: LightSchedule) {
+        self.light = light;
+    }
+
+    // Takes the pins the GBA drives.
+    pub fn write(&mut self, pins: u8, cycle: u64) {
+        let clk = pins & PIN_CLK != 0;
+        if pins & PIN_RST != 0 {
+            self.counter = 0;
+            self.reading = 0xFF - self.light.level(cycle);
+        } else if clk && !self.clk {
+            self.counter = self.counter.saturating_add(1);
+        }
+        self.clk = clk;
+    }
+
+    // Returns the pins the sensor drives, which is FLAG once the count
+    // reaches the reading.
+    pub fn read(&self) -> u8 {
+        match self.counter >= self.reading {
+            true => PIN_FLAG,
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod light_schedule {
+        use super::super::*;
+        use crate::gamepak::CYCLES_PER_SECOND;
+
+        #[test]
+        fn fixed() {
+            assert_eq!(100, LightSchedule::fixed(100).level(12345));
+        }
+
+        #[test]
+        fn ramp() {
+            let l = LightSchedule::ramp(0, 200, 100, 300);
+            assert_eq!(0, l.level(0));
+            assert_eq!(0, l.level(100));
+            assert_eq!(100, l.level(200));
+            assert_eq!(200, l.level(300));
+            assert_eq!(200, l.level(1000));
+        }
+
+        #[test]
+        fn parse() {
+            assert_eq!(Ok(LightSchedule::fixed(80)), LightSchedule::parse("80"));
+            assert_eq!(Ok(LightSchedule::ramp(0, 255, 0, 10 * CYCLES_PER_SECOND)), LightSchedule::parse("0:0,10:255"));
+            assert!(LightSchedule::parse("256").is_err());
+            assert!(LightSchedule::parse("10:0,0:255").is_err());
+            assert!(LightSchedule::parse("10").is_ok());
+            assert!(LightSchedule::parse("x").is_err());
+        }
+
+        #[test]
+        fn parse_cycles() {
+            assert_eq!(Ok(LightSchedule::ramp(0, 255, 100, 300)), LightSchedule::parse("100c:0,300c:255"));
+
+            let l = LightSchedule::parse_script("100c 0\n300c 200\n").unwrap();
+            assert_eq!(100, l.level(200));
+            assert!(LightSchedule::parse_script("1.5c 0").is_err());
+        }
+
+        #[test]
+        fn parse_script() {
+            let l = LightSchedule::parse_script("# dawn\n0 0\n\n1.5 150\n2 150\n").unwrap();
+            assert_eq!(100, l.level(CYCLES_PER_SECOND));
+            assert_eq!(150, l.level(2 * CYCLES_PER_SECOND));
+            assert!(LightSchedule::parse_script("1 2 3").is_err());
+            assert!(LightSchedule::parse_script("2 0\n1 0").is_err());
+            assert!(LightSchedule::parse_script("# nothing\n").is_err());
+        }
+    }
+
+    mod solar_sensor {
+        use super::super::*;
+
+        // Counts clocks until the flag goes up, like the game does.
+        fn measure(s: &mut SolarSensor, cycle: u64) -> u32 {
+            s.write(PIN_RST, cycle);
+            s.write(0, cycle);
+            let mut count = 0;
+            while s.read() & PIN_FLAG == 0 && count < 0x100 {
+                s.write(PIN_CLK, cycle);
+                s.write(0, cycle);
+                count += 1;
+            }
+            count
+        }
+
+        #[test]
+        fn reading() {
+            let mut s = SolarSensor::new(LightSchedule::fixed(0x50));
+            assert_eq!(0xAF, measure(&mut s, 0));
+
+            s.set_light(LightSchedule::fixed(0xFF));
+            assert_eq!(0, measure(&mut s, 0));
+        }
+
+        #[test]
+        fn reading_follows_ramp() {
+            let mut s = SolarSensor::new(LightSchedule::ramp(0, 0xFF, 0, 100));
+            assert_eq!(0xFF, measure(&mut s, 0));
+            assert_eq!(0x7F, measure(&mut s, 50));
+        }
+
+        #[test]
+        fn clock_needs_edges() {
+            let mut s = SolarSensor::new(LightSchedule::fixed(0xFD));
+            s.write(PIN_RST, 0);
+            s.write(PIN_CLK, 0);
+            s.write(PIN_CLK, 0);
+            assert_eq!(0, s.read());
+
+            s.write(0, 0);
+            s.write(PIN_CLK, 0);
+            assert_eq!(PIN_FLAG, s.read());
+        }
+    }
+}
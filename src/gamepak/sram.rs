@@ -0,0 +1,93 @@
+const KBYTE: usize = 1024;
+
+pub const SRAM_SIZE: usize = 32 * KBYTE;
+
+// Battery backed static RAM, read and written a byte at a time. The chip is
+// smaller than the 64 KiB PAK_RAM window, so it is mirrored across it.
+pub struct Sram {
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+impl Sram {
+    // SRAM that has never been written reads back as all ones.
+    pub fn new() -> Sram {
+        Sram {
+            data: vec![0xFF; SRAM_SIZE],
+            dirty: false,
+        }
+    }
+
+    pub fn read8(&self, offset: usize) -> u8 {
+        self.data[offset % SRAM_SIZE]
+    }
+
+    pub fn write8(&mut self, offset: usize, val: u8) {
+        let byte = &mut self.data[offset % SRAM_SIZE];
+        if *byte != val {
+            *byte = val;
+            self.dirty = true;
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    // Whether anything changed since the last call to clear_dirty, so saves
+    // only need writing out when they change.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+}
+
+impl Default for Sram {
+    fn default() -> Sram {
+        Sram::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod sram {
+        use super::super::*;
+
+        #[test]
+        fn new() {
+            let s = Sram::new();
+            assert_eq!(SRAM_SIZE, s.data().len());
+            assert_eq!(0xFF, s.read8(0));
+            assert!(!s.is_dirty());
+        }
+
+        #[test]
+        fn write_mirrored() {
+            let mut s = Sram::new();
+            s.write8(SRAM_SIZE + 0x10, 0x12);
+
+            assert_eq!(0x12, s.read8(0x10));
+            assert_eq!(0x12, s.data()[0x10]);
+        }
+
+        #[test]
+        fn dirty() {
+            let mut s = Sram::new();
+            s.write8(0, 0xFF);
+            assert!(!s.is_dirty());
+
+            s.write8(0, 0x00);
+            assert!(s.is_dirty());
+
+            s.clear_dirty();
+            assert!(!s.is_dirty());
+        }
+    }
+}
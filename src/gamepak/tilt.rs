@@ -0,0 +1,126 @@
+use super::motion::{MotionInput, MotionScript};
+
+// The sensor's registers sit in the PAK_RAM window, which the cartridges
+// that have one don't otherwise use.
+pub const TILT_START: usize = 0x8000;
+pub const TILT_SAMPLE: usize = 0x8100;
+pub const TILT_X_LOW: usize = 0x8200;
+pub const TILT_X_HIGH: usize = 0x8300;
+pub const TILT_Y_LOW: usize = 0x8400;
+pub const TILT_Y_HIGH: usize = 0x8500;
+
+// Writing these to the start and then the sample register takes a reading.
+const START_KEY: u8 = 0x55;
+const SAMPLE_KEY: u8 = 0xAA;
+
+// Readings are 12 bits, and sit around this when the GBA is held flat.
+const CENTER: i32 = 0x3A0;
+const MAX: i32 = 0xFFF;
+
+const READY: u8 = 0x80;
+
+// The two axis accelerometer on Yoshi Topsy-Turvy and Koro Koro Puzzle
+// cartridges. Games unlock it, take a reading, and then read the two axes
+// back a byte at a time.
+pub struct TiltSensor {
+    input: MotionInput,
+
+    started: bool,
+    x: u16,
+    y: u16,
+}
+
+impl TiltSensor {
+    pub fn new(script: MotionScript) -> TiltSensor {
+        TiltSensor {
+            input: MotionInput::new(script),
+
+            started: false,
+            x: CENTER as u16,
+            y: CENTER as u16,
+        }
+    }
+
+    pub fn input(&self) -> &MotionInput {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut MotionInput {
+        &mut self.input
+    }
+
+    // Reads the register at offset into PAK_RAM, or None if there isn't one
+    // there. A reading is always ready, since it is taken all at once.
+    pub fn read8(&self, offset: usize) -> Option<u8> {
+        match offset {
+            TILT_X_LOW => Some(self.x as u8),
+            TILT_X_HIGH => Some((self.x >> 8) as u8 | READY),
+            TILT_Y_LOW => Some(self.y as u8),
+            TILT_Y_HIGH => Some((self.y >> 8) as u8),
+            _ => None,
+        }
+    }
+
+    // Takes a write to the register at offset into PAK_RAM, and returns
+    // whether there was one there.
+    pub fn write8(&mut self, offset: usize, val: u8, cycle: u64) -> bool {
+        match offset {
+            TILT_START => self.started = val == START_KEY,
+            TILT_SAMPLE => {
+                if self.started && val == SAMPLE_KEY {
+                    let m = self.input.sample(cycle);
+                    self.x = (CENTER + m.tilt_x as i32).clamp(0, MAX) as u16;
+                    self.y = (CENTER + m.tilt_y as i32).clamp(0, MAX) as u16;
+                }
+                self.started = false;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod tilt_sensor {
+        use super::super::*;
+        use crate::gamepak::motion::Motion;
+
+        fn sample(t: &mut TiltSensor) -> (u16, u16) {
+            t.write8(TILT_START, START_KEY, 0);
+            t.write8(TILT_SAMPLE, SAMPLE_KEY, 0);
+            let x = t.read8(TILT_X_LOW).unwrap() as u16 | ((t.read8(TILT_X_HIGH).unwrap() & 0xF) as u16) << 8;
+            let y = t.read8(TILT_Y_LOW).unwrap() as u16 | (t.read8(TILT_Y_HIGH).unwrap() as u16) << 8;
+            (x, y)
+        }
+
+        #[test]
+        fn flat() {
+            let mut t = TiltSensor::new(MotionScript::fixed(Motion::default()));
+            assert_eq!((0x3A0, 0x3A0), sample(&mut t));
+            assert_eq!(READY, t.read8(TILT_X_HIGH).unwrap() & READY);
+        }
+
+        #[test]
+        fn tilted() {
+            let mut t = TiltSensor::new(MotionScript::fixed(Motion::default()));
+            t.input_mut().set(Motion { tilt_x: 0x20, tilt_y: -0x400, rotation: 0 });
+            assert_eq!((0x3C0, 0), sample(&mut t));
+        }
+
+        #[test]
+        fn sample_needs_start() {
+            let mut t = TiltSensor::new(MotionScript::fixed(Motion::default()));
+            t.input_mut().set(Motion { tilt_x: 0x20, tilt_y: 0, rotation: 0 });
+            t.write8(TILT_SAMPLE, SAMPLE_KEY, 0);
+            assert_eq!(Some(0xA0), t.read8(TILT_X_LOW));
+        }
+
+        #[test]
+        fn other_offsets() {
+            let mut t = TiltSensor::new(MotionScript::fixed(Motion::default()));
+            assert_eq!(None, t.read8(0x10));
+            assert!(!t.write8(0x10, 0, 0));
+        }
+    }
+}
@@ -1,11 +1,21 @@
+use crate::bios;
 use crate::cpu;
 use crate::gamepak;
 use crate::mem;
 
+// How often changed saves get written out, in cycles. This is about a
+// second, so little is lost if the emulator dies without shutting down.
+const SAVE_FLUSH_INTERVAL: u64 = gamepak::CYCLES_PER_SECOND;
+
 pub struct GBA {
     cpu: cpu::ARM7TDMI,
 
     mem: mem::Memory,
+
+    has_bios: bool,
+
+    save: Option<gamepak::save::SaveFile>,
+    next_flush: u64,
 }
 
 impl GBA {
@@ -13,23 +23,108 @@ impl GBA {
         GBA {
             cpu: cpu::ARM7TDMI::new(),
             mem: mem::Memory::new(),
+
+            has_bios: false,
+
+            save: None,
+            next_flush: SAVE_FLUSH_INTERVAL,
         }
     }
 
+    pub fn load_bios(&mut self, b: bios::Bios) {
+        self.mem.load_bios(b.data());
+        self.has_bios = true;
+    }
+
+    // Loads the gamepak and its save, if it has backup memory, and resets.
+    // With a BIOS loaded the console boots through the reset vector like
+    // real hardware does, otherwise it starts the cartridge directly in the
+    // state the BIOS would leave it in.
     pub fn load(&mut self, gp: gamepak::GamePak) -> Result<(), String> {
+        let mut backup = gp.new_backup();
+        self.save = match gp.save_path() {
+            Some(path) if !backup.data().is_empty() => Some(gamepak::save::SaveFile::open(path, &mut backup)?),
+            _ => None,
+        };
+
         self.mem.load_pak(gp.data());
+        self.mem.set_backup(backup);
+        self.mem.set_gpio(gp.new_gpio());
+        self.mem.set_tilt(gp.new_tilt());
+        self.next_flush = self.mem.cycles() + SAVE_FLUSH_INTERVAL;
 
-        self.cpu.reset();
-        self.cpu.set_reg(cpu::REG_PC, mem::PAK_ROM as u32);
+        match self.has_bios {
+            true => self.cpu.reset(),
+            false => self.cpu.skip_bios(mem::PAK_ROM),
+        }
 
         Ok(())
     }
 
-    pub fn step(&mut self) {
+    pub fn set_trace(&mut self, trace: Option<cpu::trace::Trace>) {
+        self.cpu.set_trace(trace);
+    }
+
+    pub fn set_profile(&mut self, profile: Option<mem::profile::Profile>) {
+        self.mem.set_profile(profile);
+    }
+
+    pub fn profile(&self) -> Option<&mem::profile::Profile> {
+        self.mem.profile()
+    }
+
+    // The cartridge's GPIO port, for driving the hardware on it, like
+    // setting how much light the solar sensor sees.
+    pub fn gpio_mut(&mut self) -> Option<&mut gamepak::Gpio> {
+        self.mem.gpio_mut()
+    }
+
+    // Where the cartridge's motion sensor gets its readings, if it has one,
+    // for feeding it motion or recording what the game read.
+    pub fn motion_mut(&mut self) -> Option<&mut gamepak::MotionInput> {
+        if self.mem.tilt().is_some() {
+            return self.mem.tilt_mut().map(|t| t.input_mut());
+        }
+        self.mem.gpio_mut().and_then(|g| g.gyro_mut()).map(|g| g.input_mut())
+    }
+
+    // Whether the cartridge's rumble motor is running.
+    pub fn is_rumbling(&self) -> bool {
+        self.mem.gpio().is_some_and(|g| g.is_rumbling())
+    }
+
+    pub fn save(&self) -> Option<&gamepak::save::SaveFile> {
+        self.save.as_ref()
+    }
+
+    // Writes the save out if it changed. This needs calling before the
+    // emulator exits, to keep anything written since the last flush.
+    pub fn flush_save(&mut self) -> Result<bool, String> {
+        self.next_flush = self.mem.cycles() + SAVE_FLUSH_INTERVAL;
+        match self.save.as_ref() {
+            Some(save) => save.flush(self.mem.backup_mut()),
+            None => Ok(false),
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), String> {
         println!("cpu:\n{:?}", self.cpu);
-        self.cpu.step(&self.mem);
+        self.cpu.step(&mut self.mem)?;
         println!("cpu:\n{:?}", self.cpu);
-        self.cpu.step(&self.mem);
+        self.cpu.step(&mut self.mem)?;
         println!("cpu:\n{:?}", self.cpu);
+
+        if self.mem.cycles() >= self.next_flush {
+            if let Err(e) = self.flush_save() {
+                println!("{}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for GBA {
+    fn default() -> GBA {
+        GBA::new()
     }
 }
\ No newline at end of file
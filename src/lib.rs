@@ -0,0 +1,8 @@
+pub mod bios;
+pub mod cpu;
+pub mod crc32;
+pub mod gamepak;
+pub mod gba;
+pub mod mem;
+pub mod png;
+pub mod tracediff;
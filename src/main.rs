@@ -1,14 +1,129 @@
-mod cpu;
-mod gamepak;
-mod gba;
-mod mem;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+
+use gabba::cpu::trace::{Trace, TraceFormat};
+use gabba::mem::profile::Profile;
+use gabba::{bios, gamepak, gba, tracediff};
+
+const TRACE_DIFF_CONTEXT: usize = 5;
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|a| a.as_str()) {
+        Some("trace-diff") => trace_diff(&args[1..]),
+        Some("fix-header") => fix_header(&args[1..]),
+        _ => run(&args),
+    }
+}
+
+fn usage() -> ! {
+    println!("usage:");
+    println!("  gabba [rom] [--bios <file>] [--trace <file>] [--trace-binary <file>]");
+    println!("        [--profile <file.csv|file.json>] [--heatmap <file.png>]");
+    println!("        [--save-type <none|sram|eeprom|flash64|flash128>]");
+    println!("        [--flash-chip <macronix|panasonic|sanyo|sst|atmel>] [--save-dir <dir>]");
+    println!("        [--rtc <off|host|fixed:<time>|virtual[:<time>]>]");
+    println!("        [--solar <level|time:level,...>] [--solar-script <file>]");
+    println!("        [--motion-script <file>] [--motion-record <file>]");
+    println!("  gabba trace-diff <left> <right> [--context <n>]");
+    println!("  gabba fix-header <rom> [--output <file>]");
+    process::exit(2);
+}
+
+fn run(args: &[String]) {
     //let rom_path = "test/roms/240pee_mb.gba";
-    let rom_path = "/home/aphistic/Downloads/pokemon-sapphire.gba";
+    let mut rom_path = "/home/aphistic/Downloads/pokemon-sapphire.gba";
+    let mut bios_path = None;
+    let mut trace = None;
+    let mut profile_path = None;
+    let mut heatmap_path = None;
+    let mut backup_type = None;
+    let mut flash_chip = None;
+    let mut save_dir = None;
+    let mut rtc = None;
+    let mut light = None;
+    let mut motion = None;
+    let mut motion_record = None;
 
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bios" => bios_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--trace" => trace = Some((args.next().unwrap_or_else(|| usage()), TraceFormat::Text)),
+            "--trace-binary" => trace = Some((args.next().unwrap_or_else(|| usage()), TraceFormat::Binary)),
+            "--profile" => profile_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--heatmap" => heatmap_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--save-type" => {
+                backup_type = match args.next().map(|t| gamepak::BackupType::from_name(t)) {
+                    Some(Some(t)) => Some(t),
+                    _ => usage(),
+                }
+            }
+            "--flash-chip" => {
+                flash_chip = match args.next().map(|c| gamepak::FlashChip::from_name(c)) {
+                    Some(Some(c)) => Some(c),
+                    _ => usage(),
+                }
+            }
+            "--save-dir" => save_dir = Some(args.next().unwrap_or_else(|| usage())),
+            "--rtc" => {
+                rtc = match args.next().map(|s| s.as_str()) {
+                    Some("off") => Some(None),
+                    Some(s) => match gamepak::TimeSource::parse(s) {
+                        Ok(source) => Some(Some(source)),
+                        Err(e) => {
+                            println!("{}", e);
+                            usage();
+                        }
+                    },
+                    None => usage(),
+                }
+            }
+            "--solar" => {
+                light = match args.next().map(|s| gamepak::LightSchedule::parse(s)) {
+                    Some(Ok(l)) => Some(l),
+                    Some(Err(e)) => {
+                        println!("{}", e);
+                        usage();
+                    }
+                    None => usage(),
+                }
+            }
+            "--solar-script" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                let script = fs::read_to_string(path).map_err(|e| e.to_string());
+                light = match script.and_then(|s| gamepak::LightSchedule::parse_script(&s)) {
+                    Ok(l) => Some(l),
+                    Err(e) => {
+                        println!("could not load light script {}: {}", path, e);
+                        return;
+                    }
+                }
+            }
+            "--motion-script" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                let script = fs::read_to_string(path).map_err(|e| e.to_string());
+                motion = match script.and_then(|s| gamepak::MotionScript::parse(&s)) {
+                    Ok(m) => Some(m),
+                    Err(e) => {
+                        println!("could not load motion script {}: {}", path, e);
+                        return;
+                    }
+                }
+            }
+            "--motion-record" => motion_record = Some(args.next().unwrap_or_else(|| usage())),
+            a if a.starts_with("--") => usage(),
+            a => rom_path = a,
+        }
+    }
 
-    let gp = match gamepak::GamePak::load_from_file(rom_path) {
+    let mut gp = match gamepak::GamePak::load_from_file(rom_path) {
         Ok(c) => c,
         Err(e) => {
             println!("Error loading gamepak: {}", e);
@@ -16,19 +131,248 @@ fn main() {
         }
     };
 
-    println!("game title: {} ({})", gp.header().game_title(), gp.header().game_title().len());
-    println!("game code: {} ({})", gp.header().game_code(), gp.header().game_code().len());
-    println!("maker code: {}", gp.header().maker_code());
+    println!("{:?}", gp.header());
+    for problem in gp.header().validate() {
+        println!("warning: {}, real hardware would not boot this rom", problem);
+    }
+    if let Some(id) = gp.library_id() {
+        println!("backup library: {}", id);
+    }
+    if let Some(t) = backup_type {
+        gp.set_backup_type(t);
+    }
+    if let Some(c) = flash_chip {
+        gp.set_flash_chip(c);
+    }
+    if let Some(dir) = save_dir {
+        gp.set_save_dir(Path::new(dir));
+    }
+    match rtc {
+        Some(Some(source)) => {
+            gp.set_rtc(true);
+            gp.set_time_source(source);
+        }
+        Some(None) => gp.set_rtc(false),
+        None => {}
+    }
+    if let Some(l) = light {
+        gp.set_solar_sensor(true);
+        gp.set_light(l);
+    }
+    if let Some(m) = motion {
+        gp.set_motion(m);
+    }
+    if let Some(game) = gp.game() {
+        println!("game: {}", game.title);
+    }
+    if gp.has_rtc() {
+        println!("rtc: {}", gp.time_source());
+    }
+    if gp.has_solar_sensor() {
+        println!("solar sensor");
+    }
+    if gp.has_tilt_sensor() {
+        println!("tilt sensor");
+    }
+    if gp.has_gyro() {
+        println!("gyro sensor");
+    }
+    if gp.has_rumble() {
+        println!("rumble");
+    }
+    println!("backup type: {}", gp.backup_type());
 
     let mut console = gba::GBA::new();
+    if let Some(path) = bios_path {
+        match bios::Bios::load_from_file(path) {
+            Ok(b) => {
+                if let bios::BiosKind::Unknown(crc) = b.kind() {
+                    println!("warning: unknown bios (crc32 {:08x}), it may not be a good dump", crc);
+                }
+                console.load_bios(b);
+            }
+            Err(e) => {
+                println!("Error loading bios: {}", e);
+                return;
+            }
+        }
+    }
+
     match console.load(gp) {
         Ok(_) => println!("loaded console"),
         Err(e) => println!("could not load console: {}", e)
     }
+    if let Some(save) = console.save() {
+        match save.is_loaded() {
+            true => println!("loaded save {}", save.path().display()),
+            false => println!("new save {}", save.path().display()),
+        }
+        if let Some(mismatch) = save.mismatch() {
+            println!("warning: {}, the original was kept as .bak", mismatch);
+        }
+    }
+
+    if motion_record.is_some() {
+        match console.motion_mut() {
+            Some(input) => input.record(),
+            None => println!("warning: the cartridge has no motion sensor to record"),
+        }
+    }
+
+    if let Some((path, format)) = trace {
+        match File::create(path) {
+            Ok(f) => console.set_trace(Some(Trace::new(Box::new(BufWriter::new(f)), format))),
+            Err(e) => println!("could not create trace file: {}", e),
+        }
+    }
+
+    if profile_path.is_some() || heatmap_path.is_some() {
+        console.set_profile(Some(Profile::new()));
+    }
 
-    println!("stepping");
-    console.step();
+    for _ in 0..2 {
+        println!("stepping");
+        if let Err(e) = console.step() {
+            println!("{}", e);
+            break;
+        }
+    }
+
+    console.set_trace(None);
+
+    if let Err(e) = console.flush_save() {
+        println!("{}", e);
+    }
 
-    println!("stepping");
-    console.step()
+    if let Some(path) = motion_record {
+        if let Some(script) = console.motion_mut().and_then(|input| input.recording()) {
+            let result = File::create(path).and_then(|f| {
+                let mut w = BufWriter::new(f);
+                script.write(&mut w)?;
+                io::Write::flush(&mut w)
+            });
+            if let Err(e) = result {
+                println!("could not write motion recording {}: {}", path, e);
+            }
+        }
+    }
+
+    if let Some(p) = console.profile() {
+        if let Some(path) = profile_path {
+            write_profile(path, |w| match path.ends_with(".json") {
+                true => p.write_json(w),
+                false => p.write_csv(w),
+            });
+        }
+        if let Some(path) = heatmap_path {
+            write_profile(path, |w| p.write_heatmap(w));
+        }
+    }
+}
+
+fn write_profile<F>(path: &str, write: F)
+where
+    F: FnOnce(&mut dyn io::Write) -> io::Result<()>,
+{
+    let result = File::create(path).and_then(|f| {
+        let mut w = BufWriter::new(f);
+        write(&mut w)?;
+        io::Write::flush(&mut w)
+    });
+    if let Err(e) = result {
+        println!("could not write profile {}: {}", path, e);
+    }
+}
+
+fn trace_diff(args: &[String]) {
+    let mut paths = Vec::new();
+    let mut context = TRACE_DIFF_CONTEXT;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                context = match args.next().map(|n| n.parse()) {
+                    Some(Ok(n)) => n,
+                    _ => usage(),
+                }
+            }
+            a if a.starts_with("--") => usage(),
+            a => paths.push(a),
+        }
+    }
+
+    if paths.len() != 2 {
+        usage();
+    }
+
+    let open = |path: &str| match tracediff::TraceReader::open(path) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("could not open trace {}: {}", path, e);
+            process::exit(2);
+        }
+    };
+    let mut left = open(paths[0]);
+    let mut right = open(paths[1]);
+
+    match tracediff::diff(&mut left, &mut right, context) {
+        Ok(None) => println!("traces match"),
+        Ok(Some(d)) => {
+            if let Err(e) = d.report(&mut io::stdout()) {
+                println!("could not write report: {}", e);
+            }
+            process::exit(1);
+        }
+        Err(e) => {
+            println!("could not read traces: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn fix_header(args: &[String]) {
+    let mut rom_path = None;
+    let mut output_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output_path = Some(args.next().unwrap_or_else(|| usage())),
+            a if a.starts_with("--") => usage(),
+            a if rom_path.is_none() => rom_path = Some(a),
+            _ => usage(),
+        }
+    }
+
+    let rom_path = rom_path.unwrap_or_else(|| usage());
+    let output_path = output_path.map(|p| p.as_str()).unwrap_or(rom_path);
+
+    let mut data = match fs::read(rom_path) {
+        Ok(d) => d,
+        Err(e) => {
+            println!("could not read rom {}: {}", rom_path, e);
+            process::exit(2);
+        }
+    };
+
+    let problems = match gamepak::header::fix(&mut data) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("could not fix header: {}", e);
+            process::exit(2);
+        }
+    };
+    if problems.is_empty() && output_path == rom_path {
+        println!("header is already valid");
+        return;
+    }
+    for problem in problems.iter() {
+        println!("fixed: {}", problem);
+    }
+
+    if let Err(e) = fs::write(output_path, &data) {
+        println!("could not write rom {}: {}", output_path, e);
+        process::exit(2);
+    }
 }
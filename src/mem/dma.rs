@@ -0,0 +1,162 @@
+use super::io;
+
+pub const CHANNELS: usize = 4;
+
+// Each channel has a source, a destination, a count and a control register,
+// in that order.
+const CHANNEL_SIZE: u32 = 12;
+const REG_SAD: u32 = 0x0;
+const REG_DAD: u32 = 0x4;
+const REG_CNT_L: u32 = 0x8;
+const REG_CNT_H: u32 = 0xA;
+
+const CNT_DST_SHIFT: u16 = 5;
+const CNT_SRC_SHIFT: u16 = 7;
+const CNT_REPEAT: u16 = 0x0200;
+const CNT_WORD: u16 = 0x0400;
+const CNT_TIMING_SHIFT: u16 = 12;
+const CNT_IRQ: u16 = 0x4000;
+pub const CNT_ENABLE: u16 = 0x8000;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum AddrControl {
+    Increment,
+    Decrement,
+    Fixed,
+    // Increments, and goes back to the start when the transfer repeats.
+    Reload,
+}
+
+impl AddrControl {
+    fn from_bits(bits: u16) -> AddrControl {
+        match bits & 0x3 {
+            0 => AddrControl::Increment,
+            1 => AddrControl::Decrement,
+            2 => AddrControl::Fixed,
+            _ => AddrControl::Reload,
+        }
+    }
+
+    pub fn step(&self, addr: u32, size: u32) -> u32 {
+        match self {
+            AddrControl::Increment | AddrControl::Reload => addr.wrapping_add(size),
+            AddrControl::Decrement => addr.wrapping_sub(size),
+            AddrControl::Fixed => addr,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Timing {
+    Immediate,
+    VBlank,
+    HBlank,
+    Special,
+}
+
+// A DMA transfer, as set up in a channel's registers.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Transfer {
+    pub channel: usize,
+    pub src: u32,
+    pub dst: u32,
+    pub count: u32,
+    pub word: bool,
+    pub src_ctrl: AddrControl,
+    pub dst_ctrl: AddrControl,
+    pub timing: Timing,
+    pub repeat: bool,
+    pub irq: bool,
+}
+
+impl Transfer {
+    pub fn from_io(io: &io::Io, channel: usize) -> Transfer {
+        let base = io::REG_DMA0SAD + channel as u32 * CHANNEL_SIZE;
+        let word = |offset| io.get(base + offset) as u32 | (io.get(base + offset + 2) as u32) << 16;
+        let cnt = io.get(base + REG_CNT_H);
+
+        // A count of 0 is the most the channel can do, which is more on
+        // DMA3.
+        let count = match (io.get(base + REG_CNT_L), channel) {
+            (0, 3) => 0x1_0000,
+            (0, _) => 0x4000,
+            (n, _) => n as u32,
+        };
+
+        Transfer {
+            channel,
+            src: word(REG_SAD),
+            dst: word(REG_DAD),
+            count,
+            word: cnt & CNT_WORD != 0,
+            src_ctrl: AddrControl::from_bits(cnt >> CNT_SRC_SHIFT),
+            dst_ctrl: AddrControl::from_bits(cnt >> CNT_DST_SHIFT),
+            timing: match (cnt >> CNT_TIMING_SHIFT) & 0x3 {
+                0 => Timing::Immediate,
+                1 => Timing::VBlank,
+                2 => Timing::HBlank,
+                _ => Timing::Special,
+            },
+            repeat: cnt & CNT_REPEAT != 0,
+            irq: cnt & CNT_IRQ != 0,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        match self.word {
+            true => 4,
+            false => 2,
+        }
+    }
+}
+
+// Returns the channel whose control register is at offset, if any.
+pub fn control_channel(offset: u32) -> Option<usize> {
+    let rel = offset.checked_sub(io::REG_DMA0SAD)?;
+    match rel % CHANNEL_SIZE == REG_CNT_H && ((rel / CHANNEL_SIZE) as usize) < CHANNELS {
+        true => Some((rel / CHANNEL_SIZE) as usize),
+        false => None,
+    }
+}
+
+pub fn control_reg(channel: usize) -> u32 {
+    io::REG_DMA0SAD + channel as u32 * CHANNEL_SIZE + REG_CNT_H
+}
+
+#[cfg(test)]
+mod tests {
+    mod transfer {
+        use super::super::*;
+
+        #[test]
+        fn from_io() {
+            let mut io = io::Io::new();
+            io.set(io::REG_DMA3SAD, 0x1234);
+            io.set(io::REG_DMA3SAD + 2, 0x0800);
+            io.set(io::REG_DMA3SAD + 4, 0x0000);
+            io.set(io::REG_DMA3SAD + 6, 0x0300);
+            io.set(io::REG_DMA3SAD + 8, 0);
+            io.set(io::REG_DMA3SAD + 10, 0x84C0);
+
+            let t = Transfer::from_io(&io, 3);
+            assert_eq!(0x0800_1234, t.src);
+            assert_eq!(0x0300_0000, t.dst);
+            assert_eq!(0x1_0000, t.count);
+            assert!(t.word);
+            assert_eq!(AddrControl::Decrement, t.src_ctrl);
+            assert_eq!(AddrControl::Fixed, t.dst_ctrl);
+            assert_eq!(Timing::Immediate, t.timing);
+            assert_eq!(4, t.size());
+        }
+
+        #[test]
+        fn control_registers() {
+            assert_eq!(Some(0), control_channel(0x0BA));
+            assert_eq!(Some(3), control_channel(0x0DE));
+            assert_eq!(None, control_channel(0x0DC));
+            assert_eq!(None, control_channel(0x0EA));
+            assert_eq!(None, control_channel(0x004));
+            assert_eq!(0x0DE, control_reg(3));
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use std::fmt;
+
+// Registers are all halfword sized and live in the first 1 KiB of IO.
+pub const IO_SIZE: usize = 0x400;
+const REG_COUNT: usize = IO_SIZE / 2;
+
+pub const REG_DISPCNT: u32 = 0x000;
+pub const REG_DISPSTAT: u32 = 0x004;
+pub const REG_VCOUNT: u32 = 0x006;
+pub const REG_DMA0SAD: u32 = 0x0B0;
+pub const REG_DMA3SAD: u32 = 0x0D4;
+pub const REG_TM0CNT_L: u32 = 0x100;
+pub const REG_KEYINPUT: u32 = 0x130;
+pub const REG_IE: u32 = 0x200;
+pub const REG_IF: u32 = 0x202;
+pub const REG_WAITCNT: u32 = 0x204;
+pub const REG_IME: u32 = 0x208;
+pub const REG_POSTFLG: u32 = 0x300;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Device {
+    Ppu = 0,
+    Apu = 1,
+    Timer = 2,
+    Dma = 3,
+    Keypad = 4,
+    Serial = 5,
+    Interrupt = 6,
+    System = 7,
+}
+
+const DEVICE_COUNT: usize = 8;
+
+// Something behind the IO registers, like the PPU or a timer. Devices see
+// every write to the registers they own and can supply the value of a
+// register when it is read, for registers like VCOUNT or the timer counters
+// that change on their own.
+pub trait IoDevice {
+    // Called after a register owned by the device is written, with the value
+    // of the whole register before and after the write. Bits the CPU
+    // acknowledged in a clear-on-write register are the ones that went from
+    // set to clear.
+    fn write(&mut self, offset: u32, old: u16, val: u16);
+
+    // Returns the current value of a register owned by the device, or None
+    // to read back the value last written to it.
+    fn read(&self, _offset: u32) -> Option<u16> {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Register {
+    pub offset: u32,
+    pub name: &'static str,
+    pub device: Device,
+    // Bits that read back, or None for write-only registers, which read as
+    // open bus. Unused bits of readable registers read as zero.
+    pub read: Option<u16>,
+    pub write: u16,
+    pub init: u16,
+    // Bits that are acknowledged by writing a one, like IF.
+    pub clear_on_write: bool,
+}
+
+impl Register {
+    const fn rw(offset: u32, name: &'static str, device: Device, read: u16, write: u16) -> Register {
+        Register {
+            offset,
+            name,
+            device,
+            read: Some(read),
+            write,
+            init: 0,
+            clear_on_write: false,
+        }
+    }
+
+    const fn wo(offset: u32, name: &'static str, device: Device, write: u16) -> Register {
+        Register {
+            offset,
+            name,
+            device,
+            read: None,
+            write,
+            init: 0,
+            clear_on_write: false,
+        }
+    }
+
+    const fn init(mut self, init: u16) -> Register {
+        self.init = init;
+        self
+    }
+
+    const fn clear_on_write(mut self) -> Register {
+        self.clear_on_write = true;
+        self
+    }
+}
+
+use Device::*;
+
+const REGISTERS: &[Register] = &[
+    // LCD
+    Register::rw(0x000, "DISPCNT", Ppu, 0xFFFF, 0xFFF7),
+    Register::rw(0x002, "GREENSWAP", Ppu, 0x0001, 0x0001),
+    Register::rw(0x004, "DISPSTAT", Ppu, 0xFF3F, 0xFF38),
+    Register::rw(0x006, "VCOUNT", Ppu, 0x00FF, 0x0000),
+    Register::rw(0x008, "BG0CNT", Ppu, 0xDFFF, 0xDFFF),
+    Register::rw(0x00A, "BG1CNT", Ppu, 0xDFFF, 0xDFFF),
+    Register::rw(0x00C, "BG2CNT", Ppu, 0xFFFF, 0xFFFF),
+    Register::rw(0x00E, "BG3CNT", Ppu, 0xFFFF, 0xFFFF),
+    Register::wo(0x010, "BG0HOFS", Ppu, 0x01FF),
+    Register::wo(0x012, "BG0VOFS", Ppu, 0x01FF),
+    Register::wo(0x014, "BG1HOFS", Ppu, 0x01FF),
+    Register::wo(0x016, "BG1VOFS", Ppu, 0x01FF),
+    Register::wo(0x018, "BG2HOFS", Ppu, 0x01FF),
+    Register::wo(0x01A, "BG2VOFS", Ppu, 0x01FF),
+    Register::wo(0x01C, "BG3HOFS", Ppu, 0x01FF),
+    Register::wo(0x01E, "BG3VOFS", Ppu, 0x01FF),
+    Register::wo(0x020, "BG2PA", Ppu, 0xFFFF).init(0x0100),
+    Register::wo(0x022, "BG2PB", Ppu, 0xFFFF),
+    Register::wo(0x024, "BG2PC", Ppu, 0xFFFF),
+    Register::wo(0x026, "BG2PD", Ppu, 0xFFFF).init(0x0100),
+    Register::wo(0x028, "BG2X_L", Ppu, 0xFFFF),
+    Register::wo(0x02A, "BG2X_H", Ppu, 0x0FFF),
+    Register::wo(0x02C, "BG2Y_L", Ppu, 0xFFFF),
+    Register::wo(0x02E, "BG2Y_H", Ppu, 0x0FFF),
+    Register::wo(0x030, "BG3PA", Ppu, 0xFFFF).init(0x0100),
+    Register::wo(0x032, "BG3PB", Ppu, 0xFFFF),
+    Register::wo(0x034, "BG3PC", Ppu, 0xFFFF),
+    Register::wo(0x036, "BG3PD", Ppu, 0xFFFF).init(0x0100),
+    Register::wo(0x038, "BG3X_L", Ppu, 0xFFFF),
+    Register::wo(0x03A, "BG3X_H", Ppu, 0x0FFF),
+    Register::wo(0x03C, "BG3Y_L", Ppu, 0xFFFF),
+    Register::wo(0x03E, "BG3Y_H", Ppu, 0x0FFF),
+    Register::wo(0x040, "WIN0H", Ppu, 0xFFFF),
+    Register::wo(0x042, "WIN1H", Ppu, 0xFFFF),
+    Register::wo(0x044, "WIN0V", Ppu, 0xFFFF),
+    Register::wo(0x046, "WIN1V", Ppu, 0xFFFF),
+    Register::rw(0x048, "WININ", Ppu, 0x3F3F, 0x3F3F),
+    Register::rw(0x04A, "WINOUT", Ppu, 0x3F3F, 0x3F3F),
+    Register::wo(0x04C, "MOSAIC", Ppu, 0xFFFF),
+    Register::rw(0x050, "BLDCNT", Ppu, 0x3FFF, 0x3FFF),
+    Register::rw(0x052, "BLDALPHA", Ppu, 0x1F1F, 0x1F1F),
+    Register::wo(0x054, "BLDY", Ppu, 0x001F),
+    // Sound
+    Register::rw(0x060, "SOUND1CNT_L", Apu, 0x007F, 0x007F),
+    Register::rw(0x062, "SOUND1CNT_H", Apu, 0xFFC0, 0xFFFF),
+    Register::rw(0x064, "SOUND1CNT_X", Apu, 0x4000, 0xC7FF),
+    Register::rw(0x066, "SOUND1CNT_X_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x068, "SOUND2CNT_L", Apu, 0xFFC0, 0xFFFF),
+    Register::rw(0x06A, "SOUND2CNT_L_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x06C, "SOUND2CNT_H", Apu, 0x4000, 0xC7FF),
+    Register::rw(0x06E, "SOUND2CNT_H_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x070, "SOUND3CNT_L", Apu, 0x00E0, 0x00E0),
+    Register::rw(0x072, "SOUND3CNT_H", Apu, 0xE000, 0xE0FF),
+    Register::rw(0x074, "SOUND3CNT_X", Apu, 0x4000, 0xC7FF),
+    Register::rw(0x076, "SOUND3CNT_X_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x078, "SOUND4CNT_L", Apu, 0xFF00, 0xFF3F),
+    Register::rw(0x07A, "SOUND4CNT_L_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x07C, "SOUND4CNT_H", Apu, 0x40FF, 0xC0FF),
+    Register::rw(0x07E, "SOUND4CNT_H_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x080, "SOUNDCNT_L", Apu, 0xFF77, 0xFF77),
+    Register::rw(0x082, "SOUNDCNT_H", Apu, 0x770F, 0xFF0F),
+    Register::rw(0x084, "SOUNDCNT_X", Apu, 0x008F, 0x0080),
+    Register::rw(0x086, "SOUNDCNT_X_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x088, "SOUNDBIAS", Apu, 0xC3FE, 0xC3FE).init(0x0200),
+    Register::rw(0x08A, "SOUNDBIAS_H", Apu, 0x0000, 0x0000),
+    Register::rw(0x090, "WAVE_RAM0_L", Apu, 0xFFFF, 0xFFFF),
+    Register::rw(0x092, "WAVE_RAM0_H", Apu, 0xFFFF, 0xFFFF),
+    Register::rw(0x094, "WAVE_RAM1_L", Apu, 0xFFFF, 0xFFFF),
+    Register::rw(0x096, "WAVE_RAM1_H", Apu, 0xFFFF, 0xFFFF),
+    Register::rw(0x098, "WAVE_RAM2_L", Apu, 0xFFFF, 0xFFFF),
+    Register::rw(0x09A, "WAVE_RAM2_H", Apu, 0xFFFF, 0xFFFF),
+    Register::rw(0x09C, "WAVE_RAM3_L", Apu, 0xFFFF, 0xFFFF),
+    Register::rw(0x09E, "WAVE_RAM3_H", Apu, 0xFFFF, 0xFFFF),
+    Register::wo(0x0A0, "FIFO_A_L", Apu, 0xFFFF),
+    Register::wo(0x0A2, "FIFO_A_H", Apu, 0xFFFF),
+    Register::wo(0x0A4, "FIFO_B_L", Apu, 0xFFFF),
+    Register::wo(0x0A6, "FIFO_B_H", Apu, 0xFFFF),
+    // DMA
+    Register::wo(0x0B0, "DMA0SAD_L", Dma, 0xFFFF),
+    Register::wo(0x0B2, "DMA0SAD_H", Dma, 0x07FF),
+    Register::wo(0x0B4, "DMA0DAD_L", Dma, 0xFFFF),
+    Register::wo(0x0B6, "DMA0DAD_H", Dma, 0x07FF),
+    Register::rw(0x0B8, "DMA0CNT_L", Dma, 0x0000, 0x3FFF),
+    Register::rw(0x0BA, "DMA0CNT_H", Dma, 0xF7E0, 0xF7E0),
+    Register::wo(0x0BC, "DMA1SAD_L", Dma, 0xFFFF),
+    Register::wo(0x0BE, "DMA1SAD_H", Dma, 0x0FFF),
+    Register::wo(0x0C0, "DMA1DAD_L", Dma, 0xFFFF),
+    Register::wo(0x0C2, "DMA1DAD_H", Dma, 0x07FF),
+    Register::rw(0x0C4, "DMA1CNT_L", Dma, 0x0000, 0x3FFF),
+    Register::rw(0x0C6, "DMA1CNT_H", Dma, 0xF7E0, 0xF7E0),
+    Register::wo(0x0C8, "DMA2SAD_L", Dma, 0xFFFF),
+    Register::wo(0x0CA, "DMA2SAD_H", Dma, 0x0FFF),
+    Register::wo(0x0CC, "DMA2DAD_L", Dma, 0xFFFF),
+    Register::wo(0x0CE, "DMA2DAD_H", Dma, 0x07FF),
+    Register::rw(0x0D0, "DMA2CNT_L", Dma, 0x0000, 0x3FFF),
+    Register::rw(0x0D2, "DMA2CNT_H", Dma, 0xF7E0, 0xF7E0),
+    Register::wo(0x0D4, "DMA3SAD_L", Dma, 0xFFFF),
+    Register::wo(0x0D6, "DMA3SAD_H", Dma, 0x0FFF),
+    Register::wo(0x0D8, "DMA3DAD_L", Dma, 0xFFFF),
+    Register::wo(0x0DA, "DMA3DAD_H", Dma, 0x0FFF),
+    Register::rw(0x0DC, "DMA3CNT_L", Dma, 0x0000, 0xFFFF),
+    Register::rw(0x0DE, "DMA3CNT_H", Dma, 0xFFE0, 0xFFE0),
+    // Timers
+    Register::rw(0x100, "TM0CNT_L", Timer, 0xFFFF, 0xFFFF),
+    Register::rw(0x102, "TM0CNT_H", Timer, 0x00C7, 0x00C7),
+    Register::rw(0x104, "TM1CNT_L", Timer, 0xFFFF, 0xFFFF),
+    Register::rw(0x106, "TM1CNT_H", Timer, 0x00C7, 0x00C7),
+    Register::rw(0x108, "TM2CNT_L", Timer, 0xFFFF, 0xFFFF),
+    Register::rw(0x10A, "TM2CNT_H", Timer, 0x00C7, 0x00C7),
+    Register::rw(0x10C, "TM3CNT_L", Timer, 0xFFFF, 0xFFFF),
+    Register::rw(0x10E, "TM3CNT_H", Timer, 0x00C7, 0x00C7),
+    // Serial
+    Register::rw(0x120, "SIOMULTI0", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x122, "SIOMULTI1", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x124, "SIOMULTI2", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x126, "SIOMULTI3", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x128, "SIOCNT", Serial, 0x7FFF, 0x7FFF),
+    Register::rw(0x12A, "SIOMLT_SEND", Serial, 0xFFFF, 0xFFFF),
+    // Keypad
+    Register::rw(0x130, "KEYINPUT", Keypad, 0x03FF, 0x0000).init(0x03FF),
+    Register::rw(0x132, "KEYCNT", Keypad, 0xC3FF, 0xC3FF),
+    // Serial
+    Register::rw(0x134, "RCNT", Serial, 0xC1FF, 0xC1FF),
+    Register::rw(0x136, "IR", Serial, 0x0000, 0x0000),
+    Register::rw(0x140, "JOYCNT", Serial, 0x0040, 0x0040),
+    Register::rw(0x142, "JOYCNT_H", Serial, 0x0000, 0x0000),
+    Register::rw(0x150, "JOY_RECV_L", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x152, "JOY_RECV_H", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x154, "JOY_TRANS_L", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x156, "JOY_TRANS_H", Serial, 0xFFFF, 0xFFFF),
+    Register::rw(0x158, "JOYSTAT", Serial, 0x003A, 0x0030),
+    Register::rw(0x15A, "JOYSTAT_H", Serial, 0x0000, 0x0000),
+    // Interrupts, wait states and power
+    Register::rw(0x200, "IE", Interrupt, 0x3FFF, 0x3FFF),
+    Register::rw(0x202, "IF", Interrupt, 0x3FFF, 0x3FFF).clear_on_write(),
+    Register::rw(0x204, "WAITCNT", System, 0x5FFF, 0x5FFF),
+    Register::rw(0x206, "WAITCNT_H", System, 0x0000, 0x0000),
+    Register::rw(0x208, "IME", Interrupt, 0x0001, 0x0001),
+    Register::rw(0x20A, "IME_H", Interrupt, 0x0000, 0x0000),
+    // POSTFLG is the low byte, HALTCNT the write-only high byte.
+    Register::rw(0x300, "POSTFLG", System, 0x0001, 0xFF01),
+    Register::rw(0x302, "POSTFLG_H", System, 0x0000, 0x0000),
+];
+
+pub struct Io {
+    regs: [u16; REG_COUNT],
+    lookup: [Option<usize>; REG_COUNT],
+    devices: Vec<Option<Box<dyn IoDevice>>>,
+}
+
+impl Io {
+    pub fn new() -> Io {
+        let mut io = Io {
+            regs: [0; REG_COUNT],
+            lookup: [None; REG_COUNT],
+            devices: (0..DEVICE_COUNT).map(|_| None).collect(),
+        };
+
+        for (idx, reg) in REGISTERS.iter().enumerate() {
+            io.lookup[(reg.offset / 2) as usize] = Some(idx);
+            io.regs[(reg.offset / 2) as usize] = reg.init;
+        }
+
+        io
+    }
+
+    // Routes the registers belonging to device through the given handler,
+    // replacing whatever was attached before.
+    pub fn attach(&mut self, device: Device, handler: Box<dyn IoDevice>) {
+        self.devices[device as usize] = Some(handler);
+    }
+
+    pub fn detach(&mut self, device: Device) -> Option<Box<dyn IoDevice>> {
+        self.devices[device as usize].take()
+    }
+
+    pub fn register(&self, offset: u32) -> Option<&'static Register> {
+        match offset < IO_SIZE as u32 {
+            true => self.lookup[(offset / 2) as usize].map(|idx| &REGISTERS[idx]),
+            false => None,
+        }
+    }
+
+    // Reads the halfword register at offset as the CPU sees it, or None if
+    // nothing readable is there and the read should see open bus instead.
+    pub fn read16(&self, offset: u32) -> Option<u16> {
+        let reg = self.register(offset)?;
+        let mask = reg.read?;
+
+        let val = match &self.devices[reg.device as usize] {
+            Some(device) => device.read(reg.offset).unwrap_or_else(|| self.get(reg.offset)),
+            None => self.get(reg.offset),
+        };
+        Some(val & mask)
+    }
+
+    // Writes the bits of val selected by mask to the register at offset.
+    // Byte writes only select the half of the register being written to.
+    pub fn write16(&mut self, offset: u32, val: u16, mask: u16) {
+        let reg = match self.register(offset) {
+            Some(reg) => reg,
+            None => return,
+        };
+
+        let idx = (reg.offset / 2) as usize;
+        let mask = mask & reg.write;
+        let old = self.regs[idx];
+        match reg.clear_on_write {
+            true => self.regs[idx] &= !(val & mask),
+            false => self.regs[idx] = (self.regs[idx] & !mask) | (val & mask),
+        }
+
+        let val = self.regs[idx];
+        if let Some(device) = self.devices[reg.device as usize].as_mut() {
+            device.write(reg.offset, old, val);
+        }
+    }
+
+    // Reads and writes the stored value of a register directly, ignoring its
+    // masks. This is how devices update registers the CPU can't write, like
+    // VCOUNT or KEYINPUT.
+    pub fn get(&self, offset: u32) -> u16 {
+        match offset < IO_SIZE as u32 {
+            true => self.regs[(offset / 2) as usize],
+            false => 0,
+        }
+    }
+
+    pub fn set(&mut self, offset: u32, val: u16) {
+        if offset < IO_SIZE as u32 {
+            self.regs[(offset / 2) as usize] = val;
+        }
+    }
+}
+
+impl Default for Io {
+    fn default() -> Io {
+        Io::new()
+    }
+}
+
+impl fmt::Debug for Io {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for reg in REGISTERS.iter() {
+            writeln!(f, "{:03x} {:<14}{:04x}", reg.offset, reg.name, self.get(reg.offset))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod io {
+        use super::super::*;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct TestDevice {
+            writes: Rc<RefCell<Vec<(u32, u16, u16)>>>,
+        }
+
+        impl IoDevice for TestDevice {
+            fn write(&mut self, offset: u32, old: u16, val: u16) {
+                self.writes.borrow_mut().push((offset, old, val));
+            }
+
+            fn read(&self, offset: u32) -> Option<u16> {
+                match offset {
+                    REG_VCOUNT => Some(0x1234),
+                    _ => None,
+                }
+            }
+        }
+
+        #[test]
+        fn new() {
+            let io = Io::new();
+            assert_eq!(0x03FF, io.get(REG_KEYINPUT));
+            assert_eq!(0x0200, io.get(0x088));
+            assert_eq!(0, io.get(REG_DISPCNT));
+        }
+
+        #[test]
+        fn read_write() {
+            let mut io = Io::new();
+            io.write16(REG_IE, 0x1234, 0xFFFF);
+            assert_eq!(Some(0x1234), io.read16(REG_IE));
+        }
+
+        #[test]
+        fn read_unused_bits() {
+            let mut io = Io::new();
+            io.write16(REG_IE, 0xFFFF, 0xFFFF);
+            assert_eq!(Some(0x3FFF), io.read16(REG_IE));
+
+            assert_eq!(Some(0), io.read16(0x206));
+        }
+
+        #[test]
+        fn read_write_only() {
+            let mut io = Io::new();
+            io.write16(0x010, 0x0123, 0xFFFF);
+
+            assert_eq!(None, io.read16(0x010));
+            assert_eq!(0x0123, io.get(0x010));
+        }
+
+        #[test]
+        fn read_unmapped() {
+            let io = Io::new();
+            assert_eq!(None, io.read16(0x056));
+            assert_eq!(None, io.read16(0x3FE));
+            assert_eq!(None, io.read16(0x400));
+        }
+
+        #[test]
+        fn write_read_only() {
+            let mut io = Io::new();
+            io.write16(REG_KEYINPUT, 0, 0xFFFF);
+            io.write16(REG_DISPSTAT, 0xFFFF, 0xFFFF);
+
+            assert_eq!(Some(0x03FF), io.read16(REG_KEYINPUT));
+            assert_eq!(Some(0xFF38), io.read16(REG_DISPSTAT));
+        }
+
+        #[test]
+        fn write_byte() {
+            let mut io = Io::new();
+            io.write16(REG_IE, 0x1234, 0xFFFF);
+            io.write16(REG_IE, 0xAB00, 0xFF00);
+
+            assert_eq!(Some(0x2B34), io.read16(REG_IE));
+        }
+
+        #[test]
+        fn write_clear_on_write() {
+            let mut io = Io::new();
+            io.set(REG_IF, 0x000F);
+            io.write16(REG_IF, 0x0005, 0xFFFF);
+
+            assert_eq!(Some(0x000A), io.read16(REG_IF));
+        }
+
+        #[test]
+        fn attach() {
+            let writes = Rc::new(RefCell::new(Vec::new()));
+            let mut io = Io::new();
+            io.attach(Device::Ppu, Box::new(TestDevice { writes: writes.clone() }));
+
+            io.write16(REG_DISPCNT, 0x0403, 0xFFFF);
+            io.write16(REG_IE, 0x0001, 0xFFFF);
+
+            assert_eq!(vec![(REG_DISPCNT, 0, 0x0403)], *writes.borrow());
+            assert_eq!(Some(0x34), io.read16(REG_VCOUNT));
+            assert_eq!(Some(0x0403), io.read16(REG_DISPCNT));
+        }
+
+        #[test]
+        fn attach_clear_on_write() {
+            let writes = Rc::new(RefCell::new(Vec::new()));
+            let mut io = Io::new();
+            io.attach(Device::Interrupt, Box::new(TestDevice { writes: writes.clone() }));
+            io.set(REG_IF, 0x000F);
+
+            io.write16(REG_IF, 0x0005, 0xFFFF);
+            io.write16(REG_IF, 0x0300, 0xFF00);
+
+            assert_eq!(vec![(REG_IF, 0x000F, 0x000A), (REG_IF, 0x000A, 0x000A)], *writes.borrow());
+        }
+
+        #[test]
+        fn detach() {
+            let writes = Rc::new(RefCell::new(Vec::new()));
+            let mut io = Io::new();
+            io.attach(Device::Ppu, Box::new(TestDevice { writes: writes.clone() }));
+            assert!(io.detach(Device::Ppu).is_some());
+
+            io.write16(REG_DISPCNT, 0x0403, 0xFFFF);
+            assert!(writes.borrow().is_empty());
+        }
+    }
+}
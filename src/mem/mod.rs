@@ -1,6 +1,12 @@
-use std::collections::BTreeMap;
+pub mod dma;
+pub mod io;
+pub mod profile;
+pub mod timing;
+pub mod watch;
 
-// TODO Mirroring support in memory
+use watch::{Access, AccessKind};
+
+use crate::gamepak::{Backup, Eeprom, Gpio, TiltSensor};
 
 const KBYTE: usize = 1024;
 
@@ -14,110 +20,908 @@ pub const INT_WRAM: u32 = 0x03_00_00_00;
 const INT_WRAM_SIZE: usize = 32 * KBYTE;
 
 pub const IORAM: u32 = 0x04_00_00_00;
-const IORAM_SIZE: usize = 1 * KBYTE;
+
+// The internal memory control register sits on its own past the rest of IO
+// and is the only IO register that is mirrored, every 64 KiB.
+pub const IO_MEM_CTRL: u32 = 0x04_00_08_00;
+const IO_MEM_CTRL_SIZE: usize = 4;
+const IO_MEM_CTRL_MIRROR: u32 = 0x00_00_FF_FC;
 
 pub const PAL_RAM: u32 = 0x05_00_00_00;
+#[allow(clippy::identity_op)]
 const PAL_RAM_SIZE: usize = 1 * KBYTE;
 
 pub const VRAM: u32 = 0x06_00_00_00;
 const VRAM_SIZE: usize = 96 * KBYTE;
+// VRAM is mirrored every 128 KiB, but only 96 KiB of that exists. The last
+// 32 KiB of each mirror repeats the 32 KiB of OBJ tiles before it.
+const VRAM_MIRROR_SIZE: usize = 128 * KBYTE;
+const VRAM_OBJ_SIZE: usize = 32 * KBYTE;
+// OBJ tiles start after the BG data, which takes up more of VRAM in the
+// bitmap modes (3-5) than in the tile modes.
+const VRAM_OBJ_TILE_MODE: usize = 64 * KBYTE;
+const VRAM_OBJ_BITMAP_MODE: usize = 80 * KBYTE;
 
 pub const OAM: u32 = 0x07_00_00_00;
+#[allow(clippy::identity_op)]
 const OAM_SIZE: usize = 1 * KBYTE;
 
+// The same ROM is mapped into three 32 MiB windows, each with its own wait
+// state settings.
 pub const PAK_ROM: u32 = 0x08_00_00_00;
 pub const PAK_ROM1: u32 = 0x0A_00_00_00;
 pub const PAK_ROM2: u32 = 0x0C_00_00_00;
+pub const PAK_ROM_SIZE: usize = 32 * KBYTE * KBYTE;
 
+// Save memory sits in a 64 KiB window, mirrored over the last 32 MiB of
+// the address space.
 pub const PAK_RAM: u32 = 0x0E_00_00_00;
 const PAK_RAM_SIZE: usize = 64 * KBYTE;
 
+// EEPROM answers in the last ROM window in place of the ROM, from 0x0D000000
+// up, or only from 0x0DFFFF00 up when the ROM is too big to leave room.
+const EEPROM_START: usize = 16 * KBYTE * KBYTE;
+const EEPROM_START_LARGE_ROM: usize = PAK_ROM_SIZE - 0x100;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Region {
+    SysRom,
+    ExtWram,
+    IntWram,
+    Io,
+    IoMemCtrl,
+    PalRam,
+    Vram,
+    Oam,
+    PakRom(usize),
+    PakRam,
+    Unmapped,
+}
+
+impl Region {
+    pub fn base(&self) -> u32 {
+        match self {
+            Region::SysRom => SYS_ROM,
+            Region::ExtWram => EXT_WRAM,
+            Region::IntWram => INT_WRAM,
+            Region::Io => IORAM,
+            Region::IoMemCtrl => IO_MEM_CTRL,
+            Region::PalRam => PAL_RAM,
+            Region::Vram => VRAM,
+            Region::Oam => OAM,
+            Region::PakRom(0) => PAK_ROM,
+            Region::PakRom(1) => PAK_ROM1,
+            Region::PakRom(_) => PAK_ROM2,
+            Region::PakRam => PAK_RAM,
+            Region::Unmapped => 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::SysRom => "bios",
+            Region::ExtWram => "ewram",
+            Region::IntWram => "iwram",
+            Region::Io => "io",
+            Region::IoMemCtrl => "io_mem_ctrl",
+            Region::PalRam => "palette",
+            Region::Vram => "vram",
+            Region::Oam => "oam",
+            Region::PakRom(0) => "rom0",
+            Region::PakRom(1) => "rom1",
+            Region::PakRom(_) => "rom2",
+            Region::PakRam => "pak_ram",
+            Region::Unmapped => "unmapped",
+        }
+    }
+}
+
+// A write the bus dropped because nothing at its address can be written,
+// such as a write to ROM, the BIOS or unmapped memory. The value holds the
+// bytes written in its low bits.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct IgnoredWrite {
+    pub addr: u32,
+    pub size: usize,
+    pub val: u32,
+}
+
+pub type IgnoredWriteHook = Box<dyn FnMut(&IgnoredWrite)>;
+
+// Every region of the address map starts on a 16 MiB boundary, so the top
+// eight bits of an address are enough to find which region it is in.
+const PAGE_SHIFT: u32 = 24;
+const PAGE_COUNT: usize = 256;
+
+const PAGES: [Region; PAGE_COUNT] = Memory::new_pages();
+
 pub struct Memory {
-    blocks: BTreeMap<u32, Block>,
+    sys_rom: Block,
+    ext_wram: Block,
+    int_wram: Block,
+    io: io::Io,
+    io_mem_ctrl: Block,
+    pal_ram: Block,
+    vram: Block,
+    oam: Block,
+    pak_rom: Block,
+    backup: Backup,
+    gpio: Option<Gpio>,
+    tilt: Option<TiltSensor>,
+
+    wait: timing::WaitControl,
+    prefetch: timing::Prefetch,
+    cycles: u64,
+    next_seq: u32,
+
+    // The BIOS can only be read while code is running from it. Other reads
+    // see the last opcode fetched from the BIOS instead.
+    exec_bios: bool,
+    bios_latch: u32,
+
+    // What a read sees when nothing drives the bus, which is whatever the
+    // CPU prefetched last.
+    open_bus: u32,
+
+    ignored_write_hook: Option<IgnoredWriteHook>,
+
+    // Watchpoints are checked on every access, so the accessors skip them
+    // entirely unless there are any. pc is only kept to report to them.
+    observers: watch::Observers,
+    pc: u32,
+
+    profile: Option<Box<profile::Profile>>,
 }
 
 impl Memory {
     pub fn new() -> Memory {
-        Memory { blocks: Memory::new_blocks() }
+        Memory {
+            sys_rom: Block::new(SYS_ROM_SIZE),
+            ext_wram: Block::new(EXT_WRAM_SIZE),
+            int_wram: Block::new(INT_WRAM_SIZE),
+            io: io::Io::new(),
+            io_mem_ctrl: Block::new(IO_MEM_CTRL_SIZE),
+            pal_ram: Block::new(PAL_RAM_SIZE),
+            vram: Block::new(VRAM_SIZE),
+            oam: Block::new(OAM_SIZE),
+            pak_rom: Block::new(0),
+            backup: Backup::None,
+            gpio: None,
+            tilt: None,
+
+            wait: timing::WaitControl::new(),
+            prefetch: timing::Prefetch::new(),
+            cycles: 0,
+            next_seq: 0,
+
+            exec_bios: false,
+            bios_latch: 0,
+
+            open_bus: 0,
+
+            ignored_write_hook: None,
+
+            observers: watch::Observers::new(),
+            pc: 0,
+
+            profile: None,
+        }
     }
 
-    fn new_blocks() -> BTreeMap<u32, Block> {
-        let mut blocks = BTreeMap::new();
+    const fn new_pages() -> [Region; PAGE_COUNT] {
+        let mut pages = [Region::Unmapped; PAGE_COUNT];
+
+        pages[(SYS_ROM >> PAGE_SHIFT) as usize] = Region::SysRom;
 
-        blocks.insert(SYS_ROM, Block::new(SYS_ROM_SIZE));
+        pages[(EXT_WRAM >> PAGE_SHIFT) as usize] = Region::ExtWram;
+        pages[(INT_WRAM >> PAGE_SHIFT) as usize] = Region::IntWram;
 
-        blocks.insert(EXT_WRAM, Block::new(EXT_WRAM_SIZE));
-        blocks.insert(INT_WRAM, Block::new(INT_WRAM_SIZE));
+        pages[(IORAM >> PAGE_SHIFT) as usize] = Region::Io;
+        pages[(PAL_RAM >> PAGE_SHIFT) as usize] = Region::PalRam;
+        pages[(VRAM >> PAGE_SHIFT) as usize] = Region::Vram;
+        pages[(OAM >> PAGE_SHIFT) as usize] = Region::Oam;
 
-        blocks.insert(IORAM, Block::new(IORAM_SIZE));
-        blocks.insert(PAL_RAM, Block::new(PAL_RAM_SIZE));
-        blocks.insert(VRAM, Block::new(VRAM_SIZE));
-        blocks.insert(OAM, Block::new(OAM_SIZE));
+        pages[(PAK_ROM >> PAGE_SHIFT) as usize] = Region::PakRom(0);
+        pages[(PAK_ROM >> PAGE_SHIFT) as usize + 1] = Region::PakRom(0);
+        pages[(PAK_ROM1 >> PAGE_SHIFT) as usize] = Region::PakRom(1);
+        pages[(PAK_ROM1 >> PAGE_SHIFT) as usize + 1] = Region::PakRom(1);
+        pages[(PAK_ROM2 >> PAGE_SHIFT) as usize] = Region::PakRom(2);
+        pages[(PAK_ROM2 >> PAGE_SHIFT) as usize + 1] = Region::PakRom(2);
 
-        blocks.insert(PAK_RAM, Block::new(PAK_RAM_SIZE));
+        pages[(PAK_RAM >> PAGE_SHIFT) as usize] = Region::PakRam;
+        pages[(PAK_RAM >> PAGE_SHIFT) as usize + 1] = Region::PakRam;
 
-        blocks
+        pages
     }
 
+    pub fn load_bios(&mut self, data: &[u8]) {
+        self.sys_rom = Block::with_contents(data);
+    }
+
+    // Loads the ROM, which is cut off at the largest size the gamepak bus
+    // can address.
     pub fn load_pak(&mut self, data: &[u8]) {
-        self.blocks.insert(PAK_ROM, Block::with_contents(data));
-        self.blocks.insert(PAK_ROM1, Block::with_contents(data));
-        self.blocks.insert(PAK_ROM2, Block::with_contents(data));
+        self.pak_rom = Block::with_contents(&data[..data.len().min(PAK_ROM_SIZE)]);
     }
 
     pub fn clear(&mut self) {
-        self.blocks = Memory::new_blocks()
-    }
-
-    pub fn read(&self, addr: u32, size: usize) -> Option<Vec<u8>> {
-        match self.blocks.range(0..=addr).last() {
-            Some((start, block)) =>
-                match addr >= *start && addr + (size as u32) < *start + block.len() as u32 {
-                    // Make sure our addr actually fits in this block
-                    true => {
-                        // TODO Accessing the vec elements directly might be
-                        //      faster than iter/skip/take?
-                        let block_offset = addr - *start;
-                        Some(block.data.iter()
-                            .skip(block_offset as usize)
-                            .take(size as usize)
-                            .cloned()
-                            .collect())
-                    }
-                    false => None,
+        *self = Memory::new()
+    }
+
+    // Finds the region addr falls in and its offset into that region's
+    // memory, with mirroring applied.
+    pub fn locate(addr: u32) -> (Region, usize) {
+        let offset = (addr & 0x00_FF_FF_FF) as usize;
+
+        match PAGES[(addr >> PAGE_SHIFT) as usize] {
+            Region::ExtWram => (Region::ExtWram, offset % EXT_WRAM_SIZE),
+            Region::IntWram => (Region::IntWram, offset % INT_WRAM_SIZE),
+            Region::Io => match offset as u32 & IO_MEM_CTRL_MIRROR {
+                0x08_00 => (Region::IoMemCtrl, offset & 0x3),
+                _ => (Region::Io, offset),
+            },
+            Region::PalRam => (Region::PalRam, offset % PAL_RAM_SIZE),
+            Region::Vram => {
+                let offset = offset % VRAM_MIRROR_SIZE;
+                match offset >= VRAM_SIZE {
+                    true => (Region::Vram, offset - VRAM_OBJ_SIZE),
+                    false => (Region::Vram, offset),
                 }
-            None => None
+            }
+            Region::Oam => (Region::Oam, offset % OAM_SIZE),
+            Region::PakRom(ws) => (Region::PakRom(ws), (addr as usize) % PAK_ROM_SIZE),
+            Region::PakRam => (Region::PakRam, offset % PAK_RAM_SIZE),
+            region => (region, offset),
+        }
+    }
+
+    // Maps a mirrored address back onto the address of the memory it mirrors.
+    // Addresses that are not mirrored are returned as is.
+    pub fn mirror(addr: u32) -> u32 {
+        match Memory::locate(addr) {
+            (Region::Unmapped, _) => addr,
+            (region, offset) => region.base() + offset as u32,
+        }
+    }
+
+    fn block(&self, region: Region) -> Option<&Block> {
+        match region {
+            Region::SysRom => Some(&self.sys_rom),
+            Region::ExtWram => Some(&self.ext_wram),
+            Region::IntWram => Some(&self.int_wram),
+            Region::Io => None,
+            Region::IoMemCtrl => Some(&self.io_mem_ctrl),
+            Region::PalRam => Some(&self.pal_ram),
+            Region::Vram => Some(&self.vram),
+            Region::Oam => Some(&self.oam),
+            Region::PakRom(_) => Some(&self.pak_rom),
+            Region::PakRam => None,
+            Region::Unmapped => None,
+        }
+    }
+
+    // ROM and the BIOS can't be written, so only RAM has a mutable block.
+    fn block_mut(&mut self, region: Region) -> Option<&mut Block> {
+        match region {
+            Region::SysRom => None,
+            Region::ExtWram => Some(&mut self.ext_wram),
+            Region::IntWram => Some(&mut self.int_wram),
+            Region::Io => None,
+            Region::IoMemCtrl => Some(&mut self.io_mem_ctrl),
+            Region::PalRam => Some(&mut self.pal_ram),
+            Region::Vram => Some(&mut self.vram),
+            Region::Oam => Some(&mut self.oam),
+            Region::PakRom(_) => None,
+            Region::PakRam => None,
+            Region::Unmapped => None,
         }
     }
 
-    pub fn read_u32(&self, addr: u32) -> Option<u32> {
-        match self.read(addr, 4) {
-            Some(data) => {
-                let mut buf = [0; 4];
-                buf.copy_from_slice(&data);
+    fn slice(&self, region: Region, offset: usize, size: usize) -> Option<&[u8]> {
+        self.block(region)?.data.get(offset..offset + size)
+    }
+
+    fn slice_mut(&mut self, region: Region, offset: usize, size: usize) -> Option<&mut [u8]> {
+        self.block_mut(region)?.data.get_mut(offset..offset + size)
+    }
+
+    // Returns size bytes of memory at addr as they are stored, without going
+    // through the bus.
+    pub fn read(&self, addr: u32, size: usize) -> Option<&[u8]> {
+        let (region, offset) = Memory::locate(addr);
+        self.slice(region, offset, size)
+    }
 
-                Some(u32::from_le_bytes(buf))
+    // The typed accessors behave like the GBA bus: addresses are forced into
+    // alignment with the access size, and unmapped reads and writes never
+    // fail. Unmapped reads return open bus. RAM and ROM are read straight out of
+    // their backing memory, only IO takes the slow path. Each access adds
+    // the cycles it takes to the bus cycle count.
+    pub fn read8(&mut self, addr: u32) -> u8 {
+        let (region, offset) = Memory::locate(addr);
+        self.access(region, addr, 1, AccessKind::Read);
+        let val = match region {
+            Region::SysRom if !self.exec_bios && offset < SYS_ROM_SIZE => (self.bios_latch >> ((addr & 0x3) * 8)) as u8,
+            Region::PakRom(_) if self.is_gpio(offset) => {
+                (self.read_gpio16(region, offset & !0x1) >> ((offset & 0x1) * 8)) as u8
             }
-            None => None,
+            _ => self.load8(region, offset),
+        };
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Read, addr, 1, val as u32);
         }
+        val
     }
 
-    pub fn write(&mut self, addr: u32, data: &[u8]) {
-        match self.blocks.range_mut(0..=addr).last() {
-            Some((start, block)) => {
-                match addr >= *start && addr + (data.len() as u32) < *start + block.len() as u32 {
-                    true => {
-                        let block_offset = (addr - *start) as usize;
-                        for (idx, d) in data.iter().enumerate() {
-                            block.data[block_offset + idx] = *d;
-                        }
+    pub fn read16(&mut self, addr: u32) -> u16 {
+        let (region, offset) = Memory::locate(addr);
+        let addr = addr & !0x1;
+        self.access(region, addr, 2, AccessKind::Read);
+        let val = match region {
+            Region::SysRom if !self.exec_bios && offset < SYS_ROM_SIZE => (self.bios_latch >> ((addr & 0x2) * 8)) as u16,
+            Region::PakRam => self.read_pak_ram(offset) as u16 * 0x0101,
+            Region::PakRom(_) if self.is_gpio(offset) => self.read_gpio16(region, offset & !0x1),
+            _ => {
+                let cycles = self.cycles;
+                match self.eeprom_at(region, offset) {
+                    Some(e) => e.read_bit(cycles),
+                    None => self.load16(region, offset & !0x1),
+                }
+            }
+        };
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Read, addr, 2, val as u32);
+        }
+        val
+    }
+
+    pub fn read32(&mut self, addr: u32) -> u32 {
+        let (region, offset) = Memory::locate(addr);
+        let addr = addr & !0x3;
+        self.access(region, addr, 4, AccessKind::Read);
+        let val = match region {
+            Region::SysRom if !self.exec_bios && offset < SYS_ROM_SIZE => self.bios_latch,
+            Region::PakRam => self.read_pak_ram(offset) as u32 * 0x0101_0101,
+            Region::PakRom(_) if self.is_gpio(offset & !0x3) => {
+                let offset = offset & !0x3;
+                self.read_gpio16(region, offset) as u32 | (self.read_gpio16(region, offset + 2) as u32) << 16
+            }
+            _ => self.load32(region, offset & !0x3),
+        };
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Read, addr, 4, val);
+        }
+        val
+    }
+
+    // Fetches are reads of code by the CPU, which can be served by the
+    // gamepak prefetch buffer. They also decide whether the BIOS can be
+    // read, depending on whether the code is in the BIOS.
+    pub fn fetch16(&mut self, addr: u32) -> u16 {
+        let addr = addr & !0x1;
+        let (region, offset) = Memory::locate(addr);
+        self.access(region, addr, 2, AccessKind::Execute);
+        self.exec_bios = region == Region::SysRom;
+        if self.exec_bios {
+            self.bios_latch = self.load32(region, offset & !0x3);
+        }
+
+        let val = self.load16(region, offset);
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Execute, addr, 2, val as u32);
+        }
+        val
+    }
+
+    pub fn fetch32(&mut self, addr: u32) -> u32 {
+        let addr = addr & !0x3;
+        let (region, offset) = Memory::locate(addr);
+        self.access(region, addr, 4, AccessKind::Execute);
+        self.exec_bios = region == Region::SysRom;
+
+        let val = self.load32(region, offset);
+        if self.exec_bios {
+            self.bios_latch = val;
+        }
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Execute, addr, 4, val);
+        }
+        val
+    }
+
+    // Video memory only has a 16-bit data bus. Byte writes to palette RAM
+    // and BG VRAM write the byte to both halves of the halfword, while byte
+    // writes to OBJ VRAM and OAM are ignored. Save memory only has an 8-bit
+    // bus, so wider writes to it store just the byte their address selects,
+    // and wider reads see that byte repeated. EEPROM is only ever driven by
+    // halfword DMA transfers, so only halfword accesses reach it.
+    pub fn write8(&mut self, addr: u32, val: u8) {
+        let (region, offset) = Memory::locate(addr);
+        self.access(region, addr, 1, AccessKind::Write);
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Write, addr, 1, val as u32);
+        }
+
+        match region {
+            Region::Io => self.write_io8(offset, val),
+            Region::PalRam => self.store(region, addr & !0x1, offset & !0x1, &[val, val]),
+            Region::Vram => {
+                if offset < self.vram_obj_start() {
+                    self.store(region, addr & !0x1, offset & !0x1, &[val, val]);
+                }
+            }
+            Region::Oam => {}
+            Region::PakRam => self.write_pak_ram(offset, val),
+            Region::PakRom(_) if self.is_gpio(offset) => {
+                self.write_gpio16(offset & !0x1, (val as u16) << ((offset & 0x1) * 8))
+            }
+            _ => self.store(region, addr, offset, &[val]),
+        }
+    }
+
+    pub fn write16(&mut self, addr: u32, val: u16) {
+        let (region, offset) = Memory::locate(addr);
+        let addr = addr & !0x1;
+        self.access(region, addr, 2, AccessKind::Write);
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Write, addr, 2, val as u32);
+        }
+
+        match region {
+            Region::Io => self.write_io16(offset & !0x1, val),
+            Region::PakRam => self.write_pak_ram(offset, (val >> ((offset & 0x1) * 8)) as u8),
+            Region::PakRom(_) if self.is_gpio(offset) => self.write_gpio16(offset & !0x1, val),
+            _ => {
+                let cycles = self.cycles;
+                match self.eeprom_at(region, offset) {
+                    Some(e) => e.write_bit(val, cycles),
+                    None => self.store(region, addr, offset & !0x1, &val.to_le_bytes()),
+                }
+            }
+        }
+    }
+
+    pub fn write32(&mut self, addr: u32, val: u32) {
+        let (region, offset) = Memory::locate(addr);
+        let addr = addr & !0x3;
+        self.access(region, addr, 4, AccessKind::Write);
+        if !self.observers.is_empty() {
+            self.notify(AccessKind::Write, addr, 4, val);
+        }
+
+        match region {
+            Region::Io => self.write_io32(offset & !0x3, val),
+            Region::PakRam => self.write_pak_ram(offset, (val >> ((offset & 0x3) * 8)) as u8),
+            Region::PakRom(_) if self.is_gpio(offset & !0x3) => {
+                self.write_gpio16(offset & !0x3, val as u16);
+                self.write_gpio16((offset & !0x3) + 2, (val >> 16) as u16);
+            }
+            _ => self.store(region, addr, offset & !0x3, &val.to_le_bytes()),
+        }
+    }
+
+    fn load8(&self, region: Region, offset: usize) -> u8 {
+        match region {
+            Region::Io => self.read_io8(offset),
+            Region::PakRam => self.read_pak_ram(offset),
+            _ => match self.slice(region, offset, 1) {
+                Some(data) => data[0],
+                None => self.unmapped8(region, offset),
+            },
+        }
+    }
+
+    fn load16(&self, region: Region, offset: usize) -> u16 {
+        match region {
+            Region::Io => self.read_io16(offset),
+            Region::PakRam => self.read_pak_ram(offset) as u16 * 0x0101,
+            _ => match self.slice(region, offset, 2) {
+                Some(data) => u16::from_le_bytes([data[0], data[1]]),
+                None => self.unmapped16(region, offset),
+            },
+        }
+    }
+
+    fn load32(&self, region: Region, offset: usize) -> u32 {
+        match region {
+            Region::Io => self.read_io32(offset),
+            Region::PakRam => self.read_pak_ram(offset) as u32 * 0x0101_0101,
+            _ => match self.slice(region, offset, 4) {
+                Some(data) => u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+                None => self.unmapped32(region, offset),
+            },
+        }
+    }
+
+    // Reads from nothing see open bus, except past the end of the ROM. The
+    // gamepak bus shares its lines between the address and the data, so with
+    // no ROM to answer, the halfword read is the low bits of its own address.
+    fn unmapped8(&self, region: Region, offset: usize) -> u8 {
+        match region {
+            Region::PakRom(_) => (self.unmapped16(region, offset & !0x1) >> ((offset & 0x1) * 8)) as u8,
+            _ => self.open_bus8(offset),
+        }
+    }
+
+    fn unmapped16(&self, region: Region, offset: usize) -> u16 {
+        match region {
+            Region::PakRom(_) => (offset >> 1) as u16,
+            _ => self.open_bus16(offset),
+        }
+    }
+
+    fn unmapped32(&self, region: Region, offset: usize) -> u32 {
+        match region {
+            Region::PakRom(_) => {
+                let lo = self.unmapped16(region, offset) as u32;
+                let hi = self.unmapped16(region, offset + 2) as u32;
+                (hi << 16) | lo
+            }
+            _ => self.open_bus,
+        }
+    }
+
+    // Reads memory without any of the side effects of a bus access, for the
+    // CPU to look at its own pipeline and for debugging.
+    pub fn peek16(&self, addr: u32) -> u16 {
+        let (region, offset) = Memory::locate(addr & !0x1);
+        self.load16(region, offset)
+    }
+
+    pub fn peek32(&self, addr: u32) -> u32 {
+        let (region, offset) = Memory::locate(addr & !0x3);
+        self.load32(region, offset)
+    }
+
+    // Sets the value unmapped reads return. The CPU updates this with its
+    // pipeline contents as it runs.
+    pub fn set_open_bus(&mut self, val: u32) {
+        self.open_bus = val;
+    }
+
+    pub fn open_bus(&self) -> u32 {
+        self.open_bus
+    }
+
+    // Narrow open bus reads see the part of the bus their address selects.
+    fn open_bus8(&self, offset: usize) -> u8 {
+        (self.open_bus >> ((offset & 0x3) * 8)) as u8
+    }
+
+    fn open_bus16(&self, offset: usize) -> u16 {
+        (self.open_bus >> ((offset & 0x2) * 8)) as u16
+    }
+
+    // Writes to ROM, the BIOS and unmapped memory go nowhere on hardware, so
+    // they are dropped here too.
+    fn store(&mut self, region: Region, addr: u32, offset: usize, bytes: &[u8]) {
+        match self.slice_mut(region, offset, bytes.len()) {
+            Some(data) => data.copy_from_slice(bytes),
+            None => self.ignore_write(addr, bytes),
+        }
+    }
+
+    fn ignore_write(&mut self, addr: u32, bytes: &[u8]) {
+        if let Some(hook) = self.ignored_write_hook.as_mut() {
+            let mut val = [0; 4];
+            val[..bytes.len()].copy_from_slice(bytes);
+            hook(&IgnoredWrite {
+                addr,
+                size: bytes.len(),
+                val: u32::from_le_bytes(val),
+            });
+        }
+    }
+
+    // Sets a hook called with every write that gets dropped, for tracking
+    // down stray pointers, or removes it with None.
+    pub fn set_ignored_write_hook(&mut self, hook: Option<IgnoredWriteHook>) {
+        self.ignored_write_hook = hook;
+    }
+
+    // Adds the cost of an access to the cycle count. Accesses to the
+    // address right after the previous one are sequential, except across
+    // the 128 KiB blocks the gamepak bus addresses ROM in. Code fetches from
+    // ROM go through the prefetch buffer, while any other use of the
+    // gamepak bus stops it.
+    fn access(&mut self, region: Region, addr: u32, size: usize, kind: AccessKind) {
+        let code = kind == AccessKind::Execute;
+        let seq = addr == self.next_seq && addr & 0x1_FF_FF != 0;
+        self.next_seq = addr.wrapping_add(size as u32);
+
+        let cycles = match region {
+            Region::PakRom(ws) if code && self.wait.prefetch => {
+                match self.prefetch.fetch(addr, size as u32) {
+                    Some(cycles) => cycles,
+                    None => {
+                        self.prefetch.restart(self.next_seq, self.wait.second[ws] + 1);
+                        self.wait.cycles(region, size, seq)
                     }
-                    false => panic!("write extends beyond block"),
                 }
             }
-            None => panic!("memory block not found"),
+            Region::PakRom(_) | Region::PakRam => {
+                self.prefetch.reset();
+                self.wait.cycles(region, size, seq)
+            }
+            _ => {
+                let cycles = self.wait.cycles(region, size, seq);
+                self.prefetch.run(cycles);
+                cycles
+            }
+        };
+
+        self.cycles += cycles as u64;
+        if let Some(p) = self.profile.as_mut() {
+            p.record(region, addr, size, kind, cycles);
         }
     }
+
+    // Starts counting accesses to each region and page of memory, or stops
+    // with None.
+    pub fn set_profile(&mut self, profile: Option<profile::Profile>) {
+        self.profile = profile.map(Box::new);
+    }
+
+    pub fn profile(&self) -> Option<&profile::Profile> {
+        self.profile.as_deref()
+    }
+
+    // Watches accesses to a range of memory, until unwatch is called with the
+    // id returned. Ranges are given without mirroring, and accesses through
+    // any mirror of them are caught too.
+    pub fn watch(&mut self, wp: watch::Watchpoint) -> watch::WatchId {
+        self.observers.add(wp)
+    }
+
+    pub fn unwatch(&mut self, id: watch::WatchId) -> bool {
+        self.observers.remove(id)
+    }
+
+    // Sets the address of the instruction making the accesses that follow,
+    // for watchpoints to report.
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+    }
+
+    fn notify(&mut self, kind: AccessKind, addr: u32, size: usize, val: u32) {
+        let access = Access {
+            kind,
+            addr,
+            size,
+            val,
+            pc: self.pc,
+            cycle: self.cycles,
+        };
+        self.observers.notify(Memory::mirror(addr), &access);
+    }
+
+    // Returns the number of cycles taken by every access so far, along with
+    // any internal cycles added with idle.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Adds cycles where the CPU is busy but not using the bus, which the
+    // prefetch buffer uses to read ahead.
+    pub fn idle(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        self.prefetch.run(cycles);
+    }
+
+    // Returns how many cycles an access would take with the current wait
+    // state settings, without the prefetch buffer.
+    pub fn access_cycles(&self, addr: u32, size: usize, seq: bool) -> u32 {
+        let (region, _) = Memory::locate(addr);
+        self.wait.cycles(region, size, seq)
+    }
+
+    // Copies data into memory without the timing of a bus access. Any of it
+    // that lands outside of writable memory is dropped.
+    pub fn write(&mut self, addr: u32, data: &[u8]) {
+        for (idx, val) in data.iter().enumerate() {
+            let addr = addr.wrapping_add(idx as u32);
+            let (region, offset) = Memory::locate(addr);
+            self.store(region, addr, offset, &[*val]);
+        }
+    }
+
+    // Sets the save memory the cartridge has, which answers at PAK_RAM.
+    pub fn set_backup(&mut self, backup: Backup) {
+        self.backup = backup;
+    }
+
+    pub fn backup(&self) -> &Backup {
+        &self.backup
+    }
+
+    pub fn backup_mut(&mut self) -> &mut Backup {
+        &mut self.backup
+    }
+
+    // Sets the GPIO port the cartridge has, which answers over the ROM
+    // header, or removes it with None.
+    pub fn set_gpio(&mut self, gpio: Option<Gpio>) {
+        self.gpio = gpio;
+    }
+
+    pub fn gpio(&self) -> Option<&Gpio> {
+        self.gpio.as_ref()
+    }
+
+    pub fn gpio_mut(&mut self) -> Option<&mut Gpio> {
+        self.gpio.as_mut()
+    }
+
+    fn is_gpio(&self, offset: usize) -> bool {
+        self.gpio.is_some() && Gpio::contains(offset)
+    }
+
+    fn read_gpio16(&self, region: Region, offset: usize) -> u16 {
+        match self.gpio.as_ref().and_then(|g| g.read16(offset)) {
+            Some(val) => val,
+            None => self.load16(region, offset),
+        }
+    }
+
+    fn write_gpio16(&mut self, offset: usize, val: u16) {
+        let cycles = self.cycles;
+        if let Some(g) = self.gpio.as_mut() {
+            g.write16(offset, val, cycles);
+        }
+    }
+
+    // Sets the tilt sensor the cartridge has, which answers in PAK_RAM, or
+    // removes it with None.
+    pub fn set_tilt(&mut self, tilt: Option<TiltSensor>) {
+        self.tilt = tilt;
+    }
+
+    pub fn tilt(&self) -> Option<&TiltSensor> {
+        self.tilt.as_ref()
+    }
+
+    pub fn tilt_mut(&mut self) -> Option<&mut TiltSensor> {
+        self.tilt.as_mut()
+    }
+
+    // The tilt sensor's registers take precedence over save memory, which
+    // the cartridges that have one don't use.
+    fn read_pak_ram(&self, offset: usize) -> u8 {
+        match self.tilt.as_ref().and_then(|t| t.read8(offset)) {
+            Some(val) => val,
+            None => self.backup.read8(offset),
+        }
+    }
+
+    fn write_pak_ram(&mut self, offset: usize, val: u8) {
+        let cycles = self.cycles;
+        if !self.tilt.as_mut().is_some_and(|t| t.write8(offset, val, cycles)) {
+            self.backup.write8(offset, val);
+        }
+    }
+
+    fn eeprom_at(&mut self, region: Region, offset: usize) -> Option<&mut Eeprom> {
+        let start = match self.pak_rom.len() > EEPROM_START {
+            true => EEPROM_START_LARGE_ROM,
+            false => EEPROM_START,
+        };
+        match region == Region::PakRom(2) && offset >= start {
+            true => self.backup.eeprom_mut(),
+            false => None,
+        }
+    }
+
+    // Runs a DMA transfer as soon as it is enabled, if it is set to start
+    // immediately. Transfers started by video or sound timing aren't run.
+    // Transfers to EEPROM tell it how big it is, by how long they are.
+    fn run_dma(&mut self, channel: usize) {
+        let t = dma::Transfer::from_io(&self.io, channel);
+        if t.timing != dma::Timing::Immediate {
+            return;
+        }
+
+        let (region, offset) = Memory::locate(t.dst);
+        if let Some(e) = self.eeprom_at(region, offset) {
+            e.infer_size(t.count);
+        }
+
+        let size = t.size();
+        let mut src = t.src & !(size - 1);
+        let mut dst = t.dst & !(size - 1);
+        for _ in 0..t.count {
+            match t.word {
+                true => {
+                    let val = self.read32(src);
+                    self.write32(dst, val);
+                }
+                false => {
+                    let val = self.read16(src);
+                    self.write16(dst, val);
+                }
+            }
+            src = t.src_ctrl.step(src, size);
+            dst = t.dst_ctrl.step(dst, size);
+        }
+        self.idle(2);
+
+        // Immediate transfers can't repeat, so the channel turns itself off.
+        let cnt = dma::control_reg(channel);
+        self.io.set(cnt, self.io.get(cnt) & !dma::CNT_ENABLE);
+        if t.irq {
+            self.io.set(io::REG_IF, self.io.get(io::REG_IF) | 1 << (8 + channel));
+        }
+    }
+
+    fn vram_obj_start(&self) -> usize {
+        match self.io.get(io::REG_DISPCNT) & 0x7 {
+            3..=5 => VRAM_OBJ_BITMAP_MODE,
+            _ => VRAM_OBJ_TILE_MODE,
+        }
+    }
+
+    pub fn io(&self) -> &io::Io {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut io::Io {
+        &mut self.io
+    }
+
+    // IO registers are all a halfword wide, so byte and word accesses are
+    // split into halfword accesses. Registers that can't be read return open
+    // bus.
+    fn read_io8(&self, offset: usize) -> u8 {
+        (self.read_io16(offset & !0x1) >> ((offset & 0x1) * 8)) as u8
+    }
+
+    fn read_io16(&self, offset: usize) -> u16 {
+        match self.io.read16(offset as u32) {
+            Some(val) => val,
+            None => self.open_bus16(offset),
+        }
+    }
+
+    fn read_io32(&self, offset: usize) -> u32 {
+        self.read_io16(offset) as u32 | (self.read_io16(offset + 2) as u32) << 16
+    }
+
+    fn write_io8(&mut self, offset: usize, val: u8) {
+        let shift = (offset & 0x1) * 8;
+        self.write_io(offset & !0x1, (val as u16) << shift, 0xFF << shift);
+    }
+
+    fn write_io16(&mut self, offset: usize, val: u16) {
+        self.write_io(offset, val, 0xFFFF);
+    }
+
+    fn write_io(&mut self, offset: usize, val: u16, mask: u16) {
+        let was_enabled = self.io.get(offset as u32) & dma::CNT_ENABLE != 0;
+        self.io.write16(offset as u32, val, mask);
+
+        if let Some(channel) = dma::control_channel(offset as u32) {
+            if !was_enabled && self.io.get(offset as u32) & dma::CNT_ENABLE != 0 {
+                self.run_dma(channel);
+            }
+        }
+
+        if offset as u32 == io::REG_WAITCNT {
+            self.wait = timing::WaitControl::from_waitcnt(self.io.get(io::REG_WAITCNT));
+            if !self.wait.prefetch {
+                self.prefetch.reset();
+            }
+        }
+    }
+
+    fn write_io32(&mut self, offset: usize, val: u32) {
+        self.write_io16(offset, val as u16);
+        self.write_io16(offset + 2, (val >> 16) as u16);
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
 }
 
 pub struct Block {
@@ -126,11 +930,7 @@ pub struct Block {
 
 impl Block {
     fn new(size: usize) -> Block {
-        let mut data = Vec::with_capacity(size);
-        for _ in 0..size {
-            data.push(0);
-        }
-        Block { data }
+        Block { data: vec![0; size] }
     }
 
     fn with_contents(contents: &[u8]) -> Block {
@@ -160,49 +960,669 @@ mod tests {
         use super::super::*;
 
         #[test]
-        fn new_blocks() {
-            let blocks = Memory::new_blocks();
-            assert_eq!(true, blocks.contains_key(&SYS_ROM));
-            assert_eq!(16 * KBYTE, blocks[&SYS_ROM].len());
+        #[allow(clippy::identity_op)]
+        fn new() {
+            let m = Memory::new();
+            assert_eq!(16 * KBYTE, m.sys_rom.len());
 
-            assert_eq!(true, blocks.contains_key(&EXT_WRAM));
-            assert_eq!(256 * KBYTE, blocks[&EXT_WRAM].len());
-            assert_eq!(true, blocks.contains_key(&INT_WRAM));
-            assert_eq!(32 * KBYTE, blocks[&INT_WRAM].len());
+            assert_eq!(256 * KBYTE, m.ext_wram.len());
+            assert_eq!(32 * KBYTE, m.int_wram.len());
 
-            assert_eq!(true, blocks.contains_key(&IORAM));
-            assert_eq!(1 * KBYTE, blocks[&IORAM].len());
-            assert_eq!(true, blocks.contains_key(&PAL_RAM));
-            assert_eq!(1 * KBYTE, blocks[&PAL_RAM].len());
-            assert_eq!(true, blocks.contains_key(&VRAM));
-            assert_eq!(96 * KBYTE, blocks[&VRAM].len());
-            assert_eq!(true, blocks.contains_key(&OAM));
-            assert_eq!(1 * KBYTE, blocks[&OAM].len());
+            assert_eq!(1 * KBYTE, m.pal_ram.len());
+            assert_eq!(96 * KBYTE, m.vram.len());
+            assert_eq!(1 * KBYTE, m.oam.len());
+        }
 
-            assert_eq!(true, blocks.contains_key(&PAK_RAM));
-            assert_eq!(64 * KBYTE, blocks[&PAK_RAM].len());
+        #[test]
+        fn new_pages() {
+            let pages = Memory::new_pages();
+            assert_eq!(Region::SysRom, pages[0x00]);
+            assert_eq!(Region::Unmapped, pages[0x01]);
+            assert_eq!(Region::ExtWram, pages[0x02]);
+            assert_eq!(Region::IntWram, pages[0x03]);
+            assert_eq!(Region::Io, pages[0x04]);
+            assert_eq!(Region::PalRam, pages[0x05]);
+            assert_eq!(Region::Vram, pages[0x06]);
+            assert_eq!(Region::Oam, pages[0x07]);
+            assert_eq!(Region::PakRom(0), pages[0x08]);
+            assert_eq!(Region::PakRom(1), pages[0x0A]);
+            assert_eq!(Region::PakRom(2), pages[0x0C]);
+            assert_eq!(Region::PakRam, pages[0x0E]);
+            assert_eq!(Region::PakRam, pages[0x0F]);
+            assert_eq!(Region::Unmapped, pages[0xFF]);
+        }
+
+        #[test]
+        fn load_bios() {
+            let mut m = Memory::new();
+            m.load_bios(&[1, 2, 3, 4]);
+
+            assert_eq!(0x04030201, m.fetch32(SYS_ROM));
+        }
+
+        #[test]
+        fn read_bios_protected() {
+            let mut m = Memory::new();
+            m.load_bios(&[1, 2, 3, 4, 5, 6, 7, 8]);
+            m.load_pak(&[0; 4]);
+
+            assert_eq!(0x08070605, m.fetch32(SYS_ROM + 4));
+            assert_eq!(0x04030201, m.read32(SYS_ROM));
+
+            m.fetch32(PAK_ROM);
+            assert_eq!(0x08070605, m.read32(SYS_ROM));
+            assert_eq!(0x0807, m.read16(SYS_ROM + 0x12));
+            assert_eq!(0x06, m.read8(SYS_ROM + 0x1));
+        }
+
+        #[test]
+        fn read_past_bios_open_bus() {
+            let mut m = Memory::new();
+            m.load_bios(&[1, 2, 3, 4]);
+            m.load_pak(&[0; 4]);
+
+            m.fetch32(PAK_ROM);
+            m.set_open_bus(0x1234_5678);
+            assert_eq!(0x1234_5678, m.read32(SYS_ROM + 0x4000));
+            assert_eq!(0x1234, m.read16(SYS_ROM + 0x4002));
+            assert_eq!(0x56, m.read8(SYS_ROM + 0x4001));
+        }
+
+        #[test]
+        fn load_pak() {
+            let mut m = Memory::new();
+            m.load_pak(&[1, 2, 3, 4]);
+
+            assert_eq!(0x04030201, m.read32(PAK_ROM));
+            assert_eq!(0x04030201, m.read32(PAK_ROM1));
+            assert_eq!(0x04030201, m.read32(PAK_ROM2));
+        }
+
+        #[test]
+        fn load_pak_32mib() {
+            let mut data = vec![0; PAK_ROM_SIZE + 4];
+            data[0x01_23_45_60] = 0x12;
+            data[PAK_ROM_SIZE - 1] = 0x34;
+
+            let mut m = Memory::new();
+            m.load_pak(&data);
+
+            assert_eq!(0x12, m.read8(0x09_23_45_60));
+            assert_eq!(0x12, m.read8(0x0B_23_45_60));
+            assert_eq!(0x12, m.read8(0x0D_23_45_60));
+            assert_eq!(0x34, m.read8(0x09_FF_FF_FF));
+            assert_eq!(0, m.read8(PAK_ROM));
+        }
+
+        #[test]
+        fn read_past_end_of_pak() {
+            let mut m = Memory::new();
+            m.load_pak(&[1, 2, 3, 4]);
+            m.set_open_bus(0xFFFF_FFFF);
+
+            assert_eq!(0x0003_0002, m.read32(PAK_ROM + 4));
+            assert_eq!(0x0003, m.read16(PAK_ROM1 + 6));
+            assert_eq!(0x5678, m.read16(PAK_ROM2 + 0x01_12_AC_F0));
+            assert_eq!(0x56, m.read8(0x09_12_AC_F1));
+            assert_eq!(0x8001_8000, m.read32(0x0D_FF_00_00));
         }
 
         #[test]
         fn write() {
             let mut m = Memory::new();
-            m.write(PAK_RAM, &vec![1, 2, 3, 4]);
+            m.write(EXT_WRAM, &[1, 2, 3, 4]);
 
-            assert_eq!(1, m.blocks[&PAK_RAM].data[0]);
-            assert_eq!(2, m.blocks[&PAK_RAM].data[1]);
-            assert_eq!(3, m.blocks[&PAK_RAM].data[2]);
-            assert_eq!(4, m.blocks[&PAK_RAM].data[3]);
+            assert_eq!(1, m.ext_wram.data[0]);
+            assert_eq!(2, m.ext_wram.data[1]);
+            assert_eq!(3, m.ext_wram.data[2]);
+            assert_eq!(4, m.ext_wram.data[3]);
+        }
+
+        #[test]
+        fn write_read_only() {
+            let mut m = Memory::new();
+            m.load_bios(&[1, 2, 3, 4]);
+            m.load_pak(&[1, 2, 3, 4]);
+
+            m.write(SYS_ROM, &[5]);
+            m.write32(PAK_ROM, 0);
+            m.write16(PAK_ROM2 + 2, 0);
+            m.write8(PAK_ROM1, 0);
+
+            assert_eq!(0x04030201, m.peek32(SYS_ROM));
+            assert_eq!(0x04030201, m.read32(PAK_ROM));
+            assert_eq!(0x04030201, m.read32(PAK_ROM1));
+            assert_eq!(0x04030201, m.read32(PAK_ROM2));
+        }
+
+        #[test]
+        fn write_out_of_bounds() {
+            let mut m = Memory::new();
+            m.write(INT_WRAM + 0x7F_FE, &[1, 2, 3, 4]);
+            m.write(0x10_00_00_00, &[1, 2, 3, 4]);
+            m.write32(0xFF_FF_FF_FC, 0x1234_5678);
+
+            assert_eq!(Some(&[1, 2][..]), m.read(INT_WRAM + 0x7F_FE, 2));
+            assert_eq!(Some(&[3, 4][..]), m.read(INT_WRAM, 2));
+        }
+
+        #[test]
+        fn ignored_write_hook() {
+            use std::cell::RefCell;
+            use std::rc::Rc;
+
+            let ignored = Rc::new(RefCell::new(Vec::new()));
+            let log = ignored.clone();
+
+            let mut m = Memory::new();
+            m.set_ignored_write_hook(Some(Box::new(move |w| log.borrow_mut().push(*w))));
+            m.write32(PAK_ROM + 4, 0x1234_5678);
+            m.write16(0x10_00_00_02, 0xABCD);
+            m.write8(EXT_WRAM, 0x12);
+
+            assert_eq!(
+                vec![
+                    IgnoredWrite { addr: PAK_ROM + 4, size: 4, val: 0x1234_5678 },
+                    IgnoredWrite { addr: 0x10_00_00_02, size: 2, val: 0xABCD },
+                ],
+                *ignored.borrow(),
+            );
+        }
+
+        #[test]
+        fn watch() {
+            use std::cell::RefCell;
+            use std::rc::Rc;
+            use watch::Watchpoint;
+
+            let hits = Rc::new(RefCell::new(Vec::new()));
+            let log = hits.clone();
+
+            let mut m = Memory::new();
+            let wp = Watchpoint::new(INT_WRAM + 0x10, INT_WRAM + 0x13, Box::new(move |a| log.borrow_mut().push(*a)))
+                .kinds(&[AccessKind::Write]);
+            let id = m.watch(wp);
+
+            m.set_pc(0x08_00_01_00);
+            m.write8(INT_WRAM + 0x0F, 0x12);
+            m.write16(0x03_FF_80_12, 0x3456);
+            m.read32(INT_WRAM + 0x10);
+            assert!(m.unwatch(id));
+            m.write8(INT_WRAM + 0x10, 0x78);
+
+            assert_eq!(
+                vec![Access {
+                    kind: AccessKind::Write,
+                    addr: 0x03_FF_80_12,
+                    size: 2,
+                    val: 0x3456,
+                    pc: 0x08_00_01_00,
+                    cycle: 2,
+                }],
+                *hits.borrow(),
+            );
         }
 
         #[test]
         fn read() {
             let mut m = Memory::new();
-            m.blocks.get_mut(&PAK_RAM).unwrap().data[0] = 1;
-            m.blocks.get_mut(&PAK_RAM).unwrap().data[1] = 2;
-            m.blocks.get_mut(&PAK_RAM).unwrap().data[2] = 3;
-            m.blocks.get_mut(&PAK_RAM).unwrap().data[3] = 4;
+            m.ext_wram.data[0] = 1;
+            m.ext_wram.data[1] = 2;
+            m.ext_wram.data[2] = 3;
+            m.ext_wram.data[3] = 4;
+
+            assert_eq!(Some(&[1, 2, 3, 4][..]), m.read(EXT_WRAM, 4));
+        }
+
+        #[test]
+        fn read_end_of_block() {
+            let mut m = Memory::new();
+            m.write(INT_WRAM + 0x7F_FC, &[1, 2, 3, 4]);
+
+            assert_eq!(Some(&[1, 2, 3, 4][..]), m.read(INT_WRAM + 0x7F_FC, 4));
+            assert_eq!(None, m.read(INT_WRAM + 0x7F_FD, 4));
+        }
+
+        #[test]
+        fn sram() {
+            use crate::gamepak::BackupType;
+
+            let mut m = Memory::new();
+            m.set_backup(Backup::new(BackupType::Sram, None));
+
+            m.write8(PAK_RAM, 0x12);
+            m.write16(PAK_RAM + 0x11, 0xABCD);
+            m.write32(PAK_RAM + 0x22, 0x1234_5678);
+
+            assert_eq!(0x12, m.read8(PAK_RAM));
+            assert_eq!(0xAB, m.read8(PAK_RAM + 0x11));
+            assert_eq!(0xFF, m.read8(PAK_RAM + 0x10));
+            assert_eq!(0x34, m.read8(PAK_RAM + 0x22));
+            assert_eq!(0xFF, m.read8(PAK_RAM + 0x20));
+
+            assert_eq!(0x1212, m.read16(PAK_RAM));
+            assert_eq!(0xABAB, m.read16(PAK_RAM + 0x11));
+            assert_eq!(0x3434_3434, m.read32(PAK_RAM + 0x22));
+            assert!(m.backup().is_dirty());
+        }
+
+        #[test]
+        fn sram_mirrored() {
+            use crate::gamepak::BackupType;
+
+            let mut m = Memory::new();
+            m.set_backup(Backup::new(BackupType::Sram, None));
+
+            m.write8(0x0F_FF_80_01, 0x12);
+            assert_eq!(0x12, m.read8(PAK_RAM + 1));
+            assert_eq!(0x12, m.read8(PAK_RAM + 0x01_00_01));
+            assert_eq!(0x12, m.read8(PAK_RAM + 0x80_01));
+        }
+
+        #[test]
+        fn flash() {
+            use crate::gamepak::BackupType;
+
+            let mut m = Memory::new();
+            m.set_backup(Backup::new(BackupType::Flash128K, None));
+
+            m.write8(PAK_RAM + 0x5555, 0xAA);
+            m.write8(PAK_RAM + 0x2AAA, 0x55);
+            m.write8(PAK_RAM + 0x5555, 0x90);
+            assert_eq!(0x62, m.read8(PAK_RAM));
+            assert_eq!(0x1313, m.read16(PAK_RAM + 1));
+        }
+
+        #[test]
+        fn no_backup() {
+            let mut m = Memory::new();
+            m.write8(PAK_RAM, 0x12);
+
+            assert_eq!(0xFF, m.read8(PAK_RAM));
+            assert_eq!(0xFFFF_FFFF, m.read32(PAK_RAM));
+        }
+
+        fn dma3(m: &mut Memory, src: u32, dst: u32, count: u16) {
+            m.write32(IORAM + io::REG_DMA3SAD, src);
+            m.write32(IORAM + io::REG_DMA3SAD + 4, dst);
+            m.write32(IORAM + io::REG_DMA3SAD + 8, count as u32 | (dma::CNT_ENABLE as u32) << 16);
+        }
+
+        fn eeprom_bits(bits: &[u16]) -> Vec<u8> {
+            bits.iter().flat_map(|bit| bit.to_le_bytes()).collect()
+        }
+
+        #[test]
+        fn dma_immediate() {
+            let mut m = Memory::new();
+            m.write(EXT_WRAM, &[1, 2, 3, 4, 5, 6]);
+            m.write16(IORAM + io::REG_IE, 0);
+            dma3(&mut m, EXT_WRAM, INT_WRAM, 3);
+
+            assert_eq!(0x0403_0201, m.read32(INT_WRAM));
+            assert_eq!(0x0605, m.read16(INT_WRAM + 4));
+            assert_eq!(0, m.read16(INT_WRAM + 6));
+            assert_eq!(0, m.io().get(dma::control_reg(3)) & dma::CNT_ENABLE);
+        }
+
+        #[test]
+        fn eeprom() {
+            use crate::gamepak::BackupType;
+
+            let mut m = Memory::new();
+            m.load_pak(&[0; 0x100]);
+            m.set_backup(Backup::new(BackupType::Eeprom, None));
+
+            // Write 0x8000_0000_0000_0001 to block 2, with a 6-bit address.
+            let mut bits = vec![1, 0, 0, 0, 0, 0, 1, 0, 1];
+            bits.extend([0; 62]);
+            bits.extend([1, 0]);
+            m.write(EXT_WRAM, &eeprom_bits(&bits));
+            dma3(&mut m, EXT_WRAM, 0x0D_00_00_00, 73);
+
+            assert_eq!(&[0x80, 0, 0, 0, 0, 0, 0, 0x01], &m.backup().data()[16..24]);
+            assert_eq!(512, m.backup().data().len());
+            assert_eq!(0, m.read16(0x0D_FF_FF_00));
+
+            // Read it back once the chip is ready again.
+            m.idle(crate::gamepak::eeprom::WRITE_CYCLES as u32);
+            assert_eq!(1, m.read16(0x0D_FF_FF_00));
+            m.write(EXT_WRAM, &eeprom_bits(&[1, 1, 0, 0, 0, 0, 1, 0, 0]));
+            dma3(&mut m, EXT_WRAM, 0x0D_00_00_00, 9);
+            dma3(&mut m, 0x0D_00_00_00, INT_WRAM, 68);
+
+            assert_eq!(0, m.read16(INT_WRAM + 6));
+            assert_eq!(1, m.read16(INT_WRAM + 8));
+            assert_eq!(0, m.read16(INT_WRAM + 10));
+            assert_eq!(1, m.read16(INT_WRAM + 134));
+        }
+
+        #[test]
+        fn gpio() {
+            use crate::gamepak::gpio::{GPIO_CONTROL, GPIO_DATA, GPIO_DIRECTION};
+
+            let mut m = Memory::new();
+            m.load_pak(&[0x12; 0x100]);
+            m.write16(PAK_ROM + GPIO_DIRECTION as u32, 0x5);
+            assert_eq!(0x1212, m.read16(PAK_ROM + GPIO_DIRECTION as u32));
+
+            m.set_gpio(Some(Gpio::new()));
+            m.write32(PAK_ROM + GPIO_DATA as u32, 0x0005_0001);
+            assert_eq!(0x1212, m.read16(PAK_ROM + GPIO_DIRECTION as u32));
+
+            m.write8(PAK_ROM + GPIO_CONTROL as u32, 0x1);
+            assert_eq!(0x0005, m.read16(PAK_ROM + GPIO_DIRECTION as u32));
+            assert_eq!(0x1212_0001, m.read32(PAK_ROM1 + GPIO_CONTROL as u32));
+            assert_eq!(0x05, m.read8(PAK_ROM2 + GPIO_DIRECTION as u32));
+            assert_eq!(0x1212, m.read16(PAK_ROM + 0xCA));
+        }
+
+        #[test]
+        fn tilt() {
+            use crate::gamepak::motion::{Motion, MotionScript};
+            use crate::gamepak::BackupType;
+
+            let mut m = Memory::new();
+            m.set_backup(Backup::new(BackupType::Sram, None));
+            m.write8(PAK_RAM + 0x8000, 0x55);
+            assert_eq!(0x55, m.read8(PAK_RAM + 0x8000));
+
+            m.set_tilt(Some(TiltSensor::new(MotionScript::fixed(Motion { tilt_x: 0x10, tilt_y: -0x10, rotation: 0 }))));
+            m.write8(PAK_RAM + 0x8000, 0x55);
+            m.write8(PAK_RAM + 0x8100, 0xAA);
+            assert_eq!(0xB0, m.read8(PAK_RAM + 0x8200));
+            assert_eq!(0x83, m.read8(PAK_RAM + 0x8300));
+            assert_eq!(0x9090, m.read16(PAK_RAM + 0x8400));
+            assert_eq!(0x03, m.read8(PAK_RAM + 0x8500));
+            assert_eq!(0x55, m.read8(PAK_RAM + 0x8000));
+        }
+
+        #[test]
+        fn eeprom_large_rom() {
+            use crate::gamepak::BackupType;
+
+            let mut m = Memory::new();
+            m.load_pak(&vec![0x12; EEPROM_START + 2]);
+            m.set_backup(Backup::new(BackupType::Eeprom, None));
+
+            assert_eq!(0x1212, m.read16(0x0D_00_00_00));
+            assert_eq!(1, m.read16(0x0D_FF_FF_00));
+        }
+
+        #[test]
+        fn read_typed() {
+            let mut m = Memory::new();
+            m.write(EXT_WRAM, &[0x78, 0x56, 0x34, 0x12]);
+
+            assert_eq!(0x78, m.read8(EXT_WRAM));
+            assert_eq!(0x34, m.read8(EXT_WRAM + 2));
+            assert_eq!(0x5678, m.read16(EXT_WRAM));
+            assert_eq!(0x1234, m.read16(EXT_WRAM + 2));
+            assert_eq!(0x12345678, m.read32(EXT_WRAM));
+        }
+
+        #[test]
+        fn read_typed_unaligned() {
+            let mut m = Memory::new();
+            m.write(EXT_WRAM, &[0x78, 0x56, 0x34, 0x12]);
+
+            assert_eq!(0x5678, m.read16(EXT_WRAM + 1));
+            assert_eq!(0x12345678, m.read32(EXT_WRAM + 1));
+            assert_eq!(0x12345678, m.read32(EXT_WRAM + 3));
+        }
+
+        #[test]
+        fn read_typed_unmapped() {
+            let mut m = Memory::new();
+
+            assert_eq!(0, m.read8(0x01_00_00_00));
+            assert_eq!(0, m.read16(0x10_00_00_00));
+            assert_eq!(0, m.read32(0xFF_FF_FF_FC));
+        }
+
+        #[test]
+        fn read_open_bus() {
+            let mut m = Memory::new();
+            m.set_open_bus(0x1234_5678);
+
+            assert_eq!(0x1234_5678, m.read32(0x01_00_00_00));
+            assert_eq!(0x1234, m.read16(0x10_00_00_02));
+            assert_eq!(0x5678, m.read16(0x10_00_00_00));
+            assert_eq!(0x34, m.read8(0xFF_FF_FF_FE));
+        }
+
+        #[test]
+        fn peek() {
+            let mut m = Memory::new();
+            m.load_pak(&[1, 2, 3, 4]);
+
+            assert_eq!(0x04030201, m.peek32(PAK_ROM));
+            assert_eq!(0x0403, m.peek16(PAK_ROM + 2));
+            assert_eq!(0, m.cycles());
+        }
+
+        #[test]
+        fn write_typed() {
+            let mut m = Memory::new();
+            m.write32(EXT_WRAM, 0x12345678);
+            assert_eq!(Some(&[0x78, 0x56, 0x34, 0x12][..]), m.read(EXT_WRAM, 4));
+
+            m.write16(EXT_WRAM + 2, 0xABCD);
+            assert_eq!(Some(&[0x78, 0x56, 0xCD, 0xAB][..]), m.read(EXT_WRAM, 4));
+
+            m.write8(EXT_WRAM + 1, 0xEF);
+            assert_eq!(Some(&[0x78, 0xEF, 0xCD, 0xAB][..]), m.read(EXT_WRAM, 4));
+        }
+
+        #[test]
+        fn write_typed_unaligned() {
+            let mut m = Memory::new();
+            m.write32(EXT_WRAM + 3, 0x12345678);
+            m.write16(EXT_WRAM + 5, 0xABCD);
+
+            assert_eq!(Some(&[0x78, 0x56, 0x34, 0x12, 0xCD, 0xAB][..]), m.read(EXT_WRAM, 6));
+        }
+
+        #[test]
+        fn write8_pal_ram() {
+            let mut m = Memory::new();
+            m.write8(PAL_RAM + 0x11, 0x12);
+
+            assert_eq!(0x1212, m.read16(PAL_RAM + 0x10));
+        }
+
+        #[test]
+        fn write8_vram_bg() {
+            let mut m = Memory::new();
+            m.write8(VRAM + 0xFF_FF, 0x12);
+            assert_eq!(0x1212, m.read16(VRAM + 0xFF_FE));
+
+            m.write8(VRAM + 0x1_00_00, 0x12);
+            assert_eq!(0, m.read16(VRAM + 0x1_00_00));
+        }
+
+        #[test]
+        fn write8_vram_bg_bitmap_mode() {
+            let mut m = Memory::new();
+            m.write16(IORAM + io::REG_DISPCNT, 0x0003);
+
+            m.write8(VRAM + 0x1_3F_FF, 0x12);
+            assert_eq!(0x1212, m.read16(VRAM + 0x1_3F_FE));
+
+            m.write8(VRAM + 0x1_40_00, 0x12);
+            assert_eq!(0, m.read16(VRAM + 0x1_40_00));
+        }
+
+        #[test]
+        fn write8_oam() {
+            let mut m = Memory::new();
+            m.write8(OAM, 0x12);
+
+            assert_eq!(0, m.read16(OAM));
+        }
+
+        #[test]
+        fn write_typed_unmapped() {
+            let mut m = Memory::new();
+            m.write8(0x01_00_00_00, 1);
+            m.write16(0x10_00_00_00, 1);
+            m.write32(0xFF_FF_FF_FC, 1);
+            m.write32(INT_WRAM + 0x7F_FC, 1);
+
+            assert_eq!(1, m.read32(INT_WRAM + 0x7F_FC));
+        }
+
+        #[test]
+        fn cycles() {
+            let mut m = Memory::new();
+            m.load_pak(&[0; 0x1_00]);
+
+            m.read32(INT_WRAM);
+            assert_eq!(1, m.cycles());
+
+            m.read16(EXT_WRAM);
+            assert_eq!(4, m.cycles());
+
+            // Non-sequential then sequential with the default 4,2 wait states
+            m.read32(PAK_ROM);
+            assert_eq!(12, m.cycles());
+            m.read32(PAK_ROM + 4);
+            assert_eq!(18, m.cycles());
+
+            m.idle(2);
+            assert_eq!(20, m.cycles());
+        }
+
+        #[test]
+        fn cycles_waitcnt() {
+            let mut m = Memory::new();
+            m.load_pak(&[0; 0x1_00]);
+            m.write16(IORAM + io::REG_WAITCNT, 0x0014);
+            let start = m.cycles();
+
+            // 3,1 wait states for WS0
+            m.read32(PAK_ROM);
+            assert_eq!(6, m.cycles() - start);
+            m.read32(PAK_ROM + 4);
+            assert_eq!(10, m.cycles() - start);
+        }
+
+        #[test]
+        fn cycles_rom_block_boundary() {
+            let mut m = Memory::new();
+            m.load_pak(&[0; 0x2_00_04]);
+
+            m.read16(PAK_ROM + 0x1_FF_FE);
+            let start = m.cycles();
+            m.read16(PAK_ROM + 0x2_00_00);
+            assert_eq!(5, m.cycles() - start);
+        }
+
+        #[test]
+        fn cycles_prefetch() {
+            let mut m = Memory::new();
+            m.load_pak(&[0; 0x1_00]);
+            m.write16(IORAM + io::REG_WAITCNT, 0x4014);
+
+            let start = m.cycles();
+            m.fetch32(PAK_ROM);
+            assert_eq!(6, m.cycles() - start);
+
+            // Two halfwords get buffered while the CPU is busy elsewhere
+            m.read32(INT_WRAM);
+            m.idle(3);
+            let start = m.cycles();
+            m.fetch32(PAK_ROM + 4);
+            assert_eq!(1, m.cycles() - start);
+
+            // Data reads from ROM stop the prefetcher
+            m.idle(8);
+            m.read16(PAK_ROM + 0x80);
+            let start = m.cycles();
+            m.fetch32(PAK_ROM + 8);
+            assert_eq!(6, m.cycles() - start);
+        }
+
+        #[test]
+        fn profile() {
+            let mut m = Memory::new();
+            m.set_profile(Some(profile::Profile::new()));
+            m.fetch32(PAK_ROM);
+            m.read16(EXT_WRAM + 0x0400);
+            m.write32(0x02_04_04_10, 0);
+
+            let p = m.profile().unwrap();
+            assert_eq!(1, p.region(Region::PakRom(0)).fetches);
+            assert_eq!(8, p.region(Region::PakRom(0)).cycles);
+            assert_eq!(1, p.page(EXT_WRAM + 0x0400).reads);
+            assert_eq!(1, p.page(EXT_WRAM + 0x0400).writes);
+            assert_eq!(9, p.region(Region::ExtWram).cycles);
+        }
+
+        #[test]
+        fn access_cycles() {
+            let m = Memory::new();
+            assert_eq!(8, m.access_cycles(PAK_ROM, 4, false));
+            assert_eq!(18, m.access_cycles(PAK_ROM2, 4, true));
+            assert_eq!(5, m.access_cycles(PAK_RAM, 1, false));
+        }
+
+        #[test]
+        fn mirror() {
+            assert_eq!(EXT_WRAM + 0x10, Memory::mirror(0x02_04_00_10));
+            assert_eq!(EXT_WRAM + 0x10, Memory::mirror(0x02_FC_00_10));
+            assert_eq!(INT_WRAM + 0x7F_F0, Memory::mirror(0x03_FF_FF_F0));
+            assert_eq!(PAL_RAM + 0x12, Memory::mirror(0x05_00_04_12));
+            assert_eq!(OAM + 0x3_00, Memory::mirror(0x07_FF_FF_00));
+
+            assert_eq!(VRAM + 0x1_00_00, Memory::mirror(0x06_01_00_00));
+            assert_eq!(VRAM + 0x1_00_00, Memory::mirror(0x06_01_80_00));
+            assert_eq!(VRAM + 0x1_7F_FF, Memory::mirror(0x06_01_FF_FF));
+            assert_eq!(VRAM + 0x10, Memory::mirror(0x06_02_00_10));
+
+            assert_eq!(IORAM + 0x2_00, Memory::mirror(IORAM + 0x2_00));
+            assert_eq!(0x04_01_02_00, Memory::mirror(0x04_01_02_00));
+            assert_eq!(IO_MEM_CTRL, Memory::mirror(0x04_FF_08_00));
+            assert_eq!(IO_MEM_CTRL + 2, Memory::mirror(0x04_01_08_02));
+
+            assert_eq!(SYS_ROM + 0x40_00, Memory::mirror(SYS_ROM + 0x40_00));
+        }
+
+        #[test]
+        fn read_write_io() {
+            let mut m = Memory::new();
+            m.write32(IORAM + io::REG_IE, 0xFFFF_1234);
+            assert_eq!(0x1234, m.read16(IORAM + io::REG_IE));
+            assert_eq!(0x0000_1234, m.read32(IORAM + io::REG_IE));
+
+            m.write8(IORAM + io::REG_IE + 1, 0x05);
+            assert_eq!(0x05, m.read8(IORAM + io::REG_IE + 1));
+            assert_eq!(0x0534, m.read16(IORAM + io::REG_IE));
+
+            assert_eq!(0x03FF, m.read16(IORAM + io::REG_KEYINPUT));
+            assert_eq!(0, m.read16(IORAM + 0x3FE));
+
+            m.set_open_bus(0xAABB_CCDD);
+            assert_eq!(0xAABB, m.read16(IORAM + 0x3FE));
+            assert_eq!(0xAABB_CCDD, m.read32(IORAM + io::REG_DMA3SAD));
+        }
+
+        #[test]
+        fn read_write_io_mem_ctrl() {
+            let mut m = Memory::new();
+            m.write32(0x04_01_08_00, 0x0D00_0020);
+
+            assert_eq!(0x0D00_0020, m.read32(IO_MEM_CTRL));
+            assert_eq!(0x0D00_0020, m.read32(0x04_FF_08_00));
+        }
+
+        #[test]
+        fn read_mirrored() {
+            let mut m = Memory::new();
+            m.write(INT_WRAM + 0x10, &[1, 2, 3, 4]);
 
-            assert_eq!(Some(vec![1, 2, 3, 4]), m.read(PAK_RAM, 4));
+            assert_eq!(Some(&[1, 2, 3, 4][..]), m.read(0x03_00_80_10, 4));
+            assert_eq!(Some(&[1, 2, 3, 4][..]), m.read(0x03_FF_80_10, 4));
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+use super::timing;
+use super::watch::AccessKind;
+use super::{Memory, Region};
+use super::{EXT_WRAM, EXT_WRAM_SIZE, INT_WRAM, INT_WRAM_SIZE};
+use crate::png;
+
+pub const PAGE_SIZE: u32 = 256;
+
+const REGIONS: [Region; 13] = [
+    Region::SysRom,
+    Region::ExtWram,
+    Region::IntWram,
+    Region::Io,
+    Region::IoMemCtrl,
+    Region::PalRam,
+    Region::Vram,
+    Region::Oam,
+    Region::PakRom(0),
+    Region::PakRom(1),
+    Region::PakRom(2),
+    Region::PakRam,
+    Region::Unmapped,
+];
+
+// The heatmap draws each page as a square cell, in rows of pages.
+const HEATMAP_ROW_PAGES: u32 = 32;
+const HEATMAP_CELL_SIZE: u32 = 8;
+const HEATMAP_GAP: [u8; 3] = [0x40, 0x40, 0x40];
+
+const IWRAM_PAGES: usize = INT_WRAM_SIZE / PAGE_SIZE as usize;
+const EWRAM_PAGES: usize = EXT_WRAM_SIZE / PAGE_SIZE as usize;
+
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Counts {
+    pub reads: u64,
+    pub writes: u64,
+    pub fetches: u64,
+    // Every cycle the accesses took, wait states included.
+    pub cycles: u64,
+    // Just the wait states, which are the cycles each access took past the
+    // one each of its bus transfers takes.
+    pub wait_cycles: u64,
+}
+
+impl Counts {
+    fn add(&mut self, kind: AccessKind, cycles: u32, waits: u32) {
+        match kind {
+            AccessKind::Read => self.reads += 1,
+            AccessKind::Write => self.writes += 1,
+            AccessKind::Execute => self.fetches += 1,
+        }
+        self.cycles += cycles as u64;
+        self.wait_cycles += waits as u64;
+    }
+
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes + self.fetches
+    }
+}
+
+// Counts the accesses made to each region of memory and to each 256 byte
+// page in it. Pages are keyed by their address with mirroring undone. The
+// work RAM pages, which see most accesses and which the heatmap draws, are
+// kept in arrays, and pages anywhere else only once they are accessed.
+pub struct Profile {
+    regions: [Counts; REGIONS.len()],
+    iwram: Box<[Counts; IWRAM_PAGES]>,
+    ewram: Box<[Counts; EWRAM_PAGES]>,
+    pages: BTreeMap<u32, Counts>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            regions: [Counts::default(); REGIONS.len()],
+            iwram: Box::new([Counts::default(); IWRAM_PAGES]),
+            ewram: Box::new([Counts::default(); EWRAM_PAGES]),
+            pages: BTreeMap::new(),
+        }
+    }
+
+    // Counts an access of size bytes that took cycles cycles.
+    pub fn record(&mut self, region: Region, addr: u32, size: usize, kind: AccessKind, cycles: u32) {
+        let waits = cycles.saturating_sub(timing::transfers(region, size));
+        self.regions[region_index(region)].add(kind, cycles, waits);
+
+        let page = Memory::mirror(addr) & !(PAGE_SIZE - 1);
+        let counts = match region {
+            Region::IntWram => &mut self.iwram[((page - INT_WRAM) / PAGE_SIZE) as usize],
+            Region::ExtWram => &mut self.ewram[((page - EXT_WRAM) / PAGE_SIZE) as usize],
+            _ => self.pages.entry(page).or_default(),
+        };
+        counts.add(kind, cycles, waits);
+    }
+
+    pub fn region(&self, region: Region) -> Counts {
+        self.regions[region_index(region)]
+    }
+
+    pub fn page(&self, addr: u32) -> Counts {
+        let page = Memory::mirror(addr) & !(PAGE_SIZE - 1);
+        match Memory::locate(page).0 {
+            Region::IntWram => self.iwram[((page - INT_WRAM) / PAGE_SIZE) as usize],
+            Region::ExtWram => self.ewram[((page - EXT_WRAM) / PAGE_SIZE) as usize],
+            _ => self.pages.get(&page).cloned().unwrap_or_default(),
+        }
+    }
+
+    // Returns every page that was accessed, in address order.
+    fn used_pages(&self) -> Vec<(u32, Counts)> {
+        let wram = |base: u32, pages: &[Counts]| {
+            pages.iter()
+                .enumerate()
+                .filter(|(_, counts)| counts.accesses() > 0)
+                .map(|(idx, counts)| (base + idx as u32 * PAGE_SIZE, *counts))
+                .collect::<Vec<_>>()
+        };
+
+        let mut pages: Vec<(u32, Counts)> = self.pages.iter().map(|(addr, counts)| (*addr, *counts)).collect();
+        pages.extend(wram(EXT_WRAM, &self.ewram[..]));
+        pages.extend(wram(INT_WRAM, &self.iwram[..]));
+        pages.sort_by_key(|(addr, _)| *addr);
+        pages
+    }
+
+    // Writes a row for every region and then every page that was accessed.
+    pub fn write_csv(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "scope,region,address,reads,writes,fetches,cycles,wait_cycles")?;
+        for (region, counts) in self.used_regions() {
+            writeln!(w, "region,{},{:08X},{},{},{},{},{}", region.name(), region.base(),
+                     counts.reads, counts.writes, counts.fetches, counts.cycles, counts.wait_cycles)?;
+        }
+        for (addr, counts) in self.used_pages() {
+            writeln!(w, "page,{},{:08X},{},{},{},{},{}", Memory::locate(addr).0.name(), addr,
+                     counts.reads, counts.writes, counts.fetches, counts.cycles, counts.wait_cycles)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, w: &mut dyn Write) -> io::Result<()> {
+        let counts_json = |c: &Counts| {
+            format!("\"reads\": {}, \"writes\": {}, \"fetches\": {}, \"cycles\": {}, \"wait_cycles\": {}",
+                    c.reads, c.writes, c.fetches, c.cycles, c.wait_cycles)
+        };
+
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"regions\": [")?;
+        let regions = self.used_regions();
+        for (idx, (region, counts)) in regions.iter().enumerate() {
+            let sep = if idx + 1 < regions.len() { "," } else { "" };
+            writeln!(w, "    {{\"region\": \"{}\", \"address\": \"{:08X}\", {}}}{}",
+                     region.name(), region.base(), counts_json(counts), sep)?;
+        }
+        writeln!(w, "  ],")?;
+        writeln!(w, "  \"pages\": [")?;
+        let pages = self.used_pages();
+        for (idx, (addr, counts)) in pages.iter().enumerate() {
+            let sep = if idx + 1 < pages.len() { "," } else { "" };
+            writeln!(w, "    {{\"region\": \"{}\", \"address\": \"{:08X}\", {}}}{}",
+                     Memory::locate(*addr).0.name(), addr, counts_json(counts), sep)?;
+        }
+        writeln!(w, "  ]")?;
+        writeln!(w, "}}")
+    }
+
+    // Draws how often each page of IWRAM (on top) and EWRAM was accessed, on
+    // a log scale running from black through red and yellow to white.
+    pub fn write_heatmap(&self, w: &mut dyn Write) -> io::Result<()> {
+        let iwram_rows = INT_WRAM_SIZE as u32 / PAGE_SIZE / HEATMAP_ROW_PAGES;
+        let ewram_rows = EXT_WRAM_SIZE as u32 / PAGE_SIZE / HEATMAP_ROW_PAGES;
+
+        let max = self.iwram.iter()
+            .chain(self.ewram.iter())
+            .map(|counts| counts.accesses())
+            .max()
+            .unwrap_or(0);
+
+        // Each row of pages is followed by the row of pixels it covers, with
+        // a gap row between the two regions.
+        let mut cells = Vec::new();
+        for row in 0..iwram_rows {
+            cells.push(Some(self.heatmap_row(INT_WRAM, row, max)));
+        }
+        cells.push(None);
+        for row in 0..ewram_rows {
+            cells.push(Some(self.heatmap_row(EXT_WRAM, row, max)));
+        }
+
+        let width = HEATMAP_ROW_PAGES * HEATMAP_CELL_SIZE;
+        let height = cells.len() as u32 * HEATMAP_CELL_SIZE;
+        let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+        for row in cells.iter() {
+            let mut line = Vec::with_capacity(width as usize * 3);
+            for idx in 0..HEATMAP_ROW_PAGES as usize {
+                let colour = match row {
+                    Some(colours) => colours[idx],
+                    None => HEATMAP_GAP,
+                };
+                for _ in 0..HEATMAP_CELL_SIZE {
+                    line.extend_from_slice(&colour);
+                }
+            }
+            for _ in 0..HEATMAP_CELL_SIZE {
+                pixels.extend_from_slice(&line);
+            }
+        }
+
+        png::write_rgb(w, width, height, &pixels)
+    }
+
+    fn heatmap_row(&self, base: u32, row: u32, max: u64) -> Vec<[u8; 3]> {
+        (0..HEATMAP_ROW_PAGES)
+            .map(|idx| {
+                let addr = base + (row * HEATMAP_ROW_PAGES + idx) * PAGE_SIZE;
+                heat(self.page(addr).accesses(), max)
+            })
+            .collect()
+    }
+
+    fn used_regions(&self) -> Vec<(Region, Counts)> {
+        REGIONS.iter()
+            .zip(self.regions.iter())
+            .filter(|(_, counts)| counts.accesses() > 0)
+            .map(|(region, counts)| (*region, *counts))
+            .collect()
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile::new()
+    }
+}
+
+fn region_index(region: Region) -> usize {
+    match region {
+        Region::SysRom => 0,
+        Region::ExtWram => 1,
+        Region::IntWram => 2,
+        Region::Io => 3,
+        Region::IoMemCtrl => 4,
+        Region::PalRam => 5,
+        Region::Vram => 6,
+        Region::Oam => 7,
+        Region::PakRom(ws) => 8 + ws,
+        Region::PakRam => 11,
+        Region::Unmapped => 12,
+    }
+}
+
+fn heat(val: u64, max: u64) -> [u8; 3] {
+    if val == 0 || max == 0 {
+        return [0, 0, 0];
+    }
+
+    let t = ((val as f64).ln_1p() / (max as f64).ln_1p()) * 3.0;
+    let channel = |offset: f64| ((t - offset).clamp(0.0, 1.0) * 255.0) as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
+
+#[cfg(test)]
+mod tests {
+    mod profile {
+        use super::super::*;
+
+        fn profile() -> Profile {
+            let mut p = Profile::new();
+            let wc = timing::WaitControl::new();
+            p.record(Region::IntWram, INT_WRAM + 0x104, 4, AccessKind::Read, 1);
+            p.record(Region::IntWram, 0x03_FF_81_00, 1, AccessKind::Write, 1);
+            p.record(Region::PakRom(0), 0x08_00_00_00, 4, AccessKind::Execute, wc.cycles(Region::PakRom(0), 4, false));
+            p
+        }
+
+        #[test]
+        fn record() {
+            let mut p = profile();
+            let wc = timing::WaitControl::new();
+            p.record(Region::ExtWram, EXT_WRAM, 4, AccessKind::Read, wc.cycles(Region::ExtWram, 4, false));
+            p.record(Region::Vram, 0x06_00_00_00, 4, AccessKind::Write, wc.cycles(Region::Vram, 4, false));
+
+            assert_eq!(Counts { reads: 1, writes: 1, fetches: 0, cycles: 2, wait_cycles: 0 }, p.region(Region::IntWram));
+            assert_eq!(Counts { reads: 0, writes: 0, fetches: 1, cycles: 8, wait_cycles: 6 }, p.region(Region::PakRom(0)));
+            assert_eq!(6, p.page(0x08_00_00_10).wait_cycles);
+            assert_eq!(Counts { reads: 1, writes: 0, fetches: 0, cycles: 6, wait_cycles: 4 }, p.region(Region::ExtWram));
+            assert_eq!(Counts { reads: 0, writes: 1, fetches: 0, cycles: 2, wait_cycles: 0 }, p.region(Region::Vram));
+            assert_eq!(Counts::default(), p.region(Region::PakRom(1)));
+            assert_eq!(2, p.page(INT_WRAM + 0x1FF).accesses());
+            assert_eq!(0, p.page(INT_WRAM + 0x200).accesses());
+        }
+
+        #[test]
+        fn write_csv() {
+            let mut buf = Vec::new();
+            profile().write_csv(&mut buf).unwrap();
+
+            assert_eq!(
+                "scope,region,address,reads,writes,fetches,cycles,wait_cycles\n\
+                 region,iwram,03000000,1,1,0,2,0\n\
+                 region,rom0,08000000,0,0,1,8,6\n\
+                 page,iwram,03000100,1,1,0,2,0\n\
+                 page,rom0,08000000,0,0,1,8,6\n",
+                String::from_utf8(buf).unwrap(),
+            );
+        }
+
+        #[test]
+        fn write_json() {
+            let mut buf = Vec::new();
+            profile().write_json(&mut buf).unwrap();
+
+            let json = String::from_utf8(buf).unwrap();
+            assert!(json.contains(
+                "    {\"region\": \"iwram\", \"address\": \"03000000\", \
+                 \"reads\": 1, \"writes\": 1, \"fetches\": 0, \"cycles\": 2, \"wait_cycles\": 0},\n"));
+            assert!(json.contains(
+                "    {\"region\": \"rom0\", \"address\": \"08000000\", \
+                 \"reads\": 0, \"writes\": 0, \"fetches\": 1, \"cycles\": 8, \"wait_cycles\": 6}\n  ]"));
+        }
+
+        #[test]
+        fn write_heatmap() {
+            let mut buf = Vec::new();
+            profile().write_heatmap(&mut buf).unwrap();
+
+            // 256 pixels wide, and 4 rows of IWRAM, a gap and 32 rows of
+            // EWRAM high.
+            assert_eq!(&[0, 0, 1, 0, 0, 0, 1, 0x28], &buf[16..24]);
+        }
+
+        #[test]
+        fn heat_scale() {
+            assert_eq!([0, 0, 0], heat(0, 10));
+            assert_eq!([255, 255, 255], heat(10, 10));
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use super::Region;
+
+pub const WAITCNT_PREFETCH: u16 = 1 << 14;
+
+// Wait states are selected by two or one bit fields in WAITCNT.
+const FIRST_WAIT: [u32; 4] = [4, 3, 2, 8];
+const WS0_SECOND_WAIT: [u32; 2] = [2, 1];
+const WS1_SECOND_WAIT: [u32; 2] = [4, 1];
+const WS2_SECOND_WAIT: [u32; 2] = [8, 1];
+
+// The prefetch buffer holds up to eight halfwords of code.
+const PREFETCH_SIZE: u32 = 8;
+
+// The wait states set by WAITCNT, in cycles on top of the one cycle every
+// access takes.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct WaitControl {
+    pub sram: u32,
+    pub first: [u32; 3],
+    pub second: [u32; 3],
+    pub prefetch: bool,
+}
+
+impl WaitControl {
+    pub fn new() -> WaitControl {
+        WaitControl::from_waitcnt(0)
+    }
+
+    pub fn from_waitcnt(val: u16) -> WaitControl {
+        let field = |shift: u16, mask: u16| ((val >> shift) & mask) as usize;
+
+        WaitControl {
+            sram: FIRST_WAIT[field(0, 0x3)],
+            first: [
+                FIRST_WAIT[field(2, 0x3)],
+                FIRST_WAIT[field(5, 0x3)],
+                FIRST_WAIT[field(8, 0x3)],
+            ],
+            second: [
+                WS0_SECOND_WAIT[field(4, 0x1)],
+                WS1_SECOND_WAIT[field(7, 0x1)],
+                WS2_SECOND_WAIT[field(10, 0x1)],
+            ],
+            prefetch: val & WAITCNT_PREFETCH != 0,
+        }
+    }
+
+    // Returns how many cycles an access of size bytes to region takes. A
+    // sequential access is one to the address right after the previous
+    // access. Word accesses over a 16-bit bus are made as two halfword
+    // accesses, the second of which is always sequential.
+    pub fn cycles(&self, region: Region, size: usize, seq: bool) -> u32 {
+        match region {
+            Region::ExtWram => match size {
+                4 => 6,
+                _ => 3,
+            },
+            Region::PalRam | Region::Vram => match size {
+                4 => 2,
+                _ => 1,
+            },
+            Region::PakRom(ws) => {
+                let first = match seq {
+                    true => self.second[ws] + 1,
+                    false => self.first[ws] + 1,
+                };
+                match size {
+                    4 => first + self.second[ws] + 1,
+                    _ => first,
+                }
+            }
+            Region::PakRam => self.sram + 1,
+            _ => 1,
+        }
+    }
+}
+
+// Returns how many transfers the bus makes for an access of size bytes to
+// region. Each takes one cycle of its own, and any cycles past those are wait
+// states.
+pub fn transfers(region: Region, size: usize) -> u32 {
+    match region {
+        Region::ExtWram | Region::PalRam | Region::Vram | Region::PakRom(_) if size == 4 => 2,
+        _ => 1,
+    }
+}
+
+impl Default for WaitControl {
+    fn default() -> WaitControl {
+        WaitControl::new()
+    }
+}
+
+// The gamepak prefetch buffer. While the CPU runs code from ROM and is busy
+// with anything other than the gamepak bus, the buffer reads ahead the
+// halfwords that come after the last opcode fetched. Fetching code that is
+// already buffered only takes a single cycle.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Prefetch {
+    active: bool,
+    next: u32,
+    count: u32,
+    progress: u32,
+    cost: u32,
+}
+
+impl Prefetch {
+    pub fn new() -> Prefetch {
+        Prefetch {
+            active: false,
+            next: 0,
+            count: 0,
+            progress: 0,
+            cost: 0,
+        }
+    }
+
+    // Empties the buffer and stops it until code is fetched from ROM again.
+    // This happens whenever the gamepak bus is used for data.
+    pub fn reset(&mut self) {
+        *self = Prefetch::new();
+    }
+
+    // Starts buffering from next, with each halfword taking cost cycles.
+    pub fn restart(&mut self, next: u32, cost: u32) {
+        self.active = true;
+        self.next = next;
+        self.count = 0;
+        self.progress = 0;
+        self.cost = cost;
+    }
+
+    // Lets the buffer fill during cycles the gamepak bus is otherwise idle.
+    pub fn run(&mut self, cycles: u32) {
+        if !self.active || self.count == PREFETCH_SIZE {
+            return;
+        }
+
+        self.progress += cycles;
+        while self.count < PREFETCH_SIZE && self.progress >= self.cost {
+            self.count += 1;
+            self.progress -= self.cost;
+        }
+        if self.count == PREFETCH_SIZE {
+            self.progress = 0;
+        }
+    }
+
+    // Fetches size bytes of code at addr through the buffer, returning the
+    // cycles it took, or None if the buffer isn't reading ahead from addr.
+    pub fn fetch(&mut self, addr: u32, size: u32) -> Option<u32> {
+        if !self.active || addr != self.next {
+            return None;
+        }
+
+        let halfwords = size / 2;
+        let cycles = match self.count >= halfwords {
+            true => {
+                self.count -= halfwords;
+                1
+            }
+            false => {
+                // Wait for the halfword in flight, then for any others after
+                // it.
+                let missing = halfwords - self.count;
+                let cycles = (self.cost - self.progress) + (missing - 1) * self.cost;
+                self.count = 0;
+                self.progress = 0;
+                cycles
+            }
+        };
+
+        self.next = addr.wrapping_add(size);
+        Some(cycles)
+    }
+}
+
+impl Default for Prefetch {
+    fn default() -> Prefetch {
+        Prefetch::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod wait_control {
+        use super::super::*;
+
+        #[test]
+        fn from_waitcnt_default() {
+            let wc = WaitControl::from_waitcnt(0);
+            assert_eq!(4, wc.sram);
+            assert_eq!([4, 4, 4], wc.first);
+            assert_eq!([2, 4, 8], wc.second);
+            assert!(!wc.prefetch);
+        }
+
+        #[test]
+        fn from_waitcnt() {
+            // The usual value set by games: 3,1 for WS0 with prefetch on.
+            let wc = WaitControl::from_waitcnt(0x4317);
+            assert_eq!(8, wc.sram);
+            assert_eq!([3, 4, 8], wc.first);
+            assert_eq!([1, 4, 8], wc.second);
+            assert!(wc.prefetch);
+        }
+
+        #[test]
+        fn cycles() {
+            let wc = WaitControl::from_waitcnt(0);
+            assert_eq!(1, wc.cycles(Region::IntWram, 4, false));
+            assert_eq!(3, wc.cycles(Region::ExtWram, 2, false));
+            assert_eq!(6, wc.cycles(Region::ExtWram, 4, false));
+            assert_eq!(2, wc.cycles(Region::Vram, 4, false));
+            assert_eq!(5, wc.cycles(Region::PakRam, 1, false));
+
+            assert_eq!(5, wc.cycles(Region::PakRom(0), 2, false));
+            assert_eq!(3, wc.cycles(Region::PakRom(0), 2, true));
+            assert_eq!(8, wc.cycles(Region::PakRom(0), 4, false));
+            assert_eq!(6, wc.cycles(Region::PakRom(0), 4, true));
+            assert_eq!(18, wc.cycles(Region::PakRom(2), 4, true));
+        }
+
+        #[test]
+        fn transfers() {
+            assert_eq!(1, super::super::transfers(Region::IntWram, 4));
+            assert_eq!(1, super::super::transfers(Region::ExtWram, 2));
+            assert_eq!(2, super::super::transfers(Region::ExtWram, 4));
+            assert_eq!(2, super::super::transfers(Region::Vram, 4));
+            assert_eq!(2, super::super::transfers(Region::PakRom(1), 4));
+            assert_eq!(1, super::super::transfers(Region::PakRam, 4));
+        }
+    }
+
+    mod prefetch {
+        use super::super::*;
+
+        #[test]
+        fn fetch_inactive() {
+            let mut p = Prefetch::new();
+            p.run(100);
+
+            assert_eq!(None, p.fetch(0x08_00_00_00, 4));
+        }
+
+        #[test]
+        fn fetch_buffered() {
+            let mut p = Prefetch::new();
+            p.restart(0x08_00_00_04, 2);
+            p.run(4);
+
+            assert_eq!(Some(1), p.fetch(0x08_00_00_04, 4));
+            assert_eq!(Some(4), p.fetch(0x08_00_00_08, 4));
+        }
+
+        #[test]
+        fn fetch_partial() {
+            let mut p = Prefetch::new();
+            p.restart(0x08_00_00_04, 3);
+            p.run(4);
+
+            // One halfword is buffered and the next is one cycle in.
+            assert_eq!(Some(2), p.fetch(0x08_00_00_04, 4));
+        }
+
+        #[test]
+        fn fetch_other_address() {
+            let mut p = Prefetch::new();
+            p.restart(0x08_00_00_04, 2);
+            p.run(16);
+
+            assert_eq!(None, p.fetch(0x08_00_01_00, 4));
+        }
+
+        #[test]
+        fn run_full() {
+            let mut p = Prefetch::new();
+            p.restart(0x08_00_00_00, 2);
+            p.run(1000);
+
+            for idx in 0..4 {
+                assert_eq!(Some(1), p.fetch(0x08_00_00_00 + idx * 4, 4));
+            }
+            assert_eq!(Some(4), p.fetch(0x08_00_00_10, 4));
+        }
+
+        #[test]
+        fn reset() {
+            let mut p = Prefetch::new();
+            p.restart(0x08_00_00_00, 2);
+            p.reset();
+            p.run(16);
+
+            assert_eq!(None, p.fetch(0x08_00_00_00, 4));
+        }
+    }
+}
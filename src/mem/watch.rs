@@ -0,0 +1,203 @@
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+impl AccessKind {
+    fn bit(self) -> u8 {
+        match self {
+            AccessKind::Read => 1 << 0,
+            AccessKind::Write => 1 << 1,
+            AccessKind::Execute => 1 << 2,
+        }
+    }
+}
+
+const ALL_KINDS: u8 = 0x7;
+const ALL_SIZES: u8 = (1 << 1) | (1 << 2) | (1 << 4);
+
+// A single bus access as seen by an observer. The value is the one read or
+// the one the CPU wrote, and pc is the address of the instruction that made
+// the access.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub addr: u32,
+    pub size: usize,
+    pub val: u32,
+    pub pc: u32,
+    pub cycle: u64,
+}
+
+pub type ObserverFn = Box<dyn FnMut(&Access)>;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct WatchId(usize);
+
+// Calls a function for every access to an address range, from start up to
+// and including end. By default every kind and size of access is watched,
+// which can be narrowed with kinds and sizes.
+pub struct Watchpoint {
+    start: u32,
+    end: u32,
+    kinds: u8,
+    sizes: u8,
+    observer: ObserverFn,
+}
+
+impl Watchpoint {
+    pub fn new(start: u32, end: u32, observer: ObserverFn) -> Watchpoint {
+        Watchpoint {
+            start,
+            end,
+            kinds: ALL_KINDS,
+            sizes: ALL_SIZES,
+            observer,
+        }
+    }
+
+    pub fn kinds(mut self, kinds: &[AccessKind]) -> Watchpoint {
+        self.kinds = kinds.iter().fold(0, |bits, kind| bits | kind.bit());
+        self
+    }
+
+    pub fn sizes(mut self, sizes: &[usize]) -> Watchpoint {
+        self.sizes = sizes.iter().fold(0, |bits, size| bits | (1 << size) as u8);
+        self
+    }
+
+    // Accesses hit the watchpoint if any of the bytes they touch are in
+    // range.
+    fn matches(&self, kind: AccessKind, addr: u32, size: usize) -> bool {
+        let last = addr.wrapping_add(size as u32 - 1);
+        self.kinds & kind.bit() != 0
+            && self.sizes & (1 << size) as u8 != 0
+            && addr <= self.end
+            && last >= self.start
+    }
+}
+
+pub struct Observers {
+    watchpoints: Vec<(WatchId, Watchpoint)>,
+    next_id: usize,
+}
+
+impl Observers {
+    pub fn new() -> Observers {
+        Observers {
+            watchpoints: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub fn add(&mut self, wp: Watchpoint) -> WatchId {
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        self.watchpoints.push((id, wp));
+        id
+    }
+
+    // Removes a watchpoint, returning false if it was already removed.
+    pub fn remove(&mut self, id: WatchId) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|(wp_id, _)| *wp_id != id);
+        self.watchpoints.len() != len
+    }
+
+    // Passes the access on to every watchpoint it hits. Ranges are matched
+    // against addr, which the caller should already have mapped out of any
+    // mirror.
+    pub fn notify(&mut self, addr: u32, access: &Access) {
+        for (_, wp) in self.watchpoints.iter_mut() {
+            if wp.matches(access.kind, addr, access.size) {
+                (wp.observer)(access);
+            }
+        }
+    }
+}
+
+impl Default for Observers {
+    fn default() -> Observers {
+        Observers::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod watchpoint {
+        use super::super::*;
+
+        fn watchpoint() -> Watchpoint {
+            Watchpoint::new(0x100, 0x1FF, Box::new(|_| {}))
+        }
+
+        #[test]
+        fn matches_range() {
+            let wp = watchpoint();
+            assert!(wp.matches(AccessKind::Read, 0x100, 1));
+            assert!(wp.matches(AccessKind::Read, 0x1FF, 1));
+            assert!(wp.matches(AccessKind::Read, 0xFE, 4));
+            assert!(!wp.matches(AccessKind::Read, 0xFC, 4));
+            assert!(!wp.matches(AccessKind::Read, 0x200, 2));
+        }
+
+        #[test]
+        fn matches_kinds() {
+            let wp = watchpoint().kinds(&[AccessKind::Write, AccessKind::Execute]);
+            assert!(!wp.matches(AccessKind::Read, 0x100, 1));
+            assert!(wp.matches(AccessKind::Write, 0x100, 1));
+            assert!(wp.matches(AccessKind::Execute, 0x100, 4));
+        }
+
+        #[test]
+        fn matches_sizes() {
+            let wp = watchpoint().sizes(&[2]);
+            assert!(!wp.matches(AccessKind::Read, 0x100, 1));
+            assert!(wp.matches(AccessKind::Read, 0x100, 2));
+            assert!(!wp.matches(AccessKind::Read, 0x100, 4));
+        }
+    }
+
+    mod observers {
+        use super::super::*;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        fn access(addr: u32) -> Access {
+            Access {
+                kind: AccessKind::Read,
+                addr,
+                size: 4,
+                val: 0,
+                pc: 0,
+                cycle: 0,
+            }
+        }
+
+        #[test]
+        fn add_remove() {
+            let hits = Rc::new(Cell::new(0));
+            let counter = hits.clone();
+
+            let mut o = Observers::new();
+            assert!(o.is_empty());
+
+            let id = o.add(Watchpoint::new(0x100, 0x1FF, Box::new(move |_| counter.set(counter.get() + 1))));
+            o.notify(0x100, &access(0x100));
+            o.notify(0x300, &access(0x300));
+            assert_eq!(1, hits.get());
+
+            assert!(o.remove(id));
+            assert!(!o.remove(id));
+            assert!(o.is_empty());
+            o.notify(0x100, &access(0x100));
+            assert_eq!(1, hits.get());
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::io;
+use std::io::Write;
+
+use crate::crc32;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// The most a stored deflate block can hold.
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+// Writes an 8-bit RGB image as a PNG. The image data is stored without
+// compression, which keeps the encoder tiny at the cost of bigger files.
+pub fn write_rgb(w: &mut dyn Write, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let stride = width as usize * 3;
+    if pixels.len() != stride * height as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "pixel data does not match image size"));
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, adaptive filtering, no interlace.
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Every row starts with its filter type, which is always none here.
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in pixels.chunks(stride.max(1)) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    w.write_all(&SIGNATURE)?;
+    write_chunk(w, b"IHDR", &ihdr)?;
+    write_chunk(w, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(w, b"IEND", &[])
+}
+
+fn write_chunk(w: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&crc32::update(crc32::crc32(kind), data).to_be_bytes())
+}
+
+// Wraps data in a zlib stream made of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / STORED_BLOCK_SIZE + 1;
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(STORED_BLOCK_SIZE).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+
+    let (mut a, mut b) = (1, 0);
+    for chunk in data.chunks(5552) {
+        for byte in chunk.iter() {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    mod png {
+        use super::super::*;
+
+        #[test]
+        fn adler32_check_value() {
+            assert_eq!(0x11E60398, adler32(b"Wikipedia"));
+            assert_eq!(1, adler32(&[]));
+        }
+
+        #[test]
+        fn zlib_stored_blocks() {
+            let data = vec![0xAB; STORED_BLOCK_SIZE + 1];
+            let z = zlib_stored(&data);
+
+            assert_eq!(data.len() + 2 * 5 + 6, z.len());
+            assert_eq!(&[0x78, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x00], &z[..7]);
+            let last = 7 + STORED_BLOCK_SIZE;
+            assert_eq!(&[0x01, 0x01, 0x00, 0xFE, 0xFF, 0xAB], &z[last..last + 6]);
+        }
+
+        #[test]
+        fn write_rgb_chunks() {
+            let mut buf = Vec::new();
+            write_rgb(&mut buf, 2, 1, &[255, 0, 0, 0, 0, 255]).unwrap();
+
+            assert_eq!(SIGNATURE, buf[..8]);
+            assert_eq!(b"IHDR", &buf[12..16]);
+            assert_eq!(&[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0], &buf[16..29]);
+            // IEND is always the same twelve bytes.
+            assert_eq!(
+                &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82],
+                &buf[buf.len() - 12..],
+            );
+        }
+
+        #[test]
+        fn write_rgb_wrong_size() {
+            let mut buf = Vec::new();
+            assert!(write_rgb(&mut buf, 2, 2, &[0; 6]).is_err());
+        }
+    }
+}
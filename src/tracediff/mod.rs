@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+
+use crate::cpu::trace::{TraceRecord, BINARY_MAGIC};
+
+const FLAGS: [(u32, char); 7] = [
+    (1 << 31, 'N'),
+    (1 << 30, 'Z'),
+    (1 << 29, 'C'),
+    (1 << 28, 'V'),
+    (1 << 7, 'I'),
+    (1 << 6, 'F'),
+    (1 << 5, 'T'),
+];
+
+// Reads trace records one at a time so traces of any size can be compared.
+// Both the text and binary trace formats are supported, detected by the
+// binary magic at the start of the file.
+pub struct TraceReader {
+    input: Input,
+    line: usize,
+}
+
+enum Input {
+    Text(Box<dyn BufRead>),
+    Binary(Box<dyn BufRead>),
+}
+
+impl TraceReader {
+    pub fn open(path: &str) -> io::Result<TraceReader> {
+        TraceReader::new(Box::new(BufReader::new(File::open(path)?)))
+    }
+
+    pub fn new(mut r: Box<dyn BufRead>) -> io::Result<TraceReader> {
+        let binary = r.fill_buf()?.starts_with(BINARY_MAGIC);
+        let input = match binary {
+            true => {
+                r.consume(BINARY_MAGIC.len());
+                Input::Binary(r)
+            }
+            false => Input::Text(r),
+        };
+
+        Ok(TraceReader { input, line: 0 })
+    }
+
+    // Returns the next record, skipping over any text lines that are not
+    // trace records (log output mixed into the trace, for example).
+    pub fn next_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        match self.input {
+            Input::Binary(ref mut r) => {
+                let record = TraceRecord::read_binary(r)?;
+                if record.is_some() {
+                    self.line += 1;
+                }
+                Ok(record)
+            }
+            Input::Text(ref mut r) => {
+                let mut buf = String::new();
+                loop {
+                    buf.clear();
+                    if r.read_line(&mut buf)? == 0 {
+                        return Ok(None);
+                    }
+                    self.line += 1;
+
+                    if let Some(record) = TraceRecord::parse_line(buf.trim_end()) {
+                        return Ok(Some(record));
+                    }
+                }
+            }
+        }
+    }
+
+    // The line (or record number, for binary traces) of the last record read.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Difference {
+    Pc(u32, u32),
+    Opcode(u32, u32),
+    Reg(usize, u32, u32),
+    Flag(char, bool, bool),
+    Mode(u32, u32),
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    // Zero-based index of the first instruction whose state differs.
+    pub index: u64,
+    pub left_line: usize,
+    pub right_line: usize,
+    pub before: Vec<(TraceRecord, TraceRecord)>,
+    pub left: Option<TraceRecord>,
+    pub right: Option<TraceRecord>,
+    pub after_left: Vec<TraceRecord>,
+    pub after_right: Vec<TraceRecord>,
+    pub differences: Vec<Difference>,
+}
+
+// Walks both traces in lockstep and returns the first instruction where they
+// disagree, along with `context` instructions on either side. Traces that
+// are identical return None, while a trace that ends early is reported as a
+// divergence with the missing side left empty.
+pub fn diff(left: &mut TraceReader, right: &mut TraceReader, context: usize) -> io::Result<Option<Divergence>> {
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut index = 0;
+
+    loop {
+        let l = left.next_record()?;
+        let r = right.next_record()?;
+
+        let differences = match (&l, &r) {
+            (None, None) => return Ok(None),
+            (Some(l), Some(r)) => compare(l, r),
+            _ => Vec::new(),
+        };
+
+        match (&l, &r) {
+            (Some(l), Some(r)) if differences.is_empty() => {
+                if context > 0 {
+                    if before.len() == context {
+                        before.pop_front();
+                    }
+                    before.push_back((l.clone(), r.clone()));
+                }
+                index += 1;
+            }
+            _ => {
+                return Ok(Some(Divergence {
+                    index,
+                    left_line: left.line(),
+                    right_line: right.line(),
+                    before: before.into_iter().collect(),
+                    left: l,
+                    right: r,
+                    after_left: take(left, context)?,
+                    after_right: take(right, context)?,
+                    differences,
+                }));
+            }
+        }
+    }
+}
+
+pub fn compare(l: &TraceRecord, r: &TraceRecord) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    if l.pc != r.pc {
+        differences.push(Difference::Pc(l.pc, r.pc));
+    }
+    if l.opcode != r.opcode {
+        differences.push(Difference::Opcode(l.opcode, r.opcode));
+    }
+    for idx in 0..16 {
+        if l.regs[idx] != r.regs[idx] {
+            differences.push(Difference::Reg(idx, l.regs[idx], r.regs[idx]));
+        }
+    }
+    for (mask, name) in FLAGS.iter() {
+        let (lf, rf) = (l.cpsr & mask != 0, r.cpsr & mask != 0);
+        if lf != rf {
+            differences.push(Difference::Flag(*name, lf, rf));
+        }
+    }
+    if l.cpsr & 0x1F != r.cpsr & 0x1F {
+        differences.push(Difference::Mode(l.cpsr & 0x1F, r.cpsr & 0x1F));
+    }
+
+    differences
+}
+
+fn take(r: &mut TraceReader, count: usize) -> io::Result<Vec<TraceRecord>> {
+    let mut records = Vec::with_capacity(count);
+    while records.len() < count {
+        match r.next_record()? {
+            Some(record) => records.push(record),
+            None => break,
+        }
+    }
+    Ok(records)
+}
+
+impl Divergence {
+    pub fn report(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "traces diverge at instruction {} (left line {}, right line {})",
+                 self.index, self.left_line, self.right_line)?;
+
+        if !self.before.is_empty() {
+            writeln!(w, "\nmatching instructions before:")?;
+            for (l, _) in self.before.iter() {
+                writeln!(w, "  {}", l)?;
+            }
+        }
+
+        writeln!(w, "\nfirst divergent instruction:")?;
+        match &self.left {
+            Some(l) => writeln!(w, "< {}", l)?,
+            None => writeln!(w, "< (end of trace)")?,
+        }
+        match &self.right {
+            Some(r) => writeln!(w, "> {}", r)?,
+            None => writeln!(w, "> (end of trace)")?,
+        }
+
+        if !self.differences.is_empty() {
+            writeln!(w, "\ndifferences (left / right):")?;
+            for d in self.differences.iter() {
+                match d {
+                    Difference::Pc(l, r) => writeln!(w, "  pc: {:08X} / {:08X}", l, r)?,
+                    Difference::Opcode(l, r) => writeln!(w, "  opcode: {:08X} / {:08X}", l, r)?,
+                    Difference::Reg(idx, l, r) => writeln!(w, "  r{}: {:08X} / {:08X}", idx, l, r)?,
+                    Difference::Flag(name, l, r) => writeln!(w, "  flag {}: {} / {}", name, *l as u8, *r as u8)?,
+                    Difference::Mode(l, r) => writeln!(w, "  mode: {:02X} / {:02X}", l, r)?,
+                }
+            }
+
+            // The state is logged before each instruction runs, so a register
+            // difference was caused by the instruction before it.
+            if let Some((l, _)) = self.before.last() {
+                writeln!(w, "  likely caused by: {:08X}: {:08X}  {}", l.pc, l.opcode, l.disasm)?;
+            }
+        }
+
+        if !self.after_left.is_empty() || !self.after_right.is_empty() {
+            writeln!(w, "\nleft after:")?;
+            for l in self.after_left.iter() {
+                writeln!(w, "< {}", l)?;
+            }
+            writeln!(w, "\nright after:")?;
+            for r in self.after_right.iter() {
+                writeln!(w, "> {}", r)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod diff {
+        use super::super::*;
+        use std::io::Cursor;
+
+        fn record(pc: u32, r0: u32, cpsr: u32) -> TraceRecord {
+            let mut regs = [0; 16];
+            regs[0] = r0;
+            regs[15] = pc + 8;
+            TraceRecord {
+                pc,
+                opcode: 0xE3_A0_00_12,
+                regs,
+                cpsr,
+                disasm: String::from("MOV r0, #0x12"),
+            }
+        }
+
+        fn text_reader(records: &[TraceRecord]) -> TraceReader {
+            let mut buf = Vec::new();
+            for r in records.iter() {
+                r.write_text(&mut buf).unwrap();
+            }
+            TraceReader::new(Box::new(Cursor::new(buf))).unwrap()
+        }
+
+        fn binary_reader(records: &[TraceRecord]) -> TraceReader {
+            let mut buf = BINARY_MAGIC.to_vec();
+            for r in records.iter() {
+                r.write_binary(&mut buf).unwrap();
+            }
+            TraceReader::new(Box::new(Cursor::new(buf))).unwrap()
+        }
+
+        #[test]
+        fn identical() {
+            let records = vec![record(0x0, 0, 0x1F), record(0x4, 0, 0x1F)];
+            let mut l = text_reader(&records);
+            let mut r = binary_reader(&records);
+
+            assert!(diff(&mut l, &mut r, 2).unwrap().is_none());
+        }
+
+        #[test]
+        fn register_and_flag() {
+            let left = vec![record(0x0, 0, 0x1F), record(0x4, 1, 0x1F), record(0x8, 1, 0x1F)];
+            let right = vec![record(0x0, 0, 0x1F), record(0x4, 2, 0x4000_001F), record(0x8, 2, 0x1F)];
+            let mut l = text_reader(&left);
+            let mut r = text_reader(&right);
+
+            let d = diff(&mut l, &mut r, 1).unwrap().unwrap();
+            assert_eq!(1, d.index);
+            assert_eq!(2, d.left_line);
+            assert_eq!(vec![Difference::Reg(0, 1, 2), Difference::Flag('Z', false, true)], d.differences);
+            assert_eq!(1, d.before.len());
+            assert_eq!(1, d.after_left.len());
+            assert_eq!(1, d.after_right.len());
+        }
+
+        #[test]
+        fn skips_non_trace_lines() {
+            let records = vec![record(0x0, 0, 0x1F)];
+            let mut buf = b"loaded console\n".to_vec();
+            records[0].write_text(&mut buf).unwrap();
+            let mut l = TraceReader::new(Box::new(Cursor::new(buf))).unwrap();
+            let mut r = text_reader(&records);
+
+            assert!(diff(&mut l, &mut r, 0).unwrap().is_none());
+        }
+
+        #[test]
+        fn trace_ends_early() {
+            let mut l = text_reader(&[record(0x0, 0, 0x1F), record(0x4, 0, 0x1F)]);
+            let mut r = text_reader(&[record(0x0, 0, 0x1F)]);
+
+            let d = diff(&mut l, &mut r, 0).unwrap().unwrap();
+            assert_eq!(1, d.index);
+            assert!(d.left.is_some());
+            assert!(d.right.is_none());
+        }
+
+        #[test]
+        fn binary_trace_ends_early() {
+            let mut l = binary_reader(&[record(0x0, 0, 0x1F), record(0x4, 0, 0x1F), record(0x8, 0, 0x1F)]);
+            let mut r = binary_reader(&[record(0x0, 0, 0x1F)]);
+
+            let d = diff(&mut l, &mut r, 0).unwrap().unwrap();
+            assert_eq!(1, d.index);
+            assert_eq!(2, d.left_line);
+            assert_eq!(1, d.right_line);
+            assert!(d.right.is_none());
+        }
+    }
+}